@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use nekoai_infra::usage::UsagePurpose;
+use nekoai_memory::{
+    consolidation::{Consolidation, FactConsolidator},
+    store::MemoryEntry,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio_retry::RetryIf;
+
+use crate::{
+    provider::{LanguageModelProvider, ModelRequest},
+    runtime::{escape_xml, model_retry_strategy},
+    structured::{InvalidStructuredOutput, prompt_structured},
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ConsolidationDecision {
+    action: ConsolidationAction,
+    /// IDs of the stored facts that are merged into (exactly one) or superseded.
+    #[serde(default)]
+    ids: Vec<String>,
+    /// For `merge`: the single fact combining the stored one and the new one.
+    #[serde(default)]
+    merged_fact: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum ConsolidationAction {
+    /// The new fact adds information none of the stored facts has.
+    Keep,
+    /// The new fact repeats or refines one stored fact.
+    Merge,
+    /// The new fact contradicts or replaces stored facts.
+    Supersede,
+}
+
+/// Lets the summarizer model decide how a new long-term fact relates to the
+/// stored facts close to it.
+pub struct SummarizerConsolidator {
+    model: Arc<dyn LanguageModelProvider>,
+}
+
+impl SummarizerConsolidator {
+    pub fn new(model: Arc<dyn LanguageModelProvider>) -> Self {
+        Self { model }
+    }
+}
+
+#[async_trait]
+impl FactConsolidator for SummarizerConsolidator {
+    async fn consolidate(&self, fact: &str, nearby: &[MemoryEntry]) -> Result<Consolidation> {
+        let stored_facts: String = nearby
+            .iter()
+            .map(|entry| {
+                format!(
+                    "    <fact id=\"{}\">{}</fact>\n",
+                    escape_xml(&entry.id),
+                    escape_xml(&entry.content)
+                )
+            })
+            .collect();
+        let prompt = format!(
+            "<fact_consolidation_task>\n  <instruction>\n    A new fact was extracted from a conversation. Compare it with the similar facts already stored and choose one action.\n    - keep: the new fact adds information that none of the stored facts has.\n    - merge: the new fact repeats or refines one stored fact. Put that fact's ID in ids and write the single combined fact in merged_fact, in the language of the facts.\n    - supersede: the new fact contradicts or replaces stored facts, for example a changed preference. Put their IDs in ids.\n  </instruction>\n  <new_fact>{}</new_fact>\n  <stored_facts>\n{}  </stored_facts>\n</fact_consolidation_task>",
+            escape_xml(fact),
+            stored_facts
+        );
+
+        let request = ModelRequest::new(prompt).purpose(UsagePurpose::Extraction);
+        let decision: ConsolidationDecision = RetryIf::spawn(
+            model_retry_strategy(),
+            || prompt_structured(request.clone(), |request| self.model.prompt(request)),
+            |e: &anyhow::Error| !e.is::<InvalidStructuredOutput>(),
+        )
+        .await?;
+
+        Ok(match decision.action {
+            ConsolidationAction::Keep => Consolidation::Keep,
+            ConsolidationAction::Merge => {
+                let Some(id) = decision.ids.into_iter().next() else {
+                    return Ok(Consolidation::Keep);
+                };
+                // Without a combined fact, the stored one already says it all.
+                let content = match decision.merged_fact {
+                    Some(content) => content,
+                    None => nearby
+                        .iter()
+                        .find(|entry| entry.id == id)
+                        .map(|entry| entry.content.clone())
+                        .unwrap_or_default(),
+                };
+                Consolidation::Merge { id, content }
+            }
+            ConsolidationAction::Supersede => Consolidation::Supersede { ids: decision.ids },
+        })
+    }
+}
@@ -1,39 +1,145 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, sync::Arc};
 
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use nekoai_config::loader::ContextConfig;
+use nekoai_domain::agent::runtime::{CallerContext, CallerProfile};
+use nekoai_infra::usage::UsagePurpose;
 use nekoai_memory::store::RecalledMemory;
-use tracing::debug;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
 
-use crate::session::{ConversationTurn, Session};
+use crate::{
+    instructions::{Instruction, InstructionStore},
+    provider::{LanguageModelProvider, ModelRequest},
+    session::{ConversationTurn, Session},
+    structured::prompt_structured,
+    tokenizer::Tokenizer,
+};
+
+/// Per-message overhead of chat formatting (role markers, separators).
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+const TRUNCATION_MARKER: &str = "\n…[truncated]";
 
 pub struct Context {
     pub system_prompt: String,
     pub turns: VecDeque<ConversationTurn>,
     pub user_message: String,
+    /// Set when older turns were folded into the session summary for this prompt.
+    pub compaction: Option<Compaction>,
+    pub token_count: usize,
+}
+
+/// Answer format of the summarization prompts.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct Summary {
+    /// The summary in natural prose.
+    pub summary: String,
+}
+
+/// The caller as shown to the model: the `<caller_context>` values and the
+/// instruction placeholders.
+struct CallerDetails<'a> {
+    user_id: String,
+    guild_id: String,
+    channel_id: String,
+    current_time: String,
+    profile: &'a CallerProfile,
+}
+
+impl<'a> CallerDetails<'a> {
+    fn new(caller: &'a CallerContext, session: &Session, now: DateTime<Utc>) -> Self {
+        let unknown = || "unknown".to_string();
+        Self {
+            user_id: caller
+                .user_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(unknown),
+            guild_id: session
+                .key
+                .guild_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(unknown),
+            channel_id: session.key.channel_id.get().to_string(),
+            current_time: now.format("%Y-%m-%d %H:%M UTC").to_string(),
+            profile: &caller.profile,
+        }
+    }
+
+    fn fill_placeholders(&self, text: &str) -> String {
+        fn or_unknown(value: &Option<String>) -> &str {
+            value.as_deref().unwrap_or("unknown")
+        }
+
+        let roles = if self.profile.roles.is_empty() {
+            "none".to_string()
+        } else {
+            self.profile.roles.join(", ")
+        };
+
+        text.replace("{guild_name}", or_unknown(&self.profile.guild_name))
+            .replace("{channel_name}", or_unknown(&self.profile.channel_name))
+            .replace("{category}", or_unknown(&self.profile.category))
+            .replace("{user_name}", or_unknown(&self.profile.user_name))
+            .replace("{user_id}", &self.user_id)
+            .replace("{guild_id}", &self.guild_id)
+            .replace("{channel_id}", &self.channel_id)
+            .replace("{roles}", &roles)
+            .replace("{current_time}", &self.current_time)
+            .replace("{locale}", or_unknown(&self.profile.locale))
+    }
+}
+
+/// Older turns that were summarized and should be removed from the session.
+pub struct Compaction {
+    pub summary: String,
+    pub compacted_turns: usize,
 }
 
 pub struct ContextManager {
-    base_system_prompt: String,
+    instructions: Arc<InstructionStore>,
     max_tokens: usize,
-    #[allow(dead_code)]
     compaction_threshold: f32,
+    memory_budget_ratio: f32,
+    tokenizer: Arc<dyn Tokenizer>,
+    summarizer: Arc<dyn LanguageModelProvider>,
 }
 
 impl ContextManager {
-    pub fn new(base_system_prompt: String, max_tokens: usize, compaction_threshold: f32) -> Self {
+    pub fn new(
+        instructions: Arc<InstructionStore>,
+        config: &ContextConfig,
+        tokenizer: Arc<dyn Tokenizer>,
+        summarizer: Arc<dyn LanguageModelProvider>,
+    ) -> Self {
         Self {
-            base_system_prompt,
-            max_tokens,
-            compaction_threshold,
+            instructions,
+            max_tokens: config.max_tokens,
+            compaction_threshold: config.compaction_threshold.clamp(0.1, 1.0),
+            memory_budget_ratio: config.memory_budget_ratio.clamp(0.0, 1.0),
+            tokenizer,
+            summarizer,
         }
     }
 
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+
+    /// Builds the prompt within `max_tokens`.
+    ///
+    /// The budget is spent in order on the system prompt and the current input,
+    /// recalled memories (up to `memory_budget_ratio`), and the remaining
+    /// history. When history exceeds `compaction_threshold` of its share, the
+    /// oldest turns are summarized into the session summary.
     pub async fn build(
         &self,
         session: &Session,
         input: &str,
         recalled_memory: &RecalledMemory,
-        caller_user_id: Option<String>,
-        caller_guild_id: Option<u64>,
+        caller: &CallerContext,
+        persona: Option<&str>,
     ) -> Context {
         debug!(
             input_len = input.len(),
@@ -41,67 +147,261 @@ impl ContextManager {
             max_tokens = self.max_tokens,
             "building prompt context"
         );
-        let mut turns = session.turns.clone();
 
-        let max_turns = (self.max_tokens / 512).max(1);
-        if turns.len() > max_turns {
-            let drain_count = turns.len() - max_turns;
-            for _ in 0 .. drain_count {
-                turns.pop_front();
+        let caller = CallerDetails::new(caller, session, Utc::now());
+        let instruction =
+            self.instructions
+                .resolve(&session.key, caller.profile.parent_channel_id, persona);
+        let fixed_tokens = self.count_tokens(&self.build_system_prompt_with_memory(
+            &instruction,
+            &RecalledMemory::default(),
+            None,
+            &caller,
+        )) + self.count_tokens(input)
+            + MESSAGE_OVERHEAD_TOKENS;
+
+        let memory_budget = (self.max_tokens as f32 * self.memory_budget_ratio) as usize;
+        let (recalled, memory_tokens) = self.fit_memories(recalled_memory, memory_budget);
+
+        let mut summary = session.summary.clone();
+        let summary_tokens = summary.as_deref().map_or(0, |s| self.count_tokens(s));
+
+        let history_budget = self
+            .max_tokens
+            .saturating_sub(fixed_tokens + memory_tokens + summary_tokens);
+
+        // A single huge turn must not push the prompt past the window on its own.
+        let turn_cap = history_budget / 2;
+        let mut turns: VecDeque<ConversationTurn> = session
+            .turns
+            .iter()
+            .map(|turn| self.clamp_turn(turn, turn_cap))
+            .collect();
+        let turn_tokens: Vec<usize> = turns.iter().map(|turn| self.turn_tokens(turn)).collect();
+        let history_tokens: usize = turn_tokens.iter().sum();
+
+        let threshold = (history_budget as f32 * self.compaction_threshold) as usize;
+        let mut compaction = None;
+
+        if history_tokens > threshold {
+            // Keep well below the threshold so the next few turns don't trigger
+            // another summarization right away.
+            let keep_budget = threshold / 2;
+            let mut kept_tokens = 0;
+            let mut keep = 0;
+            for tokens in turn_tokens.iter().rev() {
+                if kept_tokens + tokens > keep_budget {
+                    break;
+                }
+                kept_tokens += tokens;
+                keep += 1;
+            }
+
+            let compacted_turns = turns.len() - keep;
+            let older: Vec<ConversationTurn> = turns.drain(.. compacted_turns).collect();
+
+            match self.summarize_turns(summary.as_deref(), &older).await {
+                Ok(new_summary) => {
+                    info!(
+                        session = %session.key.channel_id,
+                        compacted_turns = compacted_turns,
+                        history_tokens = history_tokens,
+                        threshold = threshold,
+                        "summarized older conversation turns"
+                    );
+                    summary = Some(new_summary.clone());
+                    compaction = Some(Compaction {
+                        summary: new_summary,
+                        compacted_turns,
+                    });
+                }
+                Err(e) => {
+                    warn!(
+                        session = %session.key.channel_id,
+                        error = %e,
+                        compacted_turns = compacted_turns,
+                        "failed to summarize older turns, leaving them out of this prompt"
+                    );
+                }
             }
-            debug!(
-                drained_turns = drain_count,
-                "compacted conversation turns for context"
-            );
         }
 
-        let channel_id = session.key.channel_id.get().to_string();
         let system_prompt = self.build_system_prompt_with_memory(
-            recalled_memory,
-            caller_user_id,
-            caller_guild_id,
-            &channel_id,
+            &instruction,
+            &recalled,
+            summary.as_deref(),
+            &caller,
+        );
+
+        let token_count = self.count_tokens(&system_prompt)
+            + turns
+                .iter()
+                .map(|turn| self.turn_tokens(turn))
+                .sum::<usize>()
+            + self.count_tokens(input)
+            + MESSAGE_OVERHEAD_TOKENS;
+
+        debug!(
+            token_count = token_count,
+            memory_tokens = memory_tokens,
+            history_budget = history_budget,
+            context_turns = turns.len(),
+            "prompt context budgeted"
         );
 
         Context {
             system_prompt,
             turns,
             user_message: input.to_string(),
+            compaction,
+            token_count,
         }
     }
 
+    /// Long-term memories are preferred over mid-term summaries; anything that
+    /// doesn't fit the budget is skipped.
+    fn fit_memories(&self, recalled: &RecalledMemory, budget: usize) -> (RecalledMemory, usize) {
+        let mut used = 0;
+        let mut fit = |content: &str| {
+            let tokens = self.count_tokens(content) + MESSAGE_OVERHEAD_TOKENS;
+            if used + tokens > budget {
+                return false;
+            }
+            used += tokens;
+            true
+        };
+
+        let long_term = recalled
+            .long_term
+            .iter()
+            .filter(|mem| fit(&mem.content))
+            .cloned()
+            .collect();
+        let mid_term = recalled
+            .mid_term
+            .iter()
+            .filter(|mem| fit(&mem.content))
+            .cloned()
+            .collect();
+
+        (
+            RecalledMemory {
+                mid_term,
+                long_term,
+            },
+            used,
+        )
+    }
+
+    fn turn_tokens(&self, turn: &ConversationTurn) -> usize {
+        self.count_tokens(&turn.user)
+            + self.count_tokens(&turn.assistant)
+            + MESSAGE_OVERHEAD_TOKENS * 2
+    }
+
+    fn clamp_turn(&self, turn: &ConversationTurn, max_tokens: usize) -> ConversationTurn {
+        if self.turn_tokens(turn) <= max_tokens {
+            return turn.clone();
+        }
+
+        let half = max_tokens / 2;
+        ConversationTurn {
+            user: self.truncate_to_tokens(&turn.user, half),
+            assistant: self.truncate_to_tokens(&turn.assistant, half),
+            user_id: turn.user_id.clone(),
+        }
+    }
+
+    fn truncate_to_tokens(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.count_tokens(text);
+        if tokens <= max_tokens {
+            return text.to_string();
+        }
+
+        // Tokenizers are opaque here, so cut proportionally and shrink until it fits.
+        let chars = text.chars().count();
+        let mut keep = chars * max_tokens / tokens.max(1);
+        loop {
+            let truncated: String = text.chars().take(keep).collect();
+            if keep == 0 || self.count_tokens(&truncated) <= max_tokens {
+                return truncated + TRUNCATION_MARKER;
+            }
+            keep = keep * 9 / 10;
+        }
+    }
+
+    async fn summarize_turns(
+        &self,
+        previous_summary: Option<&str>,
+        turns: &[ConversationTurn],
+    ) -> Result<String> {
+        let mut conversation = String::new();
+        for turn in turns {
+            conversation.push_str("User: ");
+            conversation.push_str(turn.user.trim());
+            conversation.push_str("\nAssistant: ");
+            conversation.push_str(turn.assistant.trim());
+            conversation.push('\n');
+        }
+
+        let prompt = format!(
+            "<summarization_task>\n  <instruction>\n    The following are the earliest turns of an ongoing conversation, optionally preceded by a summary of even earlier turns.\n    - Merge them into a single summary that keeps topics, decisions, code identifiers, open questions and anything later turns may refer to.\n    - Use the original language of the conversation.\n    - Keep it under 300 words, in natural prose.\n  </instruction>\n  <previous_summary>{}</previous_summary>\n  <conversation_log>{}</conversation_log>\n</summarization_task>",
+            escape_xml(previous_summary.unwrap_or_default()),
+            escape_xml(&conversation)
+        );
+
+        let request = ModelRequest::new(prompt).purpose(UsagePurpose::Summarization);
+        let summary: Summary =
+            prompt_structured(request, |request| self.summarizer.prompt(request)).await?;
+        Ok(summary.summary.trim().to_string())
+    }
+
     fn build_system_prompt_with_memory(
         &self,
+        instruction: &Instruction,
         recalled: &RecalledMemory,
-        caller_user_id: Option<String>,
-        caller_guild_id: Option<u64>,
-        channel_id: &str,
+        summary: Option<&str>,
+        caller: &CallerDetails<'_>,
     ) -> String {
-        let user_id = caller_user_id.unwrap_or_else(|| "unknown".to_string());
-        let guild_id = caller_guild_id
-            .map(|id| id.to_string())
-            .unwrap_or_else(|| "unknown".to_string());
-        let channel_id = channel_id.to_string();
-
-        let base_system_prompt = self
-            .base_system_prompt
-            .replace("{guild_name}", "unknown")
-            .replace("{channel_name}", "unknown")
-            .replace("{category}", "unknown")
-            .replace("{user_name}", "unknown")
-            .replace("{user_id}", &user_id)
-            .replace("{guild_id}", &guild_id)
-            .replace("{channel_id}", &channel_id)
-            .replace("{roles}", "unknown");
+        let fill_placeholders = |text: &str| caller.fill_placeholders(text);
 
         let mut prompt = String::from("<nekoai_prompt>\n");
         prompt.push_str("  <system_instruction>");
-        prompt.push_str(&escape_xml(&base_system_prompt));
+        prompt.push_str(&escape_xml(&fill_placeholders(&instruction.base)));
         prompt.push_str("</system_instruction>\n");
+        if instruction.guild.is_some() || instruction.channel.is_some() {
+            prompt.push_str("  <instruction_precedence>The guild and channel instructions refine the system instruction. Where they conflict, follow the channel instruction, then the guild instruction.</instruction_precedence>\n");
+        }
+        if let Some(guild_instruction) = &instruction.guild {
+            prompt.push_str("  <guild_instruction>");
+            prompt.push_str(&escape_xml(&fill_placeholders(guild_instruction)));
+            prompt.push_str("</guild_instruction>\n");
+        }
+        if let Some(channel_instruction) = &instruction.channel {
+            prompt.push_str("  <channel_instruction>");
+            prompt.push_str(&escape_xml(&fill_placeholders(channel_instruction)));
+            prompt.push_str("</channel_instruction>\n");
+        }
         prompt.push_str("  <caller_context>\n");
-        prompt.push_str(&format!("    <guild_id>{}</guild_id>\n", guild_id));
-        prompt.push_str(&format!("    <channel_id>{}</channel_id>\n", channel_id));
-        prompt.push_str(&format!("    <user_id>{}</user_id>\n", user_id));
+        prompt.push_str(&format!("    <guild_id>{}</guild_id>\n", caller.guild_id));
+        prompt.push_str(&format!(
+            "    <channel_id>{}</channel_id>\n",
+            caller.channel_id
+        ));
+        prompt.push_str(&format!("    <user_id>{}</user_id>\n", caller.user_id));
+        for (tag, value) in [
+            ("guild_name", &caller.profile.guild_name),
+            ("channel_name", &caller.profile.channel_name),
+            ("user_name", &caller.profile.user_name),
+        ] {
+            if let Some(value) = value {
+                prompt.push_str(&format!("    <{tag}>{}</{tag}>\n", escape_xml(value)));
+            }
+        }
+        prompt.push_str(&format!(
+            "    <current_time>{}</current_time>\n",
+            caller.current_time
+        ));
         prompt.push_str("  </caller_context>\n");
 
         if !recalled.long_term.is_empty() {
@@ -124,6 +424,12 @@ impl ContextManager {
             prompt.push_str("  </past_conversations>\n");
         }
 
+        if let Some(summary) = summary {
+            prompt.push_str("  <earlier_in_this_conversation>");
+            prompt.push_str(&escape_xml(summary));
+            prompt.push_str("</earlier_in_this_conversation>\n");
+        }
+
         prompt.push_str("</nekoai_prompt>");
         prompt
     }
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use nekoai_config::loader::DelegationConfig;
+use nekoai_infra::usage::UsagePurpose;
+use rig::{
+    completion::ToolDefinition,
+    tool::{Tool, server::ToolServerHandle},
+};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    fallback::ModelChain,
+    limits::{LoopBudget, LoopLimits, loop_limit},
+    provider::ModelRequest,
+};
+
+const SUB_AGENT_PREAMBLE: &str = "You are a research sub-agent working for another assistant. \
+Complete the task you are given with the tools available to you, then reply with a condensed \
+report: the findings that answer the task, with the source URL of each, and anything you could \
+not find out. Do not include raw page contents, and do not address the end user. Keep the report \
+under {max_chars} characters.";
+
+/// Hands a task to a sub-agent that starts from an empty context, may only call
+/// the tools in `agent_loop.delegation.tools` and has its own loop limits. Only
+/// the sub-agent's final answer reaches the calling conversation.
+pub struct DelegateTask {
+    models: Arc<ModelChain>,
+    tool_server_handle: ToolServerHandle,
+    config: DelegationConfig,
+}
+
+impl DelegateTask {
+    pub fn new(
+        models: Arc<ModelChain>,
+        tool_server_handle: ToolServerHandle,
+        config: DelegationConfig,
+    ) -> Self {
+        Self {
+            models,
+            tool_server_handle,
+            config,
+        }
+    }
+}
+
+impl Tool for DelegateTask {
+    const NAME: &'static str = "delegate_task";
+
+    type Error = serde_json::Error;
+    type Args = Value;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: format!(
+                "Delegate a self-contained research task to a sub-agent that can use these tools: {}. \
+                 It works in a separate context and returns only a condensed report with sources, \
+                 so use it for questions that need several searches or long pages instead of \
+                 fetching them yourself.",
+                if self.config.tools.is_empty() {
+                    "none".to_string()
+                } else {
+                    self.config.tools.join(", ")
+                }
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "What the sub-agent should find out, stated completely; it does not see this conversation."
+                    },
+                    "context": {
+                        "type": "string",
+                        "description": "Background from the conversation the sub-agent needs, if any."
+                    }
+                },
+                "required": ["task"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let task = args
+            .get("task")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .trim();
+        if task.is_empty() {
+            return Ok(json!({
+                "ok": false,
+                "error": "task is required"
+            }));
+        }
+
+        let prompt = match args
+            .get("context")
+            .and_then(Value::as_str)
+            .filter(|context| !context.trim().is_empty())
+        {
+            Some(context) => format!("<context>\n{context}\n</context>\n\n<task>\n{task}\n</task>"),
+            None => format!("<task>\n{task}\n</task>"),
+        };
+
+        let limits = LoopLimits::for_delegation(&self.config);
+        let request = ModelRequest::new(prompt)
+            .preamble(
+                SUB_AGENT_PREAMBLE
+                    .replace("{max_chars}", &self.config.max_result_chars.to_string()),
+            )
+            .tool_server_handle(self.tool_server_handle.clone())
+            .loop_budget(LoopBudget::new(limits))
+            .purpose(UsagePurpose::Delegation);
+
+        info!(task_len = task.len(), "delegating task to sub-agent");
+        let result = match limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.models.prompt(request))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow::anyhow!(
+                        "sub-agent timed out after {} seconds",
+                        timeout.as_secs()
+                    ))
+                }),
+            None => self.models.prompt(request).await,
+        };
+
+        match result {
+            Ok(report) => {
+                let end = report
+                    .char_indices()
+                    .nth(self.config.max_result_chars)
+                    .map_or(report.len(), |(index, _)| index);
+                info!(
+                    report_len = report.len(),
+                    truncated = end < report.len(),
+                    "sub-agent finished"
+                );
+                Ok(json!({
+                    "ok": true,
+                    "report": &report[.. end],
+                    "truncated": end < report.len()
+                }))
+            }
+            Err(e) => {
+                warn!(error = %e, "sub-agent failed");
+                let error = match loop_limit(&e) {
+                    Some(limit) => format!("the sub-agent stopped before finishing: {limit}"),
+                    None => format!("the sub-agent failed: {e}"),
+                };
+                Ok(json!({
+                    "ok": false,
+                    "error": error
+                }))
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use futures::StreamExt;
+use nekoai_config::loader::{CircuitBreakerConfig, ConversationModel};
+use nekoai_domain::agent::runtime::current_caller_context;
+use nekoai_infra::event_bus::{AgentEvent, EventBus};
+use tracing::{info, warn};
+
+use crate::{
+    limits::loop_limit,
+    provider::{LanguageModelProvider, ModelRequest, ModelStream, build_provider},
+    usage::UsageMeter,
+};
+
+/// Ordered list of conversation models. Each call goes to the first model whose
+/// circuit breaker is closed and fails over to the next one on error, unless
+/// the failed model already called tools: the next model would start the loop
+/// over and run them again.
+pub struct ModelChain {
+    models: Vec<ChainedModel>,
+    event_bus: EventBus,
+}
+
+struct ChainedModel {
+    provider: Arc<dyn LanguageModelProvider>,
+    breaker: CircuitBreaker,
+}
+
+impl ModelChain {
+    pub fn from_config(
+        primary: &ConversationModel,
+        fallbacks: &[ConversationModel],
+        breaker: &CircuitBreakerConfig,
+        event_bus: EventBus,
+        usage_meter: &UsageMeter,
+    ) -> Result<Self> {
+        let models = std::iter::once(primary)
+            .chain(fallbacks)
+            .map(|model| {
+                let provider = build_provider(
+                    model.kind,
+                    &model.provider_base_url,
+                    model.api_key.as_ref(),
+                    &model.model_name,
+                    model.parameters.clone(),
+                    usage_meter.clone(),
+                )?;
+                Ok(ChainedModel {
+                    provider,
+                    breaker: CircuitBreaker::new(breaker),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { models, event_bus })
+    }
+
+    pub fn model_names(&self) -> Vec<String> {
+        self.models
+            .iter()
+            .map(|model| model_label(model.provider.as_ref()))
+            .collect()
+    }
+
+    pub async fn prompt(&self, request: ModelRequest) -> Result<String> {
+        let mut last_error = None;
+
+        for model in self.candidates() {
+            match model.provider.prompt(request.clone()).await {
+                Ok(response) => {
+                    self.on_success(model);
+                    return Ok(response);
+                }
+                // The model works; the request just ran out of budget.
+                Err(e) if loop_limit(&e).is_some() => return Err(e),
+                Err(e) => {
+                    self.on_failure(model, &e);
+                    if request.loop_budget.tool_calls() > 0 {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("no conversation model is configured")))
+    }
+
+    /// Fails over only until the first item arrives; once a model has started
+    /// answering or calling tools, its errors are passed through to the caller.
+    pub async fn stream(&self, request: ModelRequest) -> Result<ModelStream> {
+        let mut last_error = None;
+
+        for model in self.candidates() {
+            let first = match model.provider.stream(request.clone()).await {
+                Ok(mut stream) => match stream.next().await {
+                    Some(Ok(item)) => Ok((item, stream)),
+                    Some(Err(e)) => Err(e),
+                    None => Err(anyhow!("model stream ended without any output")),
+                },
+                Err(e) => Err(e),
+            };
+
+            match first {
+                Ok((item, stream)) => {
+                    self.on_success(model);
+                    return Ok(Box::pin(
+                        futures::stream::once(async { Ok(item) }).chain(stream),
+                    ));
+                }
+                Err(e) if loop_limit(&e).is_some() => return Err(e),
+                Err(e) => {
+                    self.on_failure(model, &e);
+                    if request.loop_budget.tool_calls() > 0 {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("no conversation model is configured")))
+    }
+
+    fn candidates(&self) -> Vec<&ChainedModel> {
+        let available: Vec<_> = self
+            .models
+            .iter()
+            .filter(|model| model.breaker.is_available())
+            .collect();
+
+        if available.is_empty() {
+            // Going silent is worse than probing a model that is probably down.
+            warn!("every conversation model circuit breaker is open, trying all models");
+            return self.models.iter().collect();
+        }
+
+        available
+    }
+
+    fn on_success(&self, model: &ChainedModel) {
+        model.breaker.record_success();
+
+        if !std::ptr::eq(model, &self.models[0]) {
+            info!(
+                model = %model_label(model.provider.as_ref()),
+                "answered with fallback conversation model"
+            );
+        }
+    }
+
+    fn on_failure(&self, model: &ChainedModel, error: &anyhow::Error) {
+        let label = model_label(model.provider.as_ref());
+
+        if model.breaker.record_failure() {
+            warn!(model = %label, error = %error, "conversation model circuit breaker opened");
+        } else {
+            warn!(model = %label, error = %error, "conversation model call failed");
+        }
+
+        if let Some(session_key) = current_caller_context().session_key {
+            self.event_bus.publish(AgentEvent::ErrorOccurred {
+                session_key,
+                error: error.to_string(),
+                model: Some(label),
+            });
+        }
+    }
+}
+
+fn model_label(provider: &dyn LanguageModelProvider) -> String {
+    format!("{}/{}", provider.provider_name(), provider.model_name())
+}
+
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_secs(config.cooldown_seconds),
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Closed, or open with the cooldown elapsed so that the model gets probed again.
+    fn is_available(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .open_until
+            .is_none_or(|open_until| Instant::now() >= open_until)
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = BreakerState::default();
+    }
+
+    /// Returns `true` when this failure opened the breaker.
+    fn record_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+
+        if state.consecutive_failures >= self.failure_threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use nekoai_config::loader::AttachmentConfig;
+use rig::completion::message::{ImageMediaType, UserContent};
+
+/// Text files larger than this are not worth downloading only to be truncated.
+const MAX_TEXT_DOWNLOAD_BYTES: u64 = 1024 * 1024;
+
+const TEXT_MIME_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "application/toml",
+    "application/yaml",
+    "application/x-yaml",
+    "application/javascript",
+    "application/x-sh",
+    "application/sql",
+];
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "log", "csv", "tsv", "json", "jsonl", "toml", "yaml", "yml", "xml",
+    "ini", "cfg", "conf", "env", "diff", "patch", "rs", "py", "js", "mjs", "ts", "tsx", "jsx",
+    "go", "java", "kt", "c", "h", "cpp", "hpp", "cc", "cs", "rb", "php", "swift", "lua", "sh",
+    "bash", "zsh", "ps1", "sql", "html", "css", "scss", "vue", "svelte",
+];
+
+/// What a user sent: the message text and any files attached to it.
+#[derive(Clone, Default)]
+pub struct UserInput {
+    pub text: String,
+    pub attachments: Vec<Attachment>,
+}
+
+impl UserInput {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+}
+
+impl From<String> for UserInput {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<&str> for UserInput {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+/// A file attached to a message. `data` is `None` when the frontend did not
+/// download it, e.g. because [`AttachmentPolicy::accepts`] turned it down.
+#[derive(Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub data: Option<Vec<u8>>,
+}
+
+enum AttachmentKind {
+    Image(ImageMediaType),
+    Text,
+    Unsupported,
+}
+
+/// The input as it is sent to the model.
+pub struct ResolvedInput {
+    /// The message followed by the inlined text files and a note for every
+    /// attachment. This is what session history and memory keep.
+    pub text: String,
+    /// What memory is recalled with: the message itself, unless it was empty.
+    pub recall_query: String,
+    pub images: Vec<UserContent>,
+}
+
+/// Decides which attachments reach the model, within `[attachments]` limits.
+pub struct AttachmentPolicy {
+    config: AttachmentConfig,
+}
+
+impl AttachmentPolicy {
+    pub fn new(config: AttachmentConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether a frontend should download an attachment and pass its data on.
+    pub fn accepts(&self, filename: &str, content_type: Option<&str>, size: u64) -> bool {
+        match attachment_kind(filename, content_type) {
+            AttachmentKind::Image(_) => {
+                self.config.max_images > 0 && size <= self.config.max_image_bytes
+            }
+            AttachmentKind::Text => {
+                self.config.max_total_text_bytes > 0 && size <= MAX_TEXT_DOWNLOAD_BYTES
+            }
+            AttachmentKind::Unsupported => false,
+        }
+    }
+
+    /// Turns images into image parts and inlines text files, leaving a note in
+    /// the text for every attachment so later turns know what was shared.
+    pub fn resolve(&self, input: UserInput) -> ResolvedInput {
+        let mut images = Vec::new();
+        let mut files = Vec::new();
+        let mut notes = Vec::new();
+        let mut inlined_bytes = 0;
+
+        for attachment in input.attachments {
+            let name = attachment.filename.replace(['"', '<', '>', '`'], "_");
+            let kind = attachment_kind(&attachment.filename, attachment.content_type.as_deref());
+
+            let Some(data) = attachment.data else {
+                notes.push(format!(
+                    "[attachment `{name}` ({} bytes) was not read: unsupported type, too large or not downloaded]",
+                    attachment.size
+                ));
+                continue;
+            };
+
+            match kind {
+                AttachmentKind::Image(media_type) => {
+                    if images.len() >= self.config.max_images
+                        || data.len() as u64 > self.config.max_image_bytes
+                    {
+                        notes.push(format!(
+                            "[image `{name}` was not shown: at most {} images of up to {} bytes are accepted per message]",
+                            self.config.max_images, self.config.max_image_bytes
+                        ));
+                        continue;
+                    }
+                    images.push(UserContent::image_base64(
+                        STANDARD.encode(&data),
+                        Some(media_type),
+                        None,
+                    ));
+                    notes.push(format!("[image attached: `{name}`]"));
+                }
+                AttachmentKind::Text => {
+                    let Ok(content) = String::from_utf8(data) else {
+                        notes.push(format!(
+                            "[attachment `{name}` was not read: it is not UTF-8 text]"
+                        ));
+                        continue;
+                    };
+
+                    let budget = self
+                        .config
+                        .max_total_text_bytes
+                        .saturating_sub(inlined_bytes)
+                        .min(self.config.max_text_bytes);
+                    if budget == 0 {
+                        notes.push(format!(
+                            "[attachment `{name}` was not read: the text limit of {} bytes per message was reached]",
+                            self.config.max_total_text_bytes
+                        ));
+                        continue;
+                    }
+
+                    let end = content.floor_char_boundary(budget);
+                    inlined_bytes += end;
+
+                    let mut file = format!(
+                        "<attachment filename=\"{name}\">\n{}\n</attachment>",
+                        &content[.. end]
+                    );
+                    if end < content.len() {
+                        file.push_str(&format!(
+                            "\n[attachment `{name}` was truncated to the first {end} of {} bytes]",
+                            content.len()
+                        ));
+                    }
+                    files.push(file);
+                }
+                AttachmentKind::Unsupported => {
+                    notes.push(format!(
+                        "[attachment `{name}` was not read: unsupported file type]"
+                    ));
+                }
+            }
+        }
+
+        let recall_query = (!input.text.trim().is_empty()).then(|| input.text.clone());
+        let text = [input.text]
+            .into_iter()
+            .chain(files)
+            .chain((!notes.is_empty()).then(|| notes.join("\n")))
+            .filter(|part| !part.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        ResolvedInput {
+            recall_query: recall_query.unwrap_or_else(|| text.clone()),
+            text,
+            images,
+        }
+    }
+}
+
+fn attachment_kind(filename: &str, content_type: Option<&str>) -> AttachmentKind {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase());
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    // Uploads often come as `application/octet-stream`, so the extension counts too.
+    let image = match mime.as_deref() {
+        Some("image/png") => Some(ImageMediaType::PNG),
+        Some("image/jpeg") => Some(ImageMediaType::JPEG),
+        Some("image/gif") => Some(ImageMediaType::GIF),
+        Some("image/webp") => Some(ImageMediaType::WEBP),
+        _ => match extension.as_deref() {
+            Some("png") => Some(ImageMediaType::PNG),
+            Some("jpg" | "jpeg") => Some(ImageMediaType::JPEG),
+            Some("gif") => Some(ImageMediaType::GIF),
+            Some("webp") => Some(ImageMediaType::WEBP),
+            _ => None,
+        },
+    };
+    if let Some(media_type) = image {
+        return AttachmentKind::Image(media_type);
+    }
+
+    let is_text = mime
+        .as_deref()
+        .is_some_and(|mime| mime.starts_with("text/") || TEXT_MIME_TYPES.contains(&mime))
+        || extension
+            .as_deref()
+            .is_some_and(|extension| TEXT_EXTENSIONS.contains(&extension));
+
+    if is_text {
+        AttachmentKind::Text
+    } else {
+        AttachmentKind::Unsupported
+    }
+}
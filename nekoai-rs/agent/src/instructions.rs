@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use nekoai_config::loader::PersonaConfig;
+use nekoai_domain::agent::session::SessionKey;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Used when `INSTRUCTION.md` does not exist.
+pub const DEFAULT_INSTRUCTION: &str = include_str!("default_instruction.md");
+
+const INSTRUCTION_FILE: &str = "INSTRUCTION.md";
+/// Holds `<guild_id>.md` and `<channel_id>.md`.
+const OVERRIDES_DIR: &str = "instructions";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The instruction layers that apply to one session.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub base: Arc<str>,
+    pub guild: Option<Arc<str>>,
+    /// The thread's own override if it has one, otherwise its channel's.
+    pub channel: Option<Arc<str>>,
+}
+
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+struct LoadedInstructions {
+    base: Arc<str>,
+    /// Instruction files of personas, by persona name.
+    personas: HashMap<String, Arc<str>>,
+    overrides: HashMap<u64, Arc<str>>,
+    /// Modification time and size of every file, taken before they were read.
+    fingerprint: Fingerprint,
+}
+
+/// The system instruction, the instructions of personas and the
+/// per-guild/per-channel overrides, read from the config directory and
+/// reloaded when the files change.
+pub struct InstructionStore {
+    dir: PathBuf,
+    /// `instruction_file` of every persona that has one, by persona name.
+    persona_files: Vec<(String, PathBuf)>,
+    loaded: RwLock<LoadedInstructions>,
+}
+
+impl InstructionStore {
+    pub fn load(dir: impl Into<PathBuf>, personas: &[PersonaConfig]) -> Self {
+        let dir = dir.into();
+        let persona_files: Vec<(String, PathBuf)> = personas
+            .iter()
+            .filter_map(|persona| {
+                let file = persona.instruction_file.as_ref()?;
+                Some((persona.name.trim().to_string(), dir.join(file)))
+            })
+            .collect();
+        let loaded = read_instructions(&dir, &persona_files);
+        info!(
+            base_len = loaded.base.len(),
+            personas = loaded.personas.len(),
+            overrides = loaded.overrides.len(),
+            "system instruction loaded"
+        );
+
+        Self {
+            dir,
+            persona_files,
+            loaded: RwLock::new(loaded),
+        }
+    }
+
+    /// `parent_channel_id` is the channel of a thread session; its override
+    /// applies when the thread has none of its own. A persona's instruction
+    /// file, when it has one, takes the place of `INSTRUCTION.md`.
+    pub fn resolve(
+        &self,
+        session_key: &SessionKey,
+        parent_channel_id: Option<u64>,
+        persona: Option<&str>,
+    ) -> Instruction {
+        let loaded = self.loaded.read().unwrap_or_else(PoisonError::into_inner);
+        let find = |id: u64| loaded.overrides.get(&id).cloned();
+
+        Instruction {
+            base: persona
+                .and_then(|name| loaded.personas.get(name).cloned())
+                .unwrap_or_else(|| loaded.base.clone()),
+            guild: session_key.guild_id.and_then(|id| find(id.get())),
+            channel: session_key
+                .thread_id
+                .and_then(|id| find(id.get()))
+                .or_else(|| find(session_key.channel_id.get()))
+                .or_else(|| parent_channel_id.and_then(find)),
+        }
+    }
+
+    /// Re-reads every file if any of them was edited, added or removed.
+    pub fn reload_if_changed(&self) -> bool {
+        let unchanged = {
+            let loaded = self.loaded.read().unwrap_or_else(PoisonError::into_inner);
+            loaded.fingerprint == fingerprint(&self.dir, &self.persona_files)
+        };
+        if unchanged {
+            return false;
+        }
+
+        let loaded = read_instructions(&self.dir, &self.persona_files);
+        info!(
+            base_len = loaded.base.len(),
+            personas = loaded.personas.len(),
+            overrides = loaded.overrides.len(),
+            "system instruction reloaded"
+        );
+        *self.loaded.write().unwrap_or_else(PoisonError::into_inner) = loaded;
+        true
+    }
+
+    /// Checks the files for changes every few seconds until `stop` is cancelled.
+    pub async fn watch(self: Arc<Self>, stop: CancellationToken) {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = stop.cancelled() => break,
+                _ = interval.tick() => {
+                    let store = self.clone();
+                    if let Err(e) = tokio::task::spawn_blocking(move || store.reload_if_changed()).await {
+                        warn!(error = %e, "failed to reload system instruction");
+                    }
+                }
+            }
+        }
+
+        debug!("system instruction watcher stopped");
+    }
+}
+
+fn read_instructions(dir: &Path, persona_files: &[(String, PathBuf)]) -> LoadedInstructions {
+    let fingerprint = fingerprint(dir, persona_files);
+
+    let base_path = dir.join(INSTRUCTION_FILE);
+    let base = match fs::read_to_string(&base_path) {
+        Ok(base) => base,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!(path = %base_path.display(), "no system instruction file, using the built-in default");
+            DEFAULT_INSTRUCTION.to_string()
+        }
+        Err(e) => {
+            warn!(path = %base_path.display(), error = %e, "failed to read system instruction, using the built-in default");
+            DEFAULT_INSTRUCTION.to_string()
+        }
+    };
+
+    // A persona whose file cannot be read falls back to `INSTRUCTION.md`.
+    let mut personas = HashMap::new();
+    for (name, path) in persona_files {
+        match fs::read_to_string(path) {
+            Ok(text) if !text.trim().is_empty() => {
+                personas.insert(name.clone(), Arc::from(text));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(persona = %name, path = %path.display(), error = %e, "failed to read persona instruction");
+            }
+        }
+    }
+
+    let mut overrides = HashMap::new();
+    for (path, _, _) in fingerprint.iter().skip(1 + persona_files.len()) {
+        let Some(id) = override_id(path) else {
+            continue;
+        };
+        match fs::read_to_string(path) {
+            Ok(text) if !text.trim().is_empty() => {
+                overrides.insert(id, Arc::from(text));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "failed to read instruction override");
+            }
+        }
+    }
+
+    LoadedInstructions {
+        base: Arc::from(base),
+        personas,
+        overrides,
+        fingerprint,
+    }
+}
+
+/// `INSTRUCTION.md` first, then the persona files in config order, then the
+/// override files sorted by path.
+fn fingerprint(dir: &Path, persona_files: &[(String, PathBuf)]) -> Fingerprint {
+    let stat = |path: PathBuf| {
+        let metadata = fs::metadata(&path).ok();
+        let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+        let len = metadata.map_or(0, |m| m.len());
+        (path, modified, len)
+    };
+
+    let mut overrides: Vec<PathBuf> = fs::read_dir(dir.join(OVERRIDES_DIR))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| override_id(path).is_some())
+        .collect();
+    overrides.sort();
+
+    std::iter::once(dir.join(INSTRUCTION_FILE))
+        .chain(persona_files.iter().map(|(_, path)| path.clone()))
+        .chain(overrides)
+        .map(stat)
+        .collect()
+}
+
+fn override_id(path: &Path) -> Option<u64> {
+    if path.extension()? != "md" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
@@ -1,4 +1,16 @@
+pub mod consolidation;
 pub mod context;
+pub mod delegate;
+pub mod fallback;
+pub mod input;
+pub mod instructions;
+pub mod limits;
+pub mod persona;
 pub mod provider;
+pub mod rate_limit;
+pub mod rerank;
 pub mod runtime;
 pub mod session;
+pub mod structured;
+pub mod tokenizer;
+pub mod usage;
@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use nekoai_config::loader::{AgentLoopConfig, DelegationConfig};
+use rig::{
+    agent::{PromptHook, StreamingError, ToolCallHookAction},
+    completion::{CompletionModel, PromptError},
+};
+
+/// Limits of one request's model/tool loop, resolved for the caller's guild.
+#[derive(Debug, Clone, Copy)]
+pub struct LoopLimits {
+    pub max_turns: usize,
+    pub max_tool_calls: Option<u32>,
+    pub max_calls_per_tool: Option<u32>,
+    pub timeout: Option<Duration>,
+}
+
+impl LoopLimits {
+    pub fn for_guild(config: &AgentLoopConfig, guild_id: Option<u64>) -> Self {
+        let overrides = guild_id.and_then(|guild_id| {
+            config
+                .guilds
+                .iter()
+                .find(|guild| guild.guild_id == guild_id)
+        });
+
+        let max_turns = overrides
+            .and_then(|o| o.max_turns)
+            .unwrap_or(config.max_turns);
+        let max_tool_calls = overrides
+            .and_then(|o| o.max_tool_calls)
+            .unwrap_or(config.max_tool_calls);
+        let max_calls_per_tool = overrides
+            .and_then(|o| o.max_calls_per_tool)
+            .unwrap_or(config.max_calls_per_tool);
+        let timeout_seconds = overrides
+            .and_then(|o| o.timeout_seconds)
+            .unwrap_or(config.timeout_seconds);
+
+        Self {
+            max_turns: max_turns.max(1),
+            max_tool_calls: (max_tool_calls > 0).then_some(max_tool_calls),
+            max_calls_per_tool: (max_calls_per_tool > 0).then_some(max_calls_per_tool),
+            timeout: (timeout_seconds > 0).then(|| Duration::from_secs(timeout_seconds)),
+        }
+    }
+
+    /// Limits of a sub-agent run by `delegate_task`.
+    pub fn for_delegation(config: &DelegationConfig) -> Self {
+        Self {
+            max_turns: config.max_turns.max(1),
+            max_tool_calls: (config.max_tool_calls > 0).then_some(config.max_tool_calls),
+            max_calls_per_tool: (config.max_calls_per_tool > 0)
+                .then_some(config.max_calls_per_tool),
+            timeout: (config.timeout_seconds > 0)
+                .then(|| Duration::from_secs(config.timeout_seconds)),
+        }
+    }
+}
+
+impl Default for LoopLimits {
+    fn default() -> Self {
+        Self::for_guild(&AgentLoopConfig::default(), None)
+    }
+}
+
+/// The limit that stopped a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopLimit {
+    Turns(usize),
+    ToolCalls(u32),
+    CallsPerTool { tool: String, limit: u32 },
+    Timeout(Duration),
+}
+
+impl LoopLimit {
+    /// Shown to the user in place of (or after) the unfinished answer.
+    pub fn explanation(&self) -> String {
+        match self {
+            Self::Turns(limit) => format!(
+                "[stopped: this request reached the limit of {limit} model turns. Try splitting it into smaller steps.]"
+            ),
+            Self::ToolCalls(limit) => format!(
+                "[stopped: this request reached the limit of {limit} tool calls. Try splitting it into smaller steps.]"
+            ),
+            Self::CallsPerTool { tool, limit } => format!(
+                "[stopped: `{tool}` was called {limit} times, which is the limit for a single request.]"
+            ),
+            Self::Timeout(timeout) => format!(
+                "[stopped: this request took longer than {} seconds.]",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for LoopLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Turns(limit) => write!(f, "max turns ({limit}) reached"),
+            Self::ToolCalls(limit) => write!(f, "max tool calls ({limit}) reached"),
+            Self::CallsPerTool { tool, limit } => {
+                write!(f, "max calls of {tool} ({limit}) reached")
+            }
+            Self::Timeout(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+        }
+    }
+}
+
+/// Returned by providers when a loop limit stopped the request. It is not a model
+/// failure, so it is neither retried nor counted against the circuit breaker.
+#[derive(Debug)]
+pub struct LoopLimitExceeded(pub LoopLimit);
+
+impl fmt::Display for LoopLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "agent loop stopped: {}", self.0)
+    }
+}
+
+impl std::error::Error for LoopLimitExceeded {}
+
+pub fn loop_limit(error: &anyhow::Error) -> Option<&LoopLimit> {
+    error
+        .downcast_ref::<LoopLimitExceeded>()
+        .map(|exceeded| &exceeded.0)
+}
+
+/// Tool-call counters of a single request. Installed as the prompt hook so that
+/// the model loop is terminated before a call over the budget runs.
+#[derive(Clone, Default)]
+pub struct LoopBudget {
+    inner: Arc<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    limits: LoopLimits,
+    calls: Mutex<ToolCalls>,
+}
+
+#[derive(Default)]
+struct ToolCalls {
+    total: u32,
+    per_tool: HashMap<String, u32>,
+    exceeded: Option<LoopLimit>,
+}
+
+impl LoopBudget {
+    pub fn new(limits: LoopLimits) -> Self {
+        Self {
+            inner: Arc::new(BudgetState {
+                limits,
+                calls: Mutex::default(),
+            }),
+        }
+    }
+
+    pub fn limits(&self) -> LoopLimits {
+        self.inner.limits
+    }
+
+    /// Tool calls started so far, by every model the request went to.
+    pub fn tool_calls(&self) -> u32 {
+        self.calls().total
+    }
+
+    /// The limit that terminated the loop, if any.
+    pub fn exceeded(&self) -> Option<LoopLimit> {
+        self.calls().exceeded.clone()
+    }
+
+    fn record_tool_call(&self, tool: &str) -> Result<(), LoopLimit> {
+        let limits = self.inner.limits;
+        let mut calls = self.calls();
+
+        let tool_calls = calls.per_tool.get(tool).copied().unwrap_or_default();
+        let exceeded = if limits
+            .max_tool_calls
+            .is_some_and(|limit| calls.total >= limit)
+        {
+            limits.max_tool_calls.map(LoopLimit::ToolCalls)
+        } else if limits
+            .max_calls_per_tool
+            .is_some_and(|limit| tool_calls >= limit)
+        {
+            limits
+                .max_calls_per_tool
+                .map(|limit| LoopLimit::CallsPerTool {
+                    tool: tool.to_string(),
+                    limit,
+                })
+        } else {
+            None
+        };
+
+        if let Some(limit) = exceeded {
+            calls.exceeded.get_or_insert_with(|| limit.clone());
+            return Err(limit);
+        }
+
+        calls.total += 1;
+        calls.per_tool.insert(tool.to_string(), tool_calls + 1);
+        Ok(())
+    }
+
+    /// Converts the errors rig raises for an exhausted loop into [`LoopLimitExceeded`].
+    pub(crate) fn prompt_error(&self, error: PromptError) -> anyhow::Error {
+        match &error {
+            PromptError::MaxTurnsError { max_turns, .. } => {
+                LoopLimitExceeded(LoopLimit::Turns(*max_turns)).into()
+            }
+            PromptError::PromptCancelled { .. } => match self.exceeded() {
+                Some(limit) => LoopLimitExceeded(limit).into(),
+                None => error.into(),
+            },
+            _ => error.into(),
+        }
+    }
+
+    pub(crate) fn streaming_error(&self, error: StreamingError) -> anyhow::Error {
+        match error {
+            StreamingError::Prompt(error) => self.prompt_error(*error),
+            error => error.into(),
+        }
+    }
+
+    fn calls(&self) -> std::sync::MutexGuard<'_, ToolCalls> {
+        self.inner.calls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<M> PromptHook<M> for LoopBudget
+where
+    M: CompletionModel,
+{
+    async fn on_tool_call(
+        &self,
+        tool_name: &str,
+        _tool_call_id: Option<String>,
+        _internal_call_id: &str,
+        _args: &str,
+    ) -> ToolCallHookAction {
+        match self.record_tool_call(tool_name) {
+            Ok(()) => ToolCallHookAction::cont(),
+            Err(limit) => ToolCallHookAction::terminate(limit.to_string()),
+        }
+    }
+}
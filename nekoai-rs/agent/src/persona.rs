@@ -0,0 +1,238 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use nekoai_config::loader::{Config, ConversationModel};
+use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::event_bus::EventBus;
+use nekoai_memory::persistence::ChannelPersonaStore;
+use rig::tool::server::{ToolServer, ToolServerHandle};
+use tracing::{debug, info, warn};
+
+use crate::{fallback::ModelChain, usage::UsageMeter};
+
+/// Picking this name with `/persona` overrides a configured binding with the
+/// plain conversation model, `INSTRUCTION.md` and every tool.
+pub const DEFAULT_PERSONA: &str = "default";
+
+/// A persona name that is neither configured nor [`DEFAULT_PERSONA`].
+#[derive(Debug, Clone)]
+pub struct UnknownPersona(pub String);
+
+impl fmt::Display for UnknownPersona {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown persona `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPersona {}
+
+/// A named agent profile from `[[personas]]`.
+pub struct Persona {
+    pub name: String,
+    pub description: String,
+    models: Arc<ModelChain>,
+    tool_server_handle: ToolServerHandle,
+    /// `None` when the persona may call every tool.
+    tools: Option<Vec<String>>,
+}
+
+impl Persona {
+    pub fn models(&self) -> &Arc<ModelChain> {
+        &self.models
+    }
+
+    pub fn tool_server_handle(&self) -> &ToolServerHandle {
+        &self.tool_server_handle
+    }
+
+    pub fn tools(&self) -> Option<&[String]> {
+        self.tools.as_deref()
+    }
+
+    /// The persona's own tool server, when `tool` is on its allowlist. Personas
+    /// without an allowlist share the runtime's tool server instead.
+    pub(crate) fn own_tool(&self, tool: &str) -> Option<&ToolServerHandle> {
+        self.tools
+            .as_ref()
+            .is_some_and(|tools| tools.iter().any(|name| name == tool))
+            .then_some(&self.tool_server_handle)
+    }
+}
+
+/// The configured personas, which channels use them, and the picks made with
+/// `/persona`, which are persisted in a [`ChannelPersonaStore`].
+pub struct PersonaRegistry {
+    personas: Vec<Arc<Persona>>,
+    /// `channel_ids` of the config, by channel ID.
+    bindings: HashMap<u64, usize>,
+    /// Picks made with `/persona`, by channel ID.
+    selections: DashMap<u64, String>,
+    store: Arc<dyn ChannelPersonaStore>,
+}
+
+impl PersonaRegistry {
+    /// Personas that set neither `model_name` nor `parameters` share
+    /// `conversation_models`, and the ones without `tools` share `tool_server_handle`.
+    pub async fn load(
+        config: &Config,
+        conversation_models: &Arc<ModelChain>,
+        tool_server_handle: &ToolServerHandle,
+        event_bus: &EventBus,
+        usage_meter: &UsageMeter,
+        store: Arc<dyn ChannelPersonaStore>,
+    ) -> Result<Self> {
+        let mut personas: Vec<Arc<Persona>> = Vec::with_capacity(config.personas.len());
+        let mut bindings = HashMap::new();
+
+        for persona in &config.personas {
+            let name = persona.name.trim();
+            if name.is_empty() || name == DEFAULT_PERSONA {
+                warn!(name = %persona.name, "skipping persona with a reserved or empty name");
+                continue;
+            }
+            if personas.iter().any(|known| known.name == name) {
+                warn!(name = %name, "skipping duplicate persona");
+                continue;
+            }
+
+            let models = if persona.model_name.is_none() && persona.parameters.is_none() {
+                conversation_models.clone()
+            } else {
+                let primary =
+                    ConversationModel {
+                        model_name: persona.model_name.clone().unwrap_or_else(|| {
+                            config.provider.conversation_model.model_name.clone()
+                        }),
+                        parameters: persona.parameters.clone().unwrap_or_else(|| {
+                            config.provider.conversation_model.parameters.clone()
+                        }),
+                        ..config.provider.conversation_model.clone()
+                    };
+                Arc::new(
+                    ModelChain::from_config(
+                        &primary,
+                        &config.provider.fallback_conversation_models,
+                        &config.provider.circuit_breaker,
+                        event_bus.clone(),
+                        usage_meter,
+                    )
+                    .with_context(|| format!("failed to initialize models of persona `{name}`"))?,
+                )
+            };
+
+            let tool_server_handle = match persona.tools {
+                Some(_) => ToolServer::new().run(),
+                None => tool_server_handle.clone(),
+            };
+
+            let index = personas.len();
+            for channel_id in &persona.channel_ids {
+                if let Some(previous) = bindings.insert(*channel_id, index) {
+                    warn!(
+                        channel_id = channel_id,
+                        persona = %name,
+                        previous = %personas[previous].name,
+                        "channel is bound to more than one persona, using the last one"
+                    );
+                }
+            }
+
+            personas.push(Arc::new(Persona {
+                name: name.to_string(),
+                description: persona.description.clone(),
+                models,
+                tool_server_handle,
+                tools: persona.tools.clone(),
+            }));
+        }
+
+        let mut selections = DashMap::new();
+        match store.load_channel_personas().await {
+            Ok(saved) => selections.extend(saved),
+            Err(e) => warn!(error = %e, "failed to load channel personas"),
+        }
+
+        info!(
+            personas = personas.len(),
+            bound_channels = bindings.len(),
+            selected_channels = selections.len(),
+            "personas loaded"
+        );
+
+        Ok(Self {
+            personas,
+            bindings,
+            selections,
+            store,
+        })
+    }
+
+    pub fn list(&self) -> &[Arc<Persona>] {
+        &self.personas
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Persona>> {
+        self.personas.iter().find(|persona| persona.name == name)
+    }
+
+    /// The persona of a session: a pick or a binding of the thread, then of
+    /// its channel, then of the thread's parent. `None` means the default.
+    pub fn resolve(
+        &self,
+        session_key: &SessionKey,
+        parent_channel_id: Option<u64>,
+    ) -> Option<Arc<Persona>> {
+        let channels = session_key
+            .thread_id
+            .map(|id| id.get())
+            .into_iter()
+            .chain([session_key.channel_id.get()])
+            .chain(parent_channel_id);
+
+        for channel_id in channels {
+            if let Some(selected) = self.selections.get(&channel_id) {
+                if selected.as_str() == DEFAULT_PERSONA {
+                    return None;
+                }
+                match self.get(&selected) {
+                    Some(persona) => return Some(persona.clone()),
+                    None => {
+                        debug!(channel_id = channel_id, persona = %*selected, "selected persona is no longer configured");
+                    }
+                }
+            }
+            if let Some(&index) = self.bindings.get(&channel_id) {
+                return Some(self.personas[index].clone());
+            }
+        }
+        None
+    }
+
+    /// Picks a persona for a channel or thread, or drops the pick when `name`
+    /// is `None` so that the configured binding applies again. Fails with
+    /// [`UnknownPersona`] for a name that isn't configured.
+    pub async fn select(&self, channel_id: u64, name: Option<&str>) -> Result<()> {
+        if let Some(name) = name
+            && name != DEFAULT_PERSONA
+            && self.get(name).is_none()
+        {
+            return Err(UnknownPersona(name.to_string()).into());
+        }
+
+        self.store
+            .save_channel_persona(channel_id, name)
+            .await
+            .context("failed to save channel persona")?;
+        match name {
+            Some(name) => {
+                self.selections.insert(channel_id, name.to_string());
+            }
+            None => {
+                self.selections.remove(&channel_id);
+            }
+        }
+        info!(channel_id = channel_id, persona = ?name, "channel persona changed");
+        Ok(())
+    }
+}
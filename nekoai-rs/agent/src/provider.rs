@@ -1,36 +1,320 @@
-use nekoai_config::loader::Parameters;
+use std::{pin::Pin, sync::Arc};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use nekoai_config::loader::{Parameters, ProviderKind};
+use nekoai_infra::usage::UsagePurpose;
 use rig::{
-    agent::AgentBuilder,
+    OneOrMany,
+    agent::{Agent, AgentBuilder, MultiTurnStreamItem},
     client::CompletionClient,
-    providers::openai::{self, completion::CompletionModel},
+    completion::{CompletionModel, Message, Prompt, message::UserContent},
+    providers::{anthropic, ollama, openai},
+    streaming::{StreamedAssistantContent, StreamingPrompt},
+    tool::server::ToolServerHandle,
 };
+use schemars::Schema;
 use serde_json::json;
 
-pub struct OpenAICompatibleAdapter {
-    client: openai::CompletionsClient,
+use crate::{limits::LoopBudget, usage::UsageMeter};
+
+/// A single model invocation: system prompt, history, the new user message and
+/// the tools the model may call while answering.
+#[derive(Clone, Default)]
+pub struct ModelRequest {
+    pub preamble: Option<String>,
+    pub prompt: String,
+    /// Image parts sent along with `prompt`.
+    pub images: Vec<UserContent>,
+    pub chat_history: Vec<Message>,
+    pub tool_server_handle: Option<ToolServerHandle>,
+    pub max_turns: usize,
+    pub loop_budget: LoopBudget,
+    /// Reported with the token usage of the call.
+    pub purpose: UsagePurpose,
+    /// JSON schema the answer must follow, sent as the provider's response
+    /// format when `parameters.structured_output` is on.
+    pub output_schema: Option<Schema>,
 }
 
-impl OpenAICompatibleAdapter {
-    pub fn new(client: openai::CompletionsClient) -> Self {
-        Self { client }
+impl ModelRequest {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            max_turns: DEFAULT_MAX_TURNS,
+            ..Default::default()
+        }
+    }
+
+    pub fn preamble(mut self, preamble: impl Into<String>) -> Self {
+        self.preamble = Some(preamble.into());
+        self
+    }
+
+    pub fn images(mut self, images: Vec<UserContent>) -> Self {
+        self.images = images;
+        self
+    }
+
+    pub fn chat_history(mut self, chat_history: Vec<Message>) -> Self {
+        self.chat_history = chat_history;
+        self
+    }
+
+    pub fn tool_server_handle(mut self, handle: ToolServerHandle) -> Self {
+        self.tool_server_handle = Some(handle);
+        self
+    }
+
+    pub fn purpose(mut self, purpose: UsagePurpose) -> Self {
+        self.purpose = purpose;
+        self
+    }
+
+    pub fn output_schema(mut self, schema: Schema) -> Self {
+        self.output_schema = Some(schema);
+        self
+    }
+
+    /// Applies the request's loop limits: the turn limit and the tool-call budget.
+    pub fn loop_budget(mut self, budget: LoopBudget) -> Self {
+        self.max_turns = budget.limits().max_turns;
+        self.loop_budget = budget;
+        self
     }
 
-    pub fn build_agent(
-        &self,
-        model: &str,
+    fn user_message(&self) -> Message {
+        let mut content = OneOrMany::one(UserContent::text(&self.prompt));
+        for image in &self.images {
+            content.push(image.clone());
+        }
+        Message::User { content }
+    }
+}
+
+pub enum ModelStreamItem {
+    Text(String),
+    Completed(String),
+}
+
+pub type ModelStream = Pin<Box<dyn Stream<Item = Result<ModelStreamItem>> + Send>>;
+
+const DEFAULT_MAX_TURNS: usize = 20;
+
+/// Backend-agnostic access to a configured language model.
+#[async_trait]
+pub trait LanguageModelProvider: Send + Sync {
+    fn provider_name(&self) -> &'static str;
+
+    fn model_name(&self) -> &str;
+
+    /// Runs the request to completion, including any tool round-trips.
+    async fn prompt(&self, request: ModelRequest) -> Result<String>;
+
+    /// Runs the request and yields text deltas followed by the final answer.
+    async fn stream(&self, request: ModelRequest) -> Result<ModelStream>;
+}
+
+/// Builds the provider for the configured API protocol.
+pub fn build_provider(
+    kind: ProviderKind,
+    base_url: &str,
+    api_key: &str,
+    model_name: &str,
+    parameters: Parameters,
+    usage_meter: UsageMeter,
+) -> Result<Arc<dyn LanguageModelProvider>> {
+    let provider: Arc<dyn LanguageModelProvider> = match kind {
+        ProviderKind::OpenAICompatible => {
+            let client = openai::Client::builder()
+                .api_key(api_key)
+                .base_url(base_url)
+                .build()
+                .context("failed to build OpenAI compatible client")?
+                .completions_api();
+            Arc::new(RigModelProvider::new(
+                "openai-compatible",
+                client.completion_model(model_name),
+                model_name,
+                parameters,
+                usage_meter,
+            ))
+        }
+        ProviderKind::OpenAIResponses => {
+            let client = openai::Client::builder()
+                .api_key(api_key)
+                .base_url(base_url)
+                .build()
+                .context("failed to build OpenAI responses client")?;
+            Arc::new(RigModelProvider::new(
+                "openai-responses",
+                client.completion_model(model_name),
+                model_name,
+                parameters,
+                usage_meter,
+            ))
+        }
+        ProviderKind::Anthropic => {
+            let client = anthropic::Client::builder()
+                .api_key(api_key)
+                .base_url(base_url)
+                .build()
+                .context("failed to build Anthropic client")?;
+            Arc::new(RigModelProvider::new(
+                "anthropic",
+                client.completion_model(model_name),
+                model_name,
+                parameters,
+                usage_meter,
+            ))
+        }
+        ProviderKind::Ollama => {
+            let client = ollama::Client::builder()
+                .api_key(api_key)
+                .base_url(base_url)
+                .build()
+                .context("failed to build Ollama client")?;
+            Arc::new(RigModelProvider::new(
+                "ollama",
+                client.completion_model(model_name),
+                model_name,
+                parameters,
+                usage_meter,
+            ))
+        }
+    };
+
+    Ok(provider)
+}
+
+/// [`LanguageModelProvider`] backed by any Rig completion model.
+pub struct RigModelProvider<M> {
+    provider_name: &'static str,
+    model: M,
+    model_name: String,
+    parameters: Parameters,
+    usage_meter: UsageMeter,
+}
+
+impl<M> RigModelProvider<M>
+where
+    M: CompletionModel + 'static,
+{
+    pub fn new(
+        provider_name: &'static str,
+        model: M,
+        model_name: &str,
         parameters: Parameters,
-    ) -> AgentBuilder<CompletionModel> {
-        self.client
-            .agent(model)
-            .max_tokens(parameters.max_token)
-            .temperature(parameters.temperature)
-            .default_max_turns(20)
-            .additional_params(json!({
-                "top_p": parameters.top_p,
-            }))
-    }
-
-    pub fn provider_name(&self) -> &str {
-        "openai-compatible"
+        usage_meter: UsageMeter,
+    ) -> Self {
+        Self {
+            provider_name,
+            model,
+            model_name: model_name.to_string(),
+            parameters,
+            usage_meter,
+        }
+    }
+
+    fn build_agent(&self, request: &ModelRequest) -> Agent<M> {
+        let builder = AgentBuilder::new(self.model.clone())
+            .max_tokens(self.parameters.max_token)
+            .temperature(self.parameters.temperature)
+            .default_max_turns(request.max_turns);
+
+        // Every supported backend accepts `top_p` through the additional params:
+        // OpenAI and Anthropic as a top-level field, Ollama inside `options`.
+        let builder = match self.parameters.top_p {
+            Some(top_p) => builder.additional_params(json!({ "top_p": top_p })),
+            None => builder,
+        };
+
+        let builder = match &request.preamble {
+            Some(preamble) => builder.preamble(preamble),
+            None => builder,
+        };
+
+        let builder = match &request.output_schema {
+            Some(schema) if self.parameters.structured_output => {
+                builder.output_schema_raw(schema.clone())
+            }
+            _ => builder,
+        };
+
+        match &request.tool_server_handle {
+            Some(handle) => builder.tool_server_handle(handle.clone()).build(),
+            None => builder.build(),
+        }
+    }
+}
+
+#[async_trait]
+impl<M> LanguageModelProvider for RigModelProvider<M>
+where
+    M: CompletionModel + 'static,
+{
+    fn provider_name(&self) -> &'static str {
+        self.provider_name
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    async fn prompt(&self, request: ModelRequest) -> Result<String> {
+        let agent = self.build_agent(&request);
+        let message = request.user_message();
+        let budget = request.loop_budget;
+        let response = agent
+            .prompt(message)
+            .max_turns(request.max_turns)
+            .with_hook(budget.clone())
+            .with_history(request.chat_history)
+            .extended_details()
+            .await
+            .map_err(|e| budget.prompt_error(e))?;
+
+        self.usage_meter
+            .record(&self.model_name, request.purpose, response.usage);
+        Ok(response.output)
+    }
+
+    async fn stream(&self, request: ModelRequest) -> Result<ModelStream> {
+        let agent = self.build_agent(&request);
+        let message = request.user_message();
+        let budget = request.loop_budget;
+        let stream = agent
+            .stream_prompt(message)
+            .multi_turn(request.max_turns)
+            .with_hook(budget.clone())
+            .with_history(request.chat_history)
+            .await;
+
+        let usage_meter = self.usage_meter.clone();
+        let model_name = self.model_name.clone();
+        let purpose = request.purpose;
+        let stream = stream.filter_map(move |item| {
+            let budget = budget.clone();
+            let usage_meter = usage_meter.clone();
+            let model_name = model_name.clone();
+            async move {
+                match item {
+                    Ok(MultiTurnStreamItem::StreamAssistantItem(
+                        StreamedAssistantContent::Text(text),
+                    )) => Some(Ok(ModelStreamItem::Text(text.text))),
+                    Ok(MultiTurnStreamItem::FinalResponse(final_response)) => {
+                        usage_meter.record(&model_name, purpose, final_response.usage());
+                        Some(Ok(ModelStreamItem::Completed(
+                            final_response.response().to_string(),
+                        )))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(Err(budget.streaming_error(e))),
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
     }
 }
@@ -0,0 +1,309 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use nekoai_config::loader::{GuildRateLimitConfig, RateLimitConfig};
+use nekoai_infra::{
+    metrics::Metrics,
+    usage::{UsageGroupBy, UsageQuery},
+};
+use nekoai_memory::persistence::RateLimitStore;
+use tracing::{info, warn};
+
+/// Idle buckets are dropped once this many users have one.
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+/// Which limit turned a request away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitScope {
+    /// The user's own token bucket.
+    User,
+    /// The guild's daily request quota.
+    GuildMessages,
+    /// The guild's daily model token quota.
+    GuildTokens,
+}
+
+impl RateLimitScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::GuildMessages => "guild_messages",
+            Self::GuildTokens => "guild_tokens",
+        }
+    }
+}
+
+/// A rejected request and when the caller may try again.
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    pub scope: RateLimitScope,
+    pub limit: u64,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rate limited by {} (limit {}) until {}",
+            self.scope.as_str(),
+            self.limit,
+            self.resets_at.to_rfc3339()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct DailyCount {
+    day: Option<NaiveDate>,
+    count: u64,
+}
+
+/// Admission control in front of the agent: a token bucket per user and daily
+/// request/token quotas per guild. Daily quotas reset at midnight UTC, and the
+/// token quota is read from the usage recorded in [`Metrics`]. Daily request
+/// counts are kept in a [`RateLimitStore`] so that a restart doesn't reset
+/// them; token buckets start full again.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    metrics: Metrics,
+    store: Arc<dyn RateLimitStore>,
+    buckets: DashMap<String, TokenBucket>,
+    guild_messages: DashMap<u64, DailyCount>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, metrics: Metrics, store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            config,
+            metrics,
+            store,
+            buckets: DashMap::new(),
+            guild_messages: DashMap::new(),
+        }
+    }
+
+    /// Drops the stored request counts of earlier days and loads today's.
+    /// Failures are logged and leave the counts starting from zero.
+    pub async fn load(&self) {
+        let today = Utc::now().date_naive();
+        if let Err(e) = self.store.delete_guild_message_counts_before(today).await {
+            warn!(error = %e, "failed to delete old guild message counts");
+        }
+
+        match self.store.load_guild_message_counts(today).await {
+            Ok(counts) => {
+                for (guild_id, count) in counts {
+                    self.guild_messages.insert(
+                        guild_id,
+                        DailyCount {
+                            day: Some(today),
+                            count,
+                        },
+                    );
+                }
+            }
+            Err(e) => warn!(error = %e, "failed to load guild message counts"),
+        }
+    }
+
+    /// Admits one request from `user_id`, or tells when it may be retried.
+    /// `role_ids` are the caller's roles in the guild, used for exemptions.
+    /// Requests without a user only count against the guild quotas.
+    pub async fn check(
+        &self,
+        user_id: Option<&str>,
+        guild_id: Option<u64>,
+        role_ids: &[u64],
+    ) -> Result<(), RateLimited> {
+        if self.is_exempt(guild_id, role_ids) {
+            return Ok(());
+        }
+
+        let today = Utc::now().date_naive();
+        if let Err(limited) = self.admit(user_id, guild_id, today) {
+            info!(
+                user_id = ?user_id,
+                guild_id = ?guild_id,
+                scope = limited.scope.as_str(),
+                resets_at = %limited.resets_at,
+                "request rate limited"
+            );
+            self.metrics.record_rate_limited(limited.scope.as_str());
+            return Err(limited);
+        }
+
+        // The request is admitted even when the count can't be stored; only a
+        // restart later today would let the guild past its quota.
+        if let Some(guild_id) = guild_id
+            && let Err(e) = self.store.add_guild_message(guild_id, today).await
+        {
+            warn!(guild_id = guild_id, error = %e, "failed to save guild message count");
+        }
+        Ok(())
+    }
+
+    fn is_exempt(&self, guild_id: Option<u64>, role_ids: &[u64]) -> bool {
+        let guild_roles = self
+            .guild_config(guild_id)
+            .map(|guild| guild.exempt_role_ids.as_slice())
+            .unwrap_or_default();
+
+        role_ids
+            .iter()
+            .any(|role| self.config.exempt_role_ids.contains(role) || guild_roles.contains(role))
+    }
+
+    fn admit(
+        &self,
+        user_id: Option<&str>,
+        guild_id: Option<u64>,
+        today: NaiveDate,
+    ) -> Result<(), RateLimited> {
+        // Held until the request is admitted so that concurrent requests of the
+        // guild can't all slip in under the quota.
+        let mut guild_messages = match guild_id {
+            Some(guild_id) => {
+                let mut messages = self.guild_messages.entry(guild_id).or_default();
+                if messages.day != Some(today) {
+                    *messages = DailyCount {
+                        day: Some(today),
+                        count: 0,
+                    };
+                }
+
+                self.check_guild_quotas(guild_id, today, messages.count)?;
+                Some(messages)
+            }
+            None => None,
+        };
+
+        if let Some(user_id) = user_id {
+            self.take_user_token(user_id)?;
+        }
+
+        if let Some(messages) = guild_messages.as_mut() {
+            messages.count += 1;
+        }
+        Ok(())
+    }
+
+    fn check_guild_quotas(
+        &self,
+        guild_id: u64,
+        today: NaiveDate,
+        messages_today: u64,
+    ) -> Result<(), RateLimited> {
+        let guild = self.guild_config(Some(guild_id));
+        let daily_messages = guild
+            .and_then(|guild| guild.daily_messages)
+            .unwrap_or(self.config.guild_daily_messages);
+        let daily_tokens = guild
+            .and_then(|guild| guild.daily_tokens)
+            .unwrap_or(self.config.guild_daily_tokens);
+
+        if daily_messages > 0 && messages_today >= u64::from(daily_messages) {
+            return Err(RateLimited {
+                scope: RateLimitScope::GuildMessages,
+                limit: u64::from(daily_messages),
+                resets_at: start_of_next_day(today),
+            });
+        }
+
+        if daily_tokens > 0 {
+            let tokens_today: u64 = self
+                .metrics
+                .usage(&UsageQuery {
+                    group_by: UsageGroupBy::Guild,
+                    guild_id: Some(guild_id),
+                    since: Some(today),
+                    until: Some(today),
+                    ..Default::default()
+                })
+                .iter()
+                .map(|summary| summary.input_tokens + summary.output_tokens)
+                .sum();
+
+            if tokens_today >= daily_tokens {
+                return Err(RateLimited {
+                    scope: RateLimitScope::GuildTokens,
+                    limit: daily_tokens,
+                    resets_at: start_of_next_day(today),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn take_user_token(&self, user_id: &str) -> Result<(), RateLimited> {
+        let burst = self.config.user_burst;
+        let refill_seconds = self.config.user_refill_seconds;
+        if burst == 0 || refill_seconds == 0 {
+            return Ok(());
+        }
+
+        let capacity = f64::from(burst);
+        let refill = Duration::from_secs(refill_seconds);
+        let now = Instant::now();
+
+        if !self.buckets.contains_key(user_id) && self.buckets.len() >= MAX_IDLE_BUCKETS {
+            // A bucket that has been idle long enough to refill is the same as no bucket.
+            let full_after = refill.saturating_mul(burst);
+            self.buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated) < full_after);
+        }
+
+        let mut bucket = self
+            .buckets
+            .entry(user_id.to_string())
+            .or_insert(TokenBucket {
+                tokens: capacity,
+                updated: now,
+            });
+
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / refill.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = refill.mul_f64(1.0 - bucket.tokens);
+            return Err(RateLimited {
+                scope: RateLimitScope::User,
+                limit: u64::from(burst),
+                resets_at: Utc::now() + TimeDelta::from_std(wait).unwrap_or_default(),
+            });
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn guild_config(&self, guild_id: Option<u64>) -> Option<&GuildRateLimitConfig> {
+        let guild_id = guild_id?;
+        self.config
+            .guilds
+            .iter()
+            .find(|guild| guild.guild_id == guild_id)
+    }
+}
+
+fn start_of_next_day(today: NaiveDate) -> DateTime<Utc> {
+    today
+        .checked_add_days(Days::new(1))
+        .unwrap_or(today)
+        .and_time(NaiveTime::MIN)
+        .and_utc()
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use nekoai_infra::usage::UsagePurpose;
+use nekoai_memory::{rerank::Reranker, store::MemoryEntry};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio_retry::RetryIf;
+
+use crate::{
+    provider::{LanguageModelProvider, ModelRequest},
+    runtime::{escape_xml, model_retry_strategy},
+    structured::{InvalidStructuredOutput, prompt_structured},
+};
+
+/// Highest relevance the model can give.
+const MAX_RELEVANCE: u8 = 10;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RerankScores {
+    scores: Vec<RerankScore>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RerankScore {
+    /// Index of the memory as given in the prompt.
+    index: usize,
+    /// From 0 (unrelated to the message) to 10 (needed to answer it).
+    relevance: u8,
+}
+
+/// Lets the summarizer model score how relevant recalled memories are to the
+/// message they were recalled for.
+pub struct SummarizerReranker {
+    model: Arc<dyn LanguageModelProvider>,
+}
+
+impl SummarizerReranker {
+    pub fn new(model: Arc<dyn LanguageModelProvider>) -> Self {
+        Self { model }
+    }
+}
+
+#[async_trait]
+impl Reranker for SummarizerReranker {
+    async fn rerank(&self, query: &str, candidates: &[MemoryEntry]) -> Result<Vec<f32>> {
+        let memories: String = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                format!(
+                    "    <memory index=\"{index}\">{}</memory>\n",
+                    escape_xml(&entry.content)
+                )
+            })
+            .collect();
+        let prompt = format!(
+            "<memory_rerank_task>\n  <instruction>\n    Memories were recalled for a message sent to the assistant. Rate how relevant each memory is to answering the message, from 0 (unrelated) to 10 (needed to answer it). Give one score per memory, by its index.\n  </instruction>\n  <message>{}</message>\n  <memories>\n{}  </memories>\n</memory_rerank_task>",
+            escape_xml(query),
+            memories
+        );
+
+        let request = ModelRequest::new(prompt).purpose(UsagePurpose::Reranking);
+        let ranked: RerankScores = RetryIf::spawn(
+            model_retry_strategy(),
+            || prompt_structured(request.clone(), |request| self.model.prompt(request)),
+            |e: &anyhow::Error| !e.is::<InvalidStructuredOutput>(),
+        )
+        .await?;
+
+        // Memories the model left out score 0.
+        let mut scores = vec![0.0; candidates.len()];
+        for score in ranked.scores {
+            if let Some(slot) = scores.get_mut(score.index) {
+                *slot = f32::from(score.relevance.min(MAX_RELEVANCE)) / f32::from(MAX_RELEVANCE);
+            }
+        }
+        Ok(scores)
+    }
+}
@@ -1,40 +1,70 @@
-use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use nekoai_config::loader::{Config, Parameters};
+use futures::{Stream, StreamExt};
+use nekoai_config::loader::{AgentLoopConfig, Config};
 use nekoai_domain::agent::{
-    runtime::{CallerContext, with_caller_context},
+    runtime::{CallerContext, current_caller_context, with_caller_context},
     session::SessionKey,
 };
 use nekoai_infra::{
     event_bus::{AgentEvent, EventBus},
     metrics::Metrics,
+    usage::UsagePurpose,
     web_ui_agent::WebUiAgent,
 };
 use nekoai_memory::{
+    long_term::NewFact,
+    persistence::{ExtractionJob, ExtractionQueue, NewExtractionJob},
     short_term::{Role, ShortTermEntry},
     store::MemoryStore,
 };
 use rig::{
-    completion::{Message, Prompt, ToolDefinition},
+    completion::{Message, ToolDefinition, message::UserContent},
     tool::{
-        ToolDyn, ToolError,
+        Tool, ToolDyn, ToolError,
         server::{ToolServer, ToolServerHandle},
     },
 };
+use schemars::JsonSchema;
 use serde::Deserialize;
-use tokio::sync::{Semaphore, mpsc};
+use tokio::sync::{Mutex, Notify, OwnedMutexGuard, Semaphore, mpsc};
 use tokio_retry::{
-    Retry,
+    RetryIf,
     strategy::{ExponentialBackoff, jitter},
 };
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{debug, info, warn};
 
 use crate::{
-    context::ContextManager,
-    provider::OpenAICompatibleAdapter,
+    consolidation::SummarizerConsolidator,
+    context::{ContextManager, Summary},
+    delegate::DelegateTask,
+    fallback::ModelChain,
+    input::{AttachmentPolicy, ResolvedInput, UserInput},
+    instructions::InstructionStore,
+    limits::{LoopBudget, LoopLimit, LoopLimits, loop_limit},
+    persona::PersonaRegistry,
+    provider::{LanguageModelProvider, ModelRequest, ModelStreamItem, build_provider},
+    rate_limit::{RateLimited, RateLimiter},
+    rerank::SummarizerReranker,
     session::{Session, SessionManager},
+    structured::{InvalidStructuredOutput, prompt_structured},
+    tokenizer::build_tokenizer,
+    usage::{UsageMeter, UsagePersistence},
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -56,45 +86,200 @@ impl RuntimeInitProgress {
     }
 }
 
+/// Identifies one submit so that it can be cancelled with
+/// [`AgentRuntime::cancel`], also while it waits for earlier submits of its
+/// session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    /// A new ID, unique within the process.
+    pub fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 pub struct AgentResponse {
     pub content: String,
+    /// The request was stopped with [`AgentRuntime::cancel`]; `content` is the partial answer.
+    pub cancelled: bool,
+}
+
+/// Item yielded by [`AgentRuntime::submit_stream`].
+pub enum ResponseStreamItem {
+    /// Text delta as it arrives from the model.
+    Chunk(String),
+    /// Final answer of the last model turn; always the last item of a successful stream.
+    Completed(AgentResponse),
+}
+
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<ResponseStreamItem>> + Send>>;
+
+/// Held for the whole lifetime of a submit. Once `turn` is taken, every earlier
+/// submit of the same session has finished.
+struct RequestSlot {
+    request_id: RequestId,
+    token: CancellationToken,
+    active_requests: Arc<DashMap<RequestId, CancellationToken>>,
+    turn: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.active_requests.remove(&self.request_id);
+    }
+}
+
+impl RequestSlot {
+    /// Resolves when the request is cancelled or has run for longer than `timeout`.
+    async fn interrupted(&self, timeout: Option<Duration>) -> Interruption {
+        let deadline = async {
+            match timeout {
+                Some(timeout) => {
+                    tokio::time::sleep(timeout).await;
+                    timeout
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = self.token.cancelled() => Interruption::Cancelled,
+            timeout = deadline => Interruption::Limit(LoopLimit::Timeout(timeout)),
+        }
+    }
+}
+
+/// Why a request stopped before the model finished answering.
+enum Interruption {
+    Cancelled,
+    Limit(LoopLimit),
+}
+
+struct PreparedPrompt {
+    caller_context: CallerContext,
+    /// The conversation models and tools of the session's persona.
+    models: Arc<ModelChain>,
+    tool_server_handle: ToolServerHandle,
+    system_prompt: String,
+    user_message: String,
+    images: Vec<UserContent>,
+    chat_history: Vec<Message>,
+    loop_budget: LoopBudget,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ExtractedFacts {
+    /// Empty when nothing in the conversation is worth remembering.
+    facts: Vec<ExtractedFact>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct ExtractedFact {
+    /// One self-contained piece of information.
     fact: String,
+    /// Short keywords describing the fact.
     #[serde(default)]
     tags: Vec<String>,
+    /// How much the fact matters for future conversations, from 1 (passing
+    /// detail) to 5 (core fact such as a name, a lasting preference or an
+    /// ongoing plan).
+    #[serde(default = "default_fact_importance")]
+    importance: u8,
+    /// The `user_id` of the user the fact is about, when it is about one of
+    /// the users in the conversation.
+    #[serde(default)]
+    user_id: Option<String>,
 }
 
-struct ExtractionTask {
-    session_key: SessionKey,
-    user_id: Option<String>,
-    conversation_batch: String,
+const fn default_fact_importance() -> u8 {
+    3
+}
+
+/// Conversation accumulated since the last long-term extraction of a session.
+#[derive(Default)]
+struct PendingExtraction {
+    /// Users who took part, in order of appearance.
+    user_ids: Vec<String>,
+    conversation: String,
+}
+
+/// Everything an extraction job needs, shared by the processor and its tasks.
+#[derive(Clone)]
+struct ExtractionWorker {
+    queue: Arc<dyn ExtractionQueue>,
+    provider: Arc<ModelChain>,
+    consolidator: Arc<SummarizerConsolidator>,
+    memory_store: Arc<MemoryStore>,
+    event_bus: EventBus,
+    metrics: Metrics,
 }
 
-const EXTRACTION_QUEUE_SIZE: usize = 100;
 const EXTRACTION_CONCURRENT_LIMIT: usize = 3;
+/// How often the queue is checked for jobs whose retry time has come.
+const EXTRACTION_POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// A claimed job becomes due again after this long, e.g. when the process died mid-job.
+const EXTRACTION_LEASE: Duration = Duration::from_secs(10 * 60);
+const EXTRACTION_MAX_ATTEMPTS: u32 = 10;
+const EXTRACTION_RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const EXTRACTION_RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+const RESPONSE_STREAM_BUFFER: usize = 64;
+/// Recorded as (the end of) the assistant side of a cancelled turn.
+const CANCELLED_TURN_MARKER: &str = "[response stopped by the user]";
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Holds `INSTRUCTION.md` and the `instructions/` overrides.
+const INSTRUCTION_DIR: &str = ".config";
 
 #[derive(Clone)]
 pub struct AgentRuntime {
     session_manager: Arc<SessionManager>,
     context_manager: Arc<ContextManager>,
     memory_store: Arc<MemoryStore>,
-    conversation_model: Arc<OpenAICompatibleAdapter>,
-    conversation_model_name: String,
-    conversation_model_parameters: Parameters,
-    summarization_model: Arc<OpenAICompatibleAdapter>,
-    summarization_model_name: String,
-    summarization_model_parameters: Parameters,
-    extraction_tx: mpsc::Sender<ExtractionTask>,
+    conversation_models: Arc<ModelChain>,
+    summarization_model: Arc<dyn LanguageModelProvider>,
+    reranker: Arc<SummarizerReranker>,
+    extraction_queue: Arc<dyn ExtractionQueue>,
+    extraction_notify: Arc<Notify>,
     tool_server_handle: ToolServerHandle,
+    /// Tools of the `delegate_task` sub-agent; `None` when delegation is off.
+    delegation_tool_server_handle: Option<ToolServerHandle>,
+    personas: Arc<PersonaRegistry>,
     summarizing: Arc<DashMap<SessionKey, ()>>,
-    accumulated_conversations: Arc<DashMap<SessionKey, String>>,
+    /// One lock per session so that submits of a session run one after another.
+    session_turns: Arc<DashMap<SessionKey, Arc<Mutex<()>>>>,
+    /// Cancellation token of each submit, running or waiting for its session.
+    active_requests: Arc<DashMap<RequestId, CancellationToken>>,
+    accumulated_conversations: Arc<DashMap<SessionKey, PendingExtraction>>,
     message_since_last_extraction: Arc<DashMap<SessionKey, usize>>,
     long_term_extraction_interval: usize,
+    session_idle_timeout: Option<Duration>,
+    agent_loop: Arc<AgentLoopConfig>,
+    rate_limiter: Arc<RateLimiter>,
+    usage: UsagePersistence,
+    attachment_policy: Arc<AttachmentPolicy>,
     event_bus: EventBus,
     metrics: Metrics,
+    /// Cancelled once shutdown starts; new submits are rejected from then on.
+    shutdown: CancellationToken,
+    /// Cancelled after the final flush; the extraction processor stops claiming jobs.
+    extraction_drain: CancellationToken,
+    /// Submits that are still producing an answer.
+    requests: TaskTracker,
+    /// Summarization and long-term extraction work that must finish before exit.
+    background_tasks: TaskTracker,
 }
 
 impl AgentRuntime {
@@ -111,95 +296,136 @@ impl AgentRuntime {
         F: FnMut(RuntimeInitProgress),
     {
         info!("initializing agent runtime");
-        let session_manager = Arc::new(SessionManager::new());
+        let session_manager = Arc::new(SessionManager::with_store(
+            memory_store.conversation_store(),
+        ));
         on_progress(RuntimeInitProgress::new(1, "session manager ready"));
 
-        let system_instruction_path = std::path::Path::new(".config").join("INSTRUCTION.md");
-        let system_instruction = std::fs::read_to_string(system_instruction_path)
-            .context("failed to read system instruction")?;
+        let instructions = Arc::new(InstructionStore::load(INSTRUCTION_DIR, &config.personas));
         on_progress(RuntimeInitProgress::new(2, "system instruction loaded"));
 
-        info!(
-            "system instruction loaded, length = {}",
-            system_instruction.len()
-        );
-
-        let context_manager = Arc::new(ContextManager::new(system_instruction, 16384, 0.7));
-
         let memory_store = Arc::new(memory_store);
-        on_progress(RuntimeInitProgress::new(
-            3,
-            "context manager and memory store ready",
-        ));
+        on_progress(RuntimeInitProgress::new(3, "memory store ready"));
 
-        let conversation_client = rig::providers::openai::Client::builder()
-            .api_key(config.provider.conversation_model.api_key.as_ref())
-            .base_url(&config.provider.conversation_model.provider_base_url)
-            .build()
-            .context("failed to build OpenAI compatible responses client")?
-            .completions_api();
-        let conversation_model = Arc::new(OpenAICompatibleAdapter::new(conversation_client));
-
-        let summarization_client = rig::providers::openai::Client::builder()
-            .api_key(config.provider.summarizer_model.api_key.as_ref())
-            .base_url(&config.provider.summarizer_model.provider_base_url)
-            .build()
-            .context("failed to build OpenAI compatible responses client")?
-            .completions_api();
-        let summarization_model = Arc::new(OpenAICompatibleAdapter::new(summarization_client));
+        let event_bus = EventBus::new(256);
+        let metrics = Metrics::new();
+        let usage_meter = UsageMeter::new(metrics.clone(), &config.provider.pricing);
+        let usage = UsagePersistence::new(
+            metrics.clone(),
+            memory_store.usage_store(),
+            config.provider.usage_retention_days,
+        );
+        usage.load().await;
+
+        let conversation_models = Arc::new(
+            ModelChain::from_config(
+                &config.provider.conversation_model,
+                &config.provider.fallback_conversation_models,
+                &config.provider.circuit_breaker,
+                event_bus.clone(),
+                &usage_meter,
+            )
+            .context("failed to initialize conversation model providers")?,
+        );
+
+        let summarizer_config = &config.provider.summarizer_model;
+        let summarization_model = build_provider(
+            summarizer_config.kind,
+            &summarizer_config.provider_base_url,
+            summarizer_config.api_key.as_ref(),
+            &summarizer_config.model_name,
+            summarizer_config.parameters.clone(),
+            usage_meter.clone(),
+        )
+        .context("failed to initialize summarization model provider")?;
         on_progress(RuntimeInitProgress::new(
             4,
             "language model provider initialized",
         ));
 
         info!(
-            conversation_model = conversation_model.provider_name(),
-            summarzation_model = summarization_model.provider_name(),
+            conversation_models = ?conversation_models.model_names(),
+            summarization_provider = summarization_model.provider_name(),
+            summarization_model = summarization_model.model_name(),
             "language model client initialized"
         );
 
-        let conversation_model_name = config.provider.conversation_model.model_name;
-        let conversation_model_parameters = config.provider.conversation_model.parameters;
-        let summarization_model_name = config.provider.summarizer_model.model_name;
-        let summarization_model_parameters = config.provider.summarizer_model.parameters;
+        let context_manager = Arc::new(ContextManager::new(
+            instructions.clone(),
+            &config.context,
+            build_tokenizer(config.context.tokenizer),
+            summarization_model.clone(),
+        ));
 
-        let (extraction_tx, extraction_rx) = mpsc::channel(EXTRACTION_QUEUE_SIZE);
+        let extraction_queue = memory_store.extraction_queue();
+        let extraction_notify = Arc::new(Notify::new());
 
         let semaphore = Arc::new(Semaphore::new(EXTRACTION_CONCURRENT_LIMIT));
 
-        let event_bus = EventBus::new(256);
-        let metrics = Metrics::new();
-
         let summarizing = Arc::new(DashMap::new());
-
-        let provider_clone = conversation_model.clone();
-        let memory_store_clone = memory_store.clone();
-        let conversation_model_name_clone = conversation_model_name.clone();
-        let conversation_model_parameters_clone = conversation_model_parameters.clone();
+        let session_turns = Arc::new(DashMap::new());
+        let active_requests = Arc::new(DashMap::new());
+        let shutdown = CancellationToken::new();
+        let extraction_drain = CancellationToken::new();
+        let requests = TaskTracker::new();
+        let background_tasks = TaskTracker::new();
+
+        let worker = ExtractionWorker {
+            queue: extraction_queue.clone(),
+            provider: conversation_models.clone(),
+            consolidator: Arc::new(SummarizerConsolidator::new(summarization_model.clone())),
+            memory_store: memory_store.clone(),
+            event_bus: event_bus.clone(),
+            metrics: metrics.clone(),
+        };
+        let notify_clone = extraction_notify.clone();
         let sem_clone = semaphore.clone();
-        let eb_clone = event_bus.clone();
+        let drain_clone = extraction_drain.clone();
+        let tasks_clone = background_tasks.clone();
 
-        tokio::spawn(async move {
+        background_tasks.spawn(instructions.watch(shutdown.clone()));
+        background_tasks.spawn(usage.clone().run(extraction_drain.clone()));
+
+        background_tasks.spawn(async move {
             info!("extraction task processor started");
-            extraction_task_processor(
-                extraction_rx,
-                provider_clone,
-                memory_store_clone,
-                conversation_model_name_clone,
-                conversation_model_parameters_clone,
-                sem_clone,
-                eb_clone,
-            )
-            .await;
+            extraction_task_processor(worker, notify_clone, sem_clone, drain_clone, tasks_clone)
+                .await;
         });
 
         let accumulated_conversations = Arc::new(DashMap::new());
         let message_since_last_extraction = Arc::new(DashMap::new());
         let long_term_extraction_interval = config.memory.long_term_extraction_interval;
+        let session_idle_timeout = (config.memory.session_idle_timeout_minutes > 0)
+            .then(|| Duration::from_secs(config.memory.session_idle_timeout_minutes * 60));
+        let agent_loop = Arc::new(config.agent_loop.clone());
+        let rate_limiter = Arc::new(RateLimiter::new(
+            config.rate_limit.clone(),
+            metrics.clone(),
+            memory_store.rate_limit_store(),
+        ));
+        rate_limiter.load().await;
+        let attachment_policy = Arc::new(AttachmentPolicy::new(config.attachments.clone()));
 
         let tool_server_handle = ToolServer::new().run();
+        let delegation_tool_server_handle = config
+            .agent_loop
+            .delegation
+            .enabled
+            .then(|| ToolServer::new().run());
         on_progress(RuntimeInitProgress::new(6, "tool server initialized"));
 
+        let personas = Arc::new(
+            PersonaRegistry::load(
+                &config,
+                &conversation_models,
+                &tool_server_handle,
+                &event_bus,
+                &usage_meter,
+                memory_store.channel_persona_store(),
+            )
+            .await?,
+        );
+
         on_progress(RuntimeInitProgress::new(6, "agent runtime initialized"));
 
         info!(
@@ -207,32 +433,152 @@ impl AgentRuntime {
             "agent runtime initialized"
         );
 
-        Ok(Self {
+        let runtime = Self {
             session_manager,
             context_manager,
             memory_store,
-            conversation_model,
-            conversation_model_name,
-            conversation_model_parameters,
+            conversation_models,
+            reranker: Arc::new(SummarizerReranker::new(summarization_model.clone())),
             summarization_model,
-            summarization_model_name,
-            summarization_model_parameters,
-            extraction_tx,
+            extraction_queue,
+            extraction_notify,
             tool_server_handle,
+            delegation_tool_server_handle,
+            personas,
             summarizing,
+            session_turns,
+            active_requests,
             accumulated_conversations,
             message_since_last_extraction,
             long_term_extraction_interval,
+            session_idle_timeout,
+            agent_loop,
+            rate_limiter,
+            usage,
+            attachment_policy,
             event_bus,
             metrics,
-        })
+            shutdown,
+            extraction_drain,
+            requests,
+            background_tasks,
+        };
+
+        if let Some(handle) = &runtime.delegation_tool_server_handle {
+            runtime
+                .add_tool(DelegateTask::new(
+                    runtime.conversation_models.clone(),
+                    handle.clone(),
+                    runtime.agent_loop.delegation.clone(),
+                ))
+                .await;
+        }
+
+        Ok(runtime)
+    }
+
+    /// Starts a background job that summarizes sessions idle for longer than
+    /// `memory.session_idle_timeout_minutes` into mid-term memory and evicts them.
+    pub fn start_session_sweeper(&self) {
+        let Some(idle_timeout) = self.session_idle_timeout else {
+            info!("session expiry disabled");
+            return;
+        };
+
+        let this = self.clone();
+        self.background_tasks.spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = this.shutdown.cancelled() => break,
+                    _ = interval.tick() => this.sweep_idle_sessions(idle_timeout).await,
+                }
+            }
+        });
+
+        info!(
+            idle_timeout_secs = idle_timeout.as_secs(),
+            "started idle session sweeper"
+        );
+    }
+
+    async fn sweep_idle_sessions(&self, idle_timeout: Duration) {
+        let idle_since = Utc::now() - to_chrono(idle_timeout);
+        let idle = self.session_manager.idle_sessions(idle_since).await;
+        if idle.is_empty() {
+            return;
+        }
+
+        debug!(idle_count = idle.len(), "sweeping idle sessions");
+        for session_key in idle {
+            if let Err(error) = self.expire_session(&session_key, idle_since).await {
+                // Left in place so that the next sweep retries it.
+                warn!(
+                    session = %session_key.channel_id,
+                    error = %error,
+                    "failed to expire idle session"
+                );
+            }
+        }
+    }
+
+    async fn expire_session(
+        &self,
+        session_key: &SessionKey,
+        idle_since: DateTime<Utc>,
+    ) -> Result<()> {
+        // Held until the session is cleared, so that no submit starts on the
+        // short-term memory being promoted. A busy session is no longer idle.
+        let Ok(turn) = self
+            .session_turns
+            .entry(session_key.clone())
+            .or_default()
+            .clone()
+            .try_lock_owned()
+        else {
+            return Ok(());
+        };
+        let expired = self.expire_locked_session(session_key, idle_since).await;
+        drop(turn);
+        self.forget_turn_lock(session_key);
+
+        if expired? {
+            self.event_bus.publish(AgentEvent::MemoryPromoted {
+                session_key: session_key.clone(),
+            });
+            info!(session = %session_key.channel_id, "expired idle session");
+        }
+        Ok(())
+    }
+
+    /// Promotes and clears a session whose turn lock the caller holds. Returns
+    /// whether it was cleared.
+    async fn expire_locked_session(
+        &self,
+        session_key: &SessionKey,
+        idle_since: DateTime<Utc>,
+    ) -> Result<bool> {
+        if self.summarizing.insert(session_key.clone(), ()).is_some() {
+            return Ok(false);
+        }
+        let promoted = self
+            .promote_short_term_to_mid_term(session_key, "idle_timeout")
+            .await;
+        self.summarizing.remove(session_key);
+        promoted?;
+
+        self.session_manager
+            .clear_if_idle(session_key, idle_since)
+            .await
     }
 
     pub async fn clear_session(&self, session_key: &SessionKey) -> Result<()> {
-        let messages = self.memory_store.get_short_term_messages(session_key);
+        let messages = self.memory_store.get_short_term_messages(session_key).await;
 
-        self.memory_store.clear_short_term(session_key);
-        self.session_manager.clear(session_key)?;
+        self.memory_store.clear_short_term(session_key).await;
+        self.session_manager.clear(session_key).await?;
+        self.forget_turn_lock(session_key);
 
         // Reset long-term extraction accumulation for this session
         self.accumulated_conversations.remove(session_key);
@@ -241,8 +587,11 @@ impl AgentRuntime {
         if !messages.is_empty() {
             let this = self.clone();
             let session_key = session_key.clone();
-            tokio::spawn(async move {
-                match this.generate_mid_term_summary(&messages).await {
+            self.background_tasks.spawn(async move {
+                match this
+                    .generate_mid_term_summary(&session_key, &messages)
+                    .await
+                {
                     Ok(summary) => {
                         if let Err(error) = this
                             .memory_store
@@ -271,41 +620,431 @@ impl AgentRuntime {
     }
 
     pub async fn get_history(&self, session_key: &SessionKey) -> Result<Session> {
-        let session = self.session_manager.get(session_key)?;
+        let session = self.session_manager.get(session_key).await?;
         Ok(session.lock().await.clone())
     }
 
+    /// Answers `input`. Attachments are resolved with [`Self::attachment_policy`]:
+    /// images go to the model as image parts and text files are inlined.
+    /// Run it inside a [`CallerContext`] to fill the instruction placeholders
+    /// with its `profile`. `request_id` is what [`Self::cancel`] stops it by.
+    ///
+    /// Fails with [`RateLimited`] when the `rate_limit` budgets turn it away.
     pub async fn submit(
         &self,
+        request_id: RequestId,
+        session_key: SessionKey,
+        user_id: Option<String>,
+        input: UserInput,
+    ) -> Result<AgentResponse> {
+        // Taken before anything is awaited, so that `shutdown` waits for this request.
+        let _request = self.requests.token();
+        self.ensure_accepting()?;
+        self.check_rate_limit(&session_key, user_id.as_deref())
+            .await?;
+        self.submit_inner(request_id, session_key, user_id, input)
+            .await
+    }
+
+    async fn submit_inner(
+        &self,
+        request_id: RequestId,
         session_key: SessionKey,
         user_id: Option<String>,
-        user_input: String,
+        input: UserInput,
     ) -> Result<AgentResponse> {
-        let start = std::time::Instant::now();
+        let input = self.attachment_policy.resolve(input);
+        let user_input = input.text.clone();
+        let Some(slot) = self.begin_request(request_id, &session_key).await else {
+            return Ok(cancelled_while_queued(request_id, &session_key));
+        };
+        let start = Instant::now();
+        let prepared = self
+            .prepare_prompt(&session_key, user_id.as_deref(), &input)
+            .await;
+
+        let request = ModelRequest::new(prepared.user_message.clone())
+            .images(prepared.images.clone())
+            .preamble(prepared.system_prompt.clone())
+            .chat_history(prepared.chat_history.clone())
+            .tool_server_handle(prepared.tool_server_handle.clone())
+            .loop_budget(prepared.loop_budget.clone());
+        let timeout = prepared.loop_budget.limits().timeout;
+
+        let response = tokio::select! {
+            biased;
+            interruption = slot.interrupted(timeout) => Err(interruption),
+            response = with_caller_context(prepared.caller_context.clone(), async {
+                // A retry starts the loop over, so it would repeat tools that already ran.
+                RetryIf::spawn(
+                    model_retry_strategy(),
+                    || prepared.models.prompt(request.clone()),
+                    |e: &anyhow::Error| {
+                        loop_limit(e).is_none() && prepared.loop_budget.tool_calls() == 0
+                    },
+                )
+                .await
+            }) => Ok(response),
+        };
+
+        let response = match interrupt_on_loop_limit(response) {
+            Ok(response) => response,
+            Err(interruption) => {
+                return Ok(self
+                    .record_interrupted_turn(&session_key, user_id, &user_input, "", interruption)
+                    .await);
+            }
+        };
+
+        let result = match response {
+            Ok(r) => {
+                self.metrics.record_latency(start.elapsed());
+                info!(response_len = r.len(), "received model response");
+                r
+            }
+            Err(e) => {
+                self.event_bus.publish(AgentEvent::RequestFailed {
+                    session_key: session_key.clone(),
+                    error: format!("{}", e),
+                });
+                return Err(e);
+            }
+        };
+
+        self.finish_turn(&session_key, user_id, &user_input, &result)
+            .await;
+
+        Ok(AgentResponse {
+            content: result,
+            cancelled: false,
+        })
+    }
+
+    /// Streaming variant of [`AgentRuntime::submit`].
+    ///
+    /// Text deltas are yielded as [`ResponseStreamItem::Chunk`] and published as
+    /// `ResponseChunk` events while the model is generating. The stream ends with
+    /// a single [`ResponseStreamItem::Completed`] carrying the final answer, after
+    /// short-term memory and session history have been updated.
+    ///
+    /// Returns right away. The request waits for earlier submits of the same
+    /// session in the background and can be cancelled meanwhile. Fails with
+    /// [`RateLimited`] like [`AgentRuntime::submit`].
+    pub async fn submit_stream(
+        &self,
+        request_id: RequestId,
+        session_key: SessionKey,
+        user_id: Option<String>,
+        input: UserInput,
+    ) -> Result<ResponseStream> {
+        // Held until the request task is spawned on `requests`, which tracks it from then on.
+        let _request = self.requests.token();
+        self.ensure_accepting()?;
+        self.check_rate_limit(&session_key, user_id.as_deref())
+            .await?;
+        let input = self.attachment_policy.resolve(input);
+        let user_input = input.text.clone();
+        let caller_context = current_caller_context();
+
+        let (tx, rx) = mpsc::channel(RESPONSE_STREAM_BUFFER);
+        let this = self.clone();
+
+        // The model loop runs in its own task so that tool calls keep the caller
+        // context and the turn is recorded even if the consumer drops the stream.
+        self.requests.spawn(async move {
+            let Some(slot) = this.begin_request(request_id, &session_key).await else {
+                let response = cancelled_while_queued(request_id, &session_key);
+                let _ = tx.send(Ok(ResponseStreamItem::Completed(response))).await;
+                return;
+            };
+            let start = Instant::now();
+            let prepared = with_caller_context(
+                caller_context,
+                this.prepare_prompt(&session_key, user_id.as_deref(), &input),
+            )
+            .await;
+
+            let mut partial = String::new();
+            let timeout = prepared.loop_budget.limits().timeout;
+            let result = tokio::select! {
+                biased;
+                interruption = slot.interrupted(timeout) => Err(interruption),
+                result = with_caller_context(
+                    prepared.caller_context.clone(),
+                    this.stream_model_response(&session_key, &prepared, &tx, &mut partial),
+                ) => Ok(result),
+            };
+
+            let result = match interrupt_on_loop_limit(result) {
+                Ok(result) => result,
+                Err(interruption) => {
+                    let response = this
+                        .record_interrupted_turn(
+                            &session_key,
+                            user_id,
+                            &user_input,
+                            &partial,
+                            interruption,
+                        )
+                        .await;
+                    let _ = tx.send(Ok(ResponseStreamItem::Completed(response))).await;
+                    return;
+                }
+            };
+
+            match result {
+                Ok(content) => {
+                    this.metrics.record_latency(start.elapsed());
+                    info!(
+                        response_len = content.len(),
+                        "received streamed model response"
+                    );
+
+                    this.finish_turn(&session_key, user_id, &user_input, &content)
+                        .await;
+
+                    let _ = tx
+                        .send(Ok(ResponseStreamItem::Completed(AgentResponse {
+                            content,
+                            cancelled: false,
+                        })))
+                        .await;
+                }
+                Err(e) => {
+                    this.event_bus.publish(AgentEvent::RequestFailed {
+                        session_key: session_key.clone(),
+                        error: format!("{}", e),
+                    });
+                    let _ = tx.send(Err(e)).await;
+                }
+            }
+        });
+
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+
+    async fn stream_model_response(
+        &self,
+        session_key: &SessionKey,
+        prepared: &PreparedPrompt,
+        tx: &mpsc::Sender<Result<ResponseStreamItem>>,
+        partial: &mut String,
+    ) -> Result<String> {
+        let mut retry_strategy = model_retry_strategy();
+        let request = ModelRequest::new(prepared.user_message.clone())
+            .images(prepared.images.clone())
+            .preamble(prepared.system_prompt.clone())
+            .chat_history(prepared.chat_history.clone())
+            .tool_server_handle(prepared.tool_server_handle.clone())
+            .loop_budget(prepared.loop_budget.clone());
+
+        loop {
+            let mut emitted = false;
+            let mut failure = None;
+
+            match prepared.models.stream(request.clone()).await {
+                Ok(mut stream) => {
+                    while let Some(item) = stream.next().await {
+                        match item {
+                            Ok(ModelStreamItem::Text(text)) => {
+                                emitted = true;
+                                partial.push_str(&text);
+                                self.event_bus.publish(AgentEvent::ResponseChunk {
+                                    session_key: session_key.clone(),
+                                    chunk: text.clone(),
+                                });
+                                if tx.send(Ok(ResponseStreamItem::Chunk(text))).await.is_err() {
+                                    debug!("response stream receiver dropped");
+                                }
+                            }
+                            Ok(ModelStreamItem::Completed(response)) => return Ok(response),
+                            Err(e) => {
+                                failure = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => failure = Some(e),
+            }
+
+            let Some(error) = failure else {
+                anyhow::bail!("model stream ended without a final response");
+            };
+
+            // Once text has reached the caller a retry would duplicate output,
+            // once tools have run it would run them again, and a loop limit would
+            // only be hit again.
+            match retry_strategy.next() {
+                Some(delay)
+                    if !emitted
+                        && prepared.loop_budget.tool_calls() == 0
+                        && loop_limit(&error).is_none() =>
+                {
+                    warn!(
+                        session = %session_key.channel_id,
+                        error = %error,
+                        "model stream failed before any output, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Which attachments frontends should download before submitting them.
+    pub fn attachment_policy(&self) -> &AttachmentPolicy {
+        &self.attachment_policy
+    }
+
+    /// Admits a submit from `user_id`, counting it against the `rate_limit`
+    /// budgets. Exemptions use the role IDs of the current [`CallerContext`].
+    async fn check_rate_limit(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+    ) -> Result<(), RateLimited> {
+        let role_ids = current_caller_context().profile.role_ids;
+        self.rate_limiter
+            .check(user_id, session_key.guild_id.map(|id| id.get()), &role_ids)
+            .await
+    }
+
+    /// Stops the submit `request_id`. A running model and tool loop is aborted
+    /// and whatever was generated so far is recorded as a partial turn; a submit
+    /// still waiting for its session ends without a turn. Returns `false` when
+    /// the submit already finished or never started.
+    pub fn cancel(&self, request_id: RequestId) -> bool {
+        match self.active_requests.get(&request_id) {
+            Some(token) => {
+                info!(request_id = %request_id, "cancelling request");
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registers a cancellation token for the submit, then waits until earlier
+    /// submits of the session have finished. `None` when it was cancelled while
+    /// waiting.
+    async fn begin_request(
+        &self,
+        request_id: RequestId,
+        session_key: &SessionKey,
+    ) -> Option<RequestSlot> {
+        let token = CancellationToken::new();
+        self.active_requests.insert(request_id, token.clone());
+        let mut slot = RequestSlot {
+            request_id,
+            token,
+            active_requests: self.active_requests.clone(),
+            turn: None,
+        };
+
+        let turn_lock = self
+            .session_turns
+            .entry(session_key.clone())
+            .or_default()
+            .clone();
+        tokio::select! {
+            turn = turn_lock.lock_owned() => slot.turn = Some(turn),
+            _ = slot.token.cancelled() => return None,
+        }
+        Some(slot)
+    }
+
+    /// Records what was generated before a cancel or a loop limit stopped the
+    /// request, followed by a note saying why it stopped.
+    async fn record_interrupted_turn(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<String>,
+        user_input: &str,
+        partial: &str,
+        interruption: Interruption,
+    ) -> AgentResponse {
+        let (note, cancelled) = match &interruption {
+            Interruption::Cancelled => {
+                info!(
+                    session = %session_key.channel_id,
+                    partial_len = partial.len(),
+                    "request cancelled, recording partial turn"
+                );
+                (CANCELLED_TURN_MARKER.to_string(), true)
+            }
+            Interruption::Limit(limit) => {
+                warn!(
+                    session = %session_key.channel_id,
+                    partial_len = partial.len(),
+                    limit = %limit,
+                    "agent loop limit reached, recording partial turn"
+                );
+                (limit.explanation(), false)
+            }
+        };
+
+        let content = if partial.trim().is_empty() {
+            note
+        } else {
+            format!("{partial}\n\n{note}")
+        };
+
+        self.finish_turn(session_key, user_id, user_input, &content)
+            .await;
+        AgentResponse { content, cancelled }
+    }
+
+    async fn prepare_prompt(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        input: &ResolvedInput,
+    ) -> PreparedPrompt {
         self.metrics.record_message();
+        let user_input = input.text.as_str();
 
+        // The frontend may have resolved names for the instruction placeholders.
         let caller_context = CallerContext {
-            user_id: user_id.as_ref().and_then(|id| id.parse::<u64>().ok()),
-            guild_id: session_key.guild_id.map(|id| id.get()),
+            profile: current_caller_context().profile,
+            ..session_caller_context(session_key, user_id)
         };
 
         self.event_bus.publish(AgentEvent::MessageReceived {
             session_key: session_key.clone(),
-            content: user_input.clone(),
+            content: user_input.to_string(),
         });
 
         info!(
             session = %session_key.channel_id,
             input_len = user_input.len(),
+            image_count = input.images.len(),
             "submitting user input"
         );
+        let persona = self
+            .personas
+            .resolve(session_key, caller_context.profile.parent_channel_id);
+        if let Some(persona) = &persona {
+            debug!(persona = %persona.name, "using persona");
+        }
+
         let session = {
-            let session_arc = self.session_manager.get_or_create(&session_key);
+            let session_arc = self.session_manager.get_or_create(session_key).await;
             session_arc.lock().await.clone()
         };
         debug!(turn_count = session.turns.len(), "session loaded");
 
-        let recalled = self.memory_store.recall(&session_key, &user_input).await;
+        // Inside the caller context so that reranking is billed to the caller.
+        let recalled = with_caller_context(
+            caller_context.clone(),
+            self.memory_store.recall(
+                session_key,
+                &input.recall_query,
+                Some(self.reranker.as_ref()),
+            ),
+        )
+        .await;
 
         self.event_bus.publish(AgentEvent::MemoryRecalled {
             session_key: session_key.clone(),
@@ -313,17 +1052,29 @@ impl AgentRuntime {
             long_count: recalled.long_term.len(),
         });
 
-        let context = self
-            .context_manager
-            .build(
+        // Inside the caller context so that summarizing older turns is billed to the caller.
+        let context = with_caller_context(
+            caller_context.clone(),
+            self.context_manager.build(
                 &session,
-                &user_input,
+                user_input,
                 &recalled,
-                user_id.clone(),
-                session_key.guild_id.map(|id| id.get()),
-            )
-            .await;
-        debug!(context_turns = context.turns.len(), "context built");
+                &caller_context,
+                persona.as_ref().map(|persona| persona.name.as_str()),
+            ),
+        )
+        .await;
+        debug!(
+            context_turns = context.turns.len(),
+            token_count = context.token_count,
+            "context built"
+        );
+
+        if let Some(compaction) = context.compaction {
+            self.session_manager
+                .compact(session_key, compaction.compacted_turns, compaction.summary)
+                .await;
+        }
 
         let mut chat_history = Vec::with_capacity(context.turns.len() * 2);
         for turn in &context.turns {
@@ -337,74 +1088,60 @@ impl AgentRuntime {
             "prompt composed"
         );
 
-        let retry_strategy = ExponentialBackoff::from_millis(100)
-            .max_delay(Duration::from_secs(10))
-            .map(jitter)
-            .take(5);
-
         self.event_bus.publish(AgentEvent::ThinkingStarted {
             session_key: session_key.clone(),
         });
 
-        let cm = self.conversation_model.clone();
-        let model_name = self.conversation_model_name.clone();
-        let model_params = self.conversation_model_parameters.clone();
-        let system_prompt = context.system_prompt.clone();
-        let tool_handle = self.tool_server_handle.clone();
-        let user_message = context.user_message.clone();
-
-        let result = match with_caller_context(caller_context, async {
-            Retry::spawn(retry_strategy, || {
-                let cm = cm.clone();
-                let mn = model_name.clone();
-                let mp = model_params.clone();
-                let sp = system_prompt.clone();
-                let th = tool_handle.clone();
-                let um = user_message.clone();
-                let ch = chat_history.clone();
-                async move {
-                    let agent = cm
-                        .build_agent(mn.as_str(), mp)
-                        .preamble(sp.as_str())
-                        .tool_server_handle(th)
-                        .build();
-                    agent.prompt(&um).max_turns(20).with_history(ch).await
-                }
-            })
-            .await
-        })
-        .await
-        {
-            Ok(r) => {
-                self.metrics.record_latency(start.elapsed());
-                info!(response_len = r.len(), "received model response");
-                r
-            }
-            Err(e) => {
-                self.event_bus.publish(AgentEvent::ErrorOccurred {
-                    session_key: session_key.clone(),
-                    error: format!("{}", e),
-                });
-                return Err(e.into());
-            }
+        let loop_limits =
+            LoopLimits::for_guild(&self.agent_loop, session_key.guild_id.map(|id| id.get()));
+
+        let (models, tool_server_handle) = match &persona {
+            Some(persona) => (
+                persona.models().clone(),
+                persona.tool_server_handle().clone(),
+            ),
+            None => (
+                self.conversation_models.clone(),
+                self.tool_server_handle.clone(),
+            ),
         };
 
+        PreparedPrompt {
+            caller_context,
+            models,
+            tool_server_handle,
+            system_prompt: context.system_prompt,
+            user_message: context.user_message,
+            images: input.images.clone(),
+            chat_history,
+            loop_budget: LoopBudget::new(loop_limits),
+        }
+    }
+
+    async fn finish_turn(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<String>,
+        user_input: &str,
+        result: &str,
+    ) {
         self.event_bus.publish(AgentEvent::ResponseCompleted {
             session_key: session_key.clone(),
-            full_response: result.clone(),
+            full_response: result.to_string(),
         });
 
         self.memory_store
-            .push_short_term(&session_key, &user_input, result.as_str());
+            .push_short_term(session_key, user_id.as_deref(), user_input, result)
+            .await;
         debug!("short-term memory updated");
 
-        if self.memory_store.should_summarize(&session_key)
+        if self.memory_store.should_summarize(session_key).await
             && self.summarizing.insert(session_key.clone(), ()).is_none()
         {
             let this = self.clone();
             let key = session_key.clone();
             let eb = self.event_bus.clone();
-            tokio::spawn(async move {
+            self.background_tasks.spawn(async move {
                 if let Err(error) = this
                     .promote_short_term_to_mid_term(&key, "compression_threshold")
                     .await
@@ -420,7 +1157,7 @@ impl AgentRuntime {
         }
 
         self.session_manager
-            .append(&session_key, &user_input, result.as_str())
+            .append(session_key, user_id.as_deref(), user_input, result)
             .await;
         debug!("session history updated");
 
@@ -430,9 +1167,18 @@ impl AgentRuntime {
                 .accumulated_conversations
                 .entry(session_key.clone())
                 .or_default();
-            *acc += &format!(
-                "<user_content>{}</user_content>\n<assistant_content>{}</assistant_content>\n",
-                user_input, result
+            let speaker = match &user_id {
+                Some(user_id) => {
+                    if !acc.user_ids.contains(user_id) {
+                        acc.user_ids.push(user_id.clone());
+                    }
+                    format!(" user_id=\"{user_id}\"")
+                }
+                None => String::new(),
+            };
+            acc.conversation += &format!(
+                "<user_content{}>{}</user_content>\n<assistant_content>{}</assistant_content>\n",
+                speaker, user_input, result
             );
         }
 
@@ -451,18 +1197,17 @@ impl AgentRuntime {
             }
         };
 
-        if should_extract {
-            let conversation_batch = self
-                .accumulated_conversations
-                .remove(&session_key)
-                .map(|(_, v)| v)
-                .unwrap_or_default();
-            if !conversation_batch.is_empty() {
-                self.spawn_long_term_extraction(session_key.clone(), user_id, conversation_batch);
-            }
+        if should_extract
+            && let Some((_, pending)) = self.accumulated_conversations.remove(session_key)
+            && !pending.conversation.is_empty()
+        {
+            self.queue_long_term_extraction(
+                session_key.clone(),
+                pending.user_ids,
+                pending.conversation,
+            )
+            .await;
         }
-
-        Ok(AgentResponse { content: result })
     }
 
     async fn promote_short_term_to_mid_term(
@@ -470,7 +1215,7 @@ impl AgentRuntime {
         session_key: &SessionKey,
         trigger: &'static str,
     ) -> Result<()> {
-        let messages = self.memory_store.get_short_term_messages(session_key);
+        let messages = self.memory_store.get_short_term_messages(session_key).await;
         if messages.is_empty() {
             debug!(
                 session = %session_key.channel_id,
@@ -480,7 +1225,9 @@ impl AgentRuntime {
             return Ok(());
         }
 
-        let summary = self.generate_mid_term_summary(&messages).await?;
+        let summary = self
+            .generate_mid_term_summary(session_key, &messages)
+            .await?;
         self.memory_store
             .promote_to_mid_term(session_key, summary)
             .await
@@ -496,74 +1243,170 @@ impl AgentRuntime {
         Ok(())
     }
 
-    async fn generate_mid_term_summary(&self, messages: &[ShortTermEntry]) -> Result<String> {
+    async fn generate_mid_term_summary(
+        &self,
+        session_key: &SessionKey,
+        messages: &[ShortTermEntry],
+    ) -> Result<String> {
         let conversation = escape_xml(&format_short_term_messages(messages));
         let prompt = format!(
             "<summarization_task>\n  <instruction>\n    The following is a conversation log from the same session.\n    - Please retain the main topics, user intent, conclusions reached, and unresolved issues.\n    - Please summarize it concisely in 5-10 sentences, using the original language of the conversation.\n    - Please write in natural prose, not in bullet points.\n  </instruction>\n  <conversation_log>{}</conversation_log>\n</summarization_task>",
             conversation
         );
 
-        let retry_strategy = ExponentialBackoff::from_millis(100)
-            .max_delay(Duration::from_secs(10))
-            .map(jitter)
-            .take(5);
-
-        let sm = self.summarization_model.clone();
-        let model_name = self.summarization_model_name.clone();
-        let model_params = self.summarization_model_parameters.clone();
-
-        let summary = Retry::spawn(retry_strategy, || {
-            let sm = sm.clone();
-            let mn = model_name.clone();
-            let mp = model_params.clone();
-            let p = prompt.clone();
-            async move {
-                let summarizer = sm.build_agent(mn.as_str(), mp).build();
-                summarizer.prompt(p).await
-            }
-        })
+        let request = ModelRequest::new(prompt).purpose(UsagePurpose::Summarization);
+        let summary: Summary = with_caller_context(
+            session_caller_context(session_key, None),
+            RetryIf::spawn(
+                model_retry_strategy(),
+                || {
+                    prompt_structured(request.clone(), |request| {
+                        self.summarization_model.prompt(request)
+                    })
+                },
+                |e: &anyhow::Error| !e.is::<InvalidStructuredOutput>(),
+            ),
+        )
         .await?;
-        Ok(summary.trim().to_string())
+        Ok(summary.summary.trim().to_string())
     }
 
-    fn spawn_long_term_extraction(
+    async fn queue_long_term_extraction(
         &self,
         session_key: SessionKey,
-        user_id: Option<String>,
-        conversation_batch: String,
+        user_ids: Vec<String>,
+        conversation: String,
     ) {
-        let task = ExtractionTask {
+        let job = NewExtractionJob {
             session_key,
-            user_id,
-            conversation_batch,
+            user_ids,
+            conversation,
         };
 
-        if let Err(e) = self.extraction_tx.try_send(task) {
-            warn!(
-                error = %e,
-                "failed to queue long-term memory extraction task (queue may be full)"
-            );
+        if let Err(e) = self.extraction_queue.push(job).await {
+            warn!(error = %e, "failed to queue long-term memory extraction job");
+            return;
         }
+        update_extraction_queue_depth(self.extraction_queue.as_ref(), &self.metrics).await;
+        self.extraction_notify.notify_one();
     }
 
+    /// Registers a tool for the default profile, for every persona whose
+    /// `tools` allowlist names it, and for the `delegate_task` sub-agent when
+    /// `agent_loop.delegation.tools` names it.
     pub async fn add_tool(&self, tool: impl ToolDyn + 'static) {
         let instrumented = InstrumentedTool {
-            inner: Box::new(tool),
+            inner: Arc::new(tool),
+            event_bus: self.event_bus.clone(),
+            metrics: self.metrics.clone(),
         };
+        let name = instrumented.name();
+
+        if let Some(handle) = &self.delegation_tool_server_handle
+            && name != DelegateTask::NAME
+            && self.agent_loop.delegation.tools.contains(&name)
+            && let Err(e) = handle.add_tool(instrumented.clone()).await
+        {
+            warn!(tool = %name, error = %e, "failed to register tool for delegation");
+        }
+
+        for persona in self.personas.list() {
+            if let Some(handle) = persona.own_tool(&name)
+                && let Err(e) = handle.add_tool(instrumented.clone()).await
+            {
+                warn!(tool = %name, persona = %persona.name, error = %e, "failed to register tool for persona");
+            }
+        }
+
         if let Err(e) = self.tool_server_handle.add_tool(instrumented).await {
-            warn!(error = %e, "failed to register tool");
+            warn!(tool = %name, error = %e, "failed to register tool");
         } else {
-            info!("tool registered successfully");
+            info!(tool = %name, "tool registered successfully");
         }
     }
 
-    pub async fn shutdown(&self) {
+    /// Memory tiers, for frontends that let users inspect or remove what is remembered.
+    pub fn memory_store(&self) -> &MemoryStore {
+        &self.memory_store
+    }
+
+    /// The configured personas and the channels using them.
+    pub fn personas(&self) -> &PersonaRegistry {
+        &self.personas
+    }
+
+    /// Drops the per-session lock unless a submit is holding or waiting for it.
+    fn forget_turn_lock(&self, session_key: &SessionKey) {
+        self.session_turns
+            .remove_if(session_key, |_, lock| Arc::strong_count(lock) == 1);
+    }
+
+    fn ensure_accepting(&self) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            anyhow::bail!("agent runtime is shutting down");
+        }
+        Ok(())
+    }
+
+    /// Stops accepting submits, waits for the ones in flight, queues every
+    /// accumulated conversation for long-term extraction and then waits for the
+    /// running extraction and summarization tasks. Gives up once `timeout` has
+    /// elapsed. Extraction jobs that did not run are resumed on the next start.
+    /// The model usage recorded so far is saved last.
+    pub async fn shutdown(&self, timeout: Duration) {
         info!("shutting down agent runtime...");
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.shutdown.cancel();
 
-        drop(self.extraction_tx.clone());
+        self.requests.close();
+        if tokio::time::timeout_at(deadline, self.requests.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                in_flight = self.requests.len(),
+                "timed out waiting for in-flight requests"
+            );
+        }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        let pending: Vec<_> = self
+            .accumulated_conversations
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        let mut flushed = 0;
+        for session_key in pending {
+            if let Some((_, pending)) = self.accumulated_conversations.remove(&session_key)
+                && !pending.conversation.is_empty()
+            {
+                self.message_since_last_extraction.remove(&session_key);
+                self.queue_long_term_extraction(
+                    session_key,
+                    pending.user_ids,
+                    pending.conversation,
+                )
+                .await;
+                flushed += 1;
+            }
+        }
+        info!(
+            flushed = flushed,
+            "queued accumulated conversations for extraction"
+        );
 
+        self.extraction_drain.cancel();
+        self.background_tasks.close();
+        if tokio::time::timeout_at(deadline, self.background_tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                pending = self.background_tasks.len(),
+                "timed out waiting for background memory tasks"
+            );
+        }
+
+        self.usage.save().await;
         info!("agent runtime shutdown complete");
     }
 
@@ -596,15 +1439,20 @@ impl WebUiAgent for AgentRuntime {
         user_id: Option<String>,
         content: String,
     ) -> anyhow::Result<String> {
-        let resp = AgentRuntime::submit(self, session_key, user_id, content).await?;
+        let resp =
+            AgentRuntime::submit(self, RequestId::new(), session_key, user_id, content.into())
+                .await?;
         Ok(resp.content)
     }
 }
 
-/// Wrapper around `ToolDyn` for future instrumentation.
-/// TODO: Add `ToolCalled` / `ToolResult` events when Rig SDK supports caller-context passthrough.
+/// Wrapper around `ToolDyn` that publishes `ToolCalled` / `ToolResult` events for the
+/// session in the current `CallerContext` and records per-tool metrics.
+#[derive(Clone)]
 struct InstrumentedTool {
-    inner: Box<dyn ToolDyn>,
+    inner: Arc<dyn ToolDyn>,
+    event_bus: EventBus,
+    metrics: Metrics,
 }
 
 impl ToolDyn for InstrumentedTool {
@@ -623,10 +1471,102 @@ impl ToolDyn for InstrumentedTool {
         &'a self,
         args: String,
     ) -> Pin<Box<dyn Future<Output = Result<String, ToolError>> + Send + 'a>> {
-        self.inner.call(args)
+        Box::pin(async move {
+            let tool = self.inner.name();
+            let session_key = current_caller_context().session_key;
+
+            if let Some(session_key) = &session_key {
+                self.event_bus.publish(AgentEvent::ToolCalled {
+                    session_key: session_key.clone(),
+                    tool: tool.clone(),
+                    args: event_payload(&args),
+                });
+            }
+
+            let start = Instant::now();
+            let result = self.inner.call(args).await;
+            let elapsed = start.elapsed();
+
+            self.metrics
+                .record_tool_call(&tool, elapsed, result.is_ok());
+
+            match &result {
+                Ok(_) => {
+                    debug!(tool = %tool, elapsed_ms = elapsed.as_millis(), "tool call finished")
+                }
+                Err(e) => {
+                    warn!(tool = %tool, error = %e, elapsed_ms = elapsed.as_millis(), "tool call failed")
+                }
+            }
+
+            if let Some(session_key) = session_key {
+                let result = match &result {
+                    Ok(output) => event_payload(output),
+                    Err(e) => serde_json::json!({ "error": e.to_string() }),
+                };
+                self.event_bus.publish(AgentEvent::ToolResult {
+                    session_key,
+                    tool,
+                    result,
+                });
+            }
+
+            result
+        })
+    }
+}
+
+/// Context that model calls and tools of a session run in. Token usage is billed to it.
+fn session_caller_context(session_key: &SessionKey, user_id: Option<&str>) -> CallerContext {
+    CallerContext {
+        user_id: user_id.and_then(|id| id.parse::<u64>().ok()),
+        guild_id: session_key.guild_id.map(|id| id.get()),
+        session_key: Some(session_key.clone()),
+        ..Default::default()
+    }
+}
+
+/// Answer to a submit cancelled before its turn came. Nothing ran, so no turn
+/// is recorded.
+fn cancelled_while_queued(request_id: RequestId, session_key: &SessionKey) -> AgentResponse {
+    info!(
+        request_id = %request_id,
+        session = %session_key.channel_id,
+        "request cancelled while waiting for the session"
+    );
+    AgentResponse {
+        content: CANCELLED_TURN_MARKER.to_string(),
+        cancelled: true,
     }
 }
 
+/// A loop limit reported by the model loop stops the request the same way a
+/// timeout does.
+fn interrupt_on_loop_limit<T>(
+    result: Result<Result<T>, Interruption>,
+) -> Result<Result<T>, Interruption> {
+    match result {
+        Ok(Err(e)) => match loop_limit(&e).cloned() {
+            Some(limit) => Err(Interruption::Limit(limit)),
+            None => Ok(Err(e)),
+        },
+        result => result,
+    }
+}
+
+/// Tool arguments and outputs are JSON strings; fall back to a plain string
+/// for tools that return free text.
+fn event_payload(raw: &str) -> serde_json::Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| serde_json::Value::String(raw.to_string()))
+}
+
+pub(crate) fn model_retry_strategy() -> impl Iterator<Item = Duration> {
+    ExponentialBackoff::from_millis(100)
+        .max_delay(Duration::from_secs(10))
+        .map(jitter)
+        .take(5)
+}
+
 fn format_short_term_messages(messages: &[ShortTermEntry]) -> String {
     let mut formatted = String::new();
 
@@ -648,7 +1588,7 @@ fn role_label(role: &Role) -> &'static str {
     }
 }
 
-fn escape_xml(input: &str) -> String {
+pub(crate) fn escape_xml(input: &str) -> String {
     input
         .replace('&', "&amp;")
         .replace('<', "&lt;")
@@ -657,58 +1597,61 @@ fn escape_xml(input: &str) -> String {
         .replace('\'', "&apos;")
 }
 
-#[allow(clippy::too_many_arguments)]
 async fn extract_and_store_long_term_facts(
-    provider: Arc<OpenAICompatibleAdapter>,
+    provider: Arc<ModelChain>,
+    consolidator: Arc<SummarizerConsolidator>,
     memory_store: Arc<MemoryStore>,
-    model: String,
-    parameters: Parameters,
     session_key: SessionKey,
-    user_id: Option<String>,
+    user_ids: Vec<String>,
     conversation_batch: String,
     event_bus: EventBus,
 ) -> Result<()> {
     let prompt = format!(
-        "<long_term_extraction_task>\n  <instruction>Extract ALL important information from the following conversation in JSON format, which should be referenced in future conversations. Include user preferences, facts, decisions, and any other key information. Extract multiple distinct facts if multiple topics are discussed. Otherwise, return an empty array.</instruction>\n  <output_format>[{{\"fact\":\" ... \",\"tags\":[\" ... \"]}}]</output_format>\n  <conversation>{}</conversation>\n</long_term_extraction_task>",
+        "<long_term_extraction_task>\n  <instruction>Extract ALL important information from the following conversation in JSON format, which should be referenced in future conversations. Include user preferences, facts, decisions, and any other key information. Extract multiple distinct facts if multiple topics are discussed. Otherwise, return an empty list of facts. Rate how much each fact matters for future conversations from 1 (passing detail) to 5 (core fact such as a name, a lasting preference or an ongoing plan). Set user_id to the user_id of the user a fact is about, taken from their messages, and leave it out when the fact is about none of them.</instruction>\n  <output_format>{{\"facts\":[{{\"fact\":\" ... \",\"tags\":[\" ... \"],\"importance\":3,\"user_id\":\" ... \"}}]}}</output_format>\n  <conversation>{}</conversation>\n</long_term_extraction_task>",
         escape_xml(&conversation_batch)
     );
 
-    let retry_strategy = ExponentialBackoff::from_millis(100)
-        .max_delay(Duration::from_secs(10))
-        .map(jitter)
-        .take(5);
-
-    let facts = Retry::spawn(retry_strategy, || {
-        let provider = provider.clone();
-        let model = model.clone();
-        let parameters = parameters.clone();
-        let prompt = prompt.clone();
-        let session_key = session_key.clone();
-
-        async move {
-            let extractor = provider.build_agent(model.as_str(), parameters).build();
-            let extracted = extractor
-                .prompt(prompt)
-                .await
-                .context("failed to prompt extraction agent")?;
-
-            match parse_extracted_facts(&extracted) {
-                Ok(facts) => Ok(facts),
-                Err(e) => {
-                    warn!(
-                        session = %session_key.channel_id,
-                        error = %e,
-                        extracted_len = extracted.len(),
-                        "JSON parse failed, retrying long-term memory extraction"
-                    );
-                    Err(e)
-                }
-            }
+    // A conversation with a single user is billed to and remembered for them.
+    let sole_user = match user_ids.as_slice() {
+        [user_id] => Some(user_id.as_str()),
+        _ => None,
+    };
+    let caller_context = session_caller_context(&session_key, sole_user);
+    let request = ModelRequest::new(prompt).purpose(UsagePurpose::Extraction);
+    // Unparsable answers are already repaired by `prompt_structured`; repeating
+    // the whole request would only repeat them.
+    let extracted: ExtractedFacts = with_caller_context(
+        caller_context,
+        RetryIf::spawn(
+            model_retry_strategy(),
+            || prompt_structured(request.clone(), |request| provider.prompt(request)),
+            |e: &anyhow::Error| !e.is::<InvalidStructuredOutput>(),
+        ),
+    )
+    .await
+    .context("failed to prompt extraction agent")?;
+
+    // Facts are kept per user so that they can be listed and erased with the
+    // user; a model's answer naming someone outside the conversation is ignored.
+    let mut facts_by_user: HashMap<Option<String>, Vec<NewFact>> = HashMap::new();
+    for item in extracted.facts {
+        let fact = item.fact.trim();
+        if fact.is_empty() {
+            continue;
         }
-    })
-    .await?;
+        let owner = match sole_user {
+            Some(user_id) => Some(user_id.to_string()),
+            None => item.user_id.filter(|user_id| user_ids.contains(user_id)),
+        };
+        facts_by_user.entry(owner).or_default().push(NewFact {
+            content: fact.to_string(),
+            tags: item.tags,
+            // 1..=5 onto 0.0..=1.0
+            importance: f32::from(item.importance.clamp(1, 5) - 1) / 4.0,
+        });
+    }
 
-    if facts.is_empty() {
+    if facts_by_user.is_empty() {
         debug!(
             session = %session_key.channel_id,
             "no long-term facts were extracted"
@@ -716,19 +1659,29 @@ async fn extract_and_store_long_term_facts(
         return Ok(());
     }
 
-    let fact_count = facts.len();
+    let fact_count = facts_by_user.values().map(Vec::len).sum::<usize>();
 
-    for (fact, _tags) in &facts {
-        event_bus.publish(AgentEvent::MemoryExtracted {
-            session_key: session_key.clone(),
-            fact: fact.clone(),
-        });
-    }
+    for (user_id, facts) in facts_by_user {
+        for fact in &facts {
+            event_bus.publish(AgentEvent::MemoryExtracted {
+                session_key: session_key.clone(),
+                fact: fact.content.clone(),
+            });
+        }
 
-    memory_store
-        .extract_long_term(&session_key, user_id.as_deref(), facts)
+        // Consolidation prompts are billed to the same caller as the extraction.
+        with_caller_context(
+            session_caller_context(&session_key, sole_user),
+            memory_store.extract_long_term(
+                &session_key,
+                user_id.as_deref(),
+                facts,
+                Some(consolidator.as_ref()),
+            ),
+        )
         .await
         .context("failed to store extracted long-term facts")?;
+    }
 
     info!(
         session = %session_key.channel_id,
@@ -739,89 +1692,126 @@ async fn extract_and_store_long_term_facts(
     Ok(())
 }
 
+/// Runs due jobs from the durable extraction queue, at most
+/// `EXTRACTION_CONCURRENT_LIMIT` at a time. Jobs left in the queue when `drain`
+/// is cancelled are picked up again on the next start.
 async fn extraction_task_processor(
-    mut rx: mpsc::Receiver<ExtractionTask>,
-    provider: Arc<OpenAICompatibleAdapter>,
-    memory_store: Arc<MemoryStore>,
-    model: String,
-    parameters: Parameters,
+    worker: ExtractionWorker,
+    notify: Arc<Notify>,
     semaphore: Arc<Semaphore>,
-    event_bus: EventBus,
+    drain: CancellationToken,
+    tasks: TaskTracker,
 ) {
-    while let Some(task) = rx.recv().await {
-        let provider = provider.clone();
-        let memory_store = memory_store.clone();
-        let model = model.clone();
-        let parameters = parameters.clone();
-        let sem = semaphore.clone();
-        let eb = event_bus.clone();
-
-        tokio::spawn(async move {
-            let _permit = match sem.acquire().await {
-                Ok(permit) => permit,
-                Err(e) => {
-                    warn!(error = %e, "failed to acquire semaphore permit");
-                    return;
-                }
-            };
+    update_extraction_queue_depth(worker.queue.as_ref(), &worker.metrics).await;
 
-            if let Err(error) = extract_and_store_long_term_facts(
-                provider,
-                memory_store,
-                model,
-                parameters,
-                task.session_key,
-                task.user_id,
-                task.conversation_batch,
-                eb,
-            )
-            .await
-            {
-                warn!(error = %error, "failed to extract long-term memory facts");
+    loop {
+        let permit = tokio::select! {
+            permit = semaphore.clone().acquire_owned() => permit,
+            _ = drain.cancelled() => break,
+        };
+        let Ok(permit) = permit else {
+            break;
+        };
+
+        let lease_until = Utc::now() + to_chrono(EXTRACTION_LEASE);
+        let job = match worker.queue.claim_next(lease_until).await {
+            Ok(job) => job,
+            Err(e) => {
+                warn!(error = %e, "failed to read long-term extraction queue");
+                None
+            }
+        };
+
+        let Some(job) = job else {
+            drop(permit);
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::sleep(EXTRACTION_POLL_INTERVAL) => {}
+                _ = drain.cancelled() => break,
             }
+            continue;
+        };
+
+        let worker = worker.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            run_extraction_job(worker, job).await;
         });
     }
 
     info!("extraction task processor stopped");
 }
 
-fn parse_extracted_facts(raw: &str) -> Result<Vec<(String, Vec<String>)>> {
-    parse_extracted_facts_json(raw)
-        .or_else(|| {
-            let trimmed = raw.trim();
-            let start = trimmed.find('[')?;
-            let end = trimmed.rfind(']')?;
-
-            if end < start {
-                return None;
+async fn run_extraction_job(worker: ExtractionWorker, job: ExtractionJob) {
+    let result = extract_and_store_long_term_facts(
+        worker.provider.clone(),
+        worker.consolidator.clone(),
+        worker.memory_store.clone(),
+        job.session_key.clone(),
+        job.user_ids.clone(),
+        job.conversation.clone(),
+        worker.event_bus.clone(),
+    )
+    .await;
+
+    let queue_result = match result {
+        Ok(()) => worker.queue.complete(job.id).await,
+        Err(error) => {
+            worker.metrics.record_extraction_failure();
+            let attempts = job.attempts + 1;
+
+            if attempts >= EXTRACTION_MAX_ATTEMPTS {
+                warn!(
+                    session = %job.session_key.channel_id,
+                    job_id = job.id,
+                    attempts = attempts,
+                    error = %error,
+                    "giving up on long-term memory extraction job"
+                );
+                worker.queue.complete(job.id).await
+            } else {
+                let delay = extraction_retry_delay(attempts);
+                warn!(
+                    session = %job.session_key.channel_id,
+                    job_id = job.id,
+                    attempts = attempts,
+                    retry_in_secs = delay.as_secs(),
+                    error = %error,
+                    "failed to extract long-term memory facts, will retry"
+                );
+                worker
+                    .queue
+                    .reschedule(
+                        job.id,
+                        attempts,
+                        Utc::now() + to_chrono(delay),
+                        &format!("{error:#}"),
+                    )
+                    .await
             }
+        }
+    };
 
-            parse_extracted_facts_json(&trimmed[start ..= end])
-        })
-        .ok_or_else(|| anyhow::anyhow!("failed to parse extracted facts JSON"))
+    if let Err(e) = queue_result {
+        warn!(job_id = job.id, error = %e, "failed to update long-term extraction queue");
+    }
+    update_extraction_queue_depth(worker.queue.as_ref(), &worker.metrics).await;
 }
 
-fn parse_extracted_facts_json(candidate: &str) -> Option<Vec<(String, Vec<String>)>> {
-    let parsed: Vec<ExtractedFact> = serde_json::from_str(candidate)
-        .map_err(|e| {
-            warn!(
-                error = %e,
-                candidate = candidate,
-                "failed to parse extracted facts JSON"
-            );
-        })
-        .ok()?;
-    let facts = parsed
-        .into_iter()
-        .filter_map(|item| {
-            let fact = item.fact.trim();
-            if fact.is_empty() {
-                return None;
-            }
+/// Exponential backoff: 30s, 1m, 2m, ... capped at one hour.
+fn extraction_retry_delay(attempts: u32) -> Duration {
+    EXTRACTION_RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(EXTRACTION_RETRY_MAX_DELAY)
+}
 
-            Some((fact.to_string(), item.tags))
-        })
-        .collect();
+async fn update_extraction_queue_depth(queue: &dyn ExtractionQueue, metrics: &Metrics) {
+    match queue.depth().await {
+        Ok(depth) => metrics.set_extraction_queue_depth(depth as u64),
+        Err(e) => warn!(error = %e, "failed to read long-term extraction queue depth"),
+    }
+}
 
-    Some(facts)
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX)
 }
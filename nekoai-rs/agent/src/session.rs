@@ -4,14 +4,19 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use nekoai_domain::agent::session::SessionKey;
+use nekoai_memory::persistence::{
+    ConversationStore, StoredSession, StoredTurn, inmemory::InMemoryConversationStore,
+};
 use rig::completion::Message;
 use tokio::sync::Mutex;
-use tracing::debug;
+use tracing::{debug, warn};
 
 #[derive(Clone)]
 pub struct ConversationTurn {
     pub user: String,
     pub assistant: String,
+    /// The user who took the turn, so that it can be exported or erased.
+    pub user_id: Option<String>,
 }
 
 #[derive(Clone)]
@@ -22,10 +27,76 @@ pub struct Session {
     pub created_at: DateTime<Utc>,
     pub last_active: DateTime<Utc>,
     pub token_count: usize,
+    /// Running summary of turns that were compacted out of `turns`.
+    pub summary: Option<String>,
+}
+
+impl Session {
+    fn new(key: SessionKey) -> Self {
+        let now = Utc::now();
+        Self {
+            key,
+            messages: VecDeque::new(),
+            turns: VecDeque::new(),
+            created_at: now,
+            last_active: now,
+            token_count: 0,
+            summary: None,
+        }
+    }
+
+    fn from_stored(key: SessionKey, stored: StoredSession) -> Self {
+        let turns: VecDeque<_> = stored
+            .turns
+            .into_iter()
+            .map(|turn| ConversationTurn {
+                user: turn.user,
+                assistant: turn.assistant,
+                user_id: turn.user_id,
+            })
+            .collect();
+        let messages = turns
+            .iter()
+            .flat_map(|turn| {
+                [
+                    Message::user(turn.user.as_str()),
+                    Message::assistant(turn.assistant.as_str()),
+                ]
+            })
+            .collect();
+
+        Self {
+            key,
+            messages,
+            turns,
+            created_at: stored.created_at,
+            last_active: stored.last_active,
+            token_count: 0,
+            summary: stored.summary,
+        }
+    }
+
+    fn to_stored(&self) -> StoredSession {
+        StoredSession {
+            turns: self
+                .turns
+                .iter()
+                .map(|turn| StoredTurn {
+                    user: turn.user.clone(),
+                    assistant: turn.assistant.clone(),
+                    user_id: turn.user_id.clone(),
+                })
+                .collect(),
+            summary: self.summary.clone(),
+            created_at: self.created_at,
+            last_active: self.last_active,
+        }
+    }
 }
 
 pub struct SessionManager {
     sessions: DashMap<SessionKey, Arc<Mutex<Session>>>,
+    store: Arc<dyn ConversationStore>,
     max_messages: usize,
 }
 
@@ -36,21 +107,34 @@ impl Default for SessionManager {
 }
 
 impl SessionManager {
+    /// Sessions are kept in memory only and lost on restart.
     pub fn new() -> Self {
+        Self::with_store(Arc::new(InMemoryConversationStore::new()))
+    }
+
+    pub fn with_store(store: Arc<dyn ConversationStore>) -> Self {
         Self {
             sessions: DashMap::new(),
+            store,
             max_messages: 40,
         }
     }
 
-    pub async fn append(&self, session_key: &SessionKey, user: &str, assistant: &str) {
+    pub async fn append(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        user: &str,
+        assistant: &str,
+    ) {
         let max_messages = self.max_messages;
-        let session_arc = self.get_or_create(session_key);
+        let session_arc = self.get_or_create(session_key).await;
         let mut session = session_arc.lock().await;
 
         session.turns.push_back(ConversationTurn {
             user: user.to_string(),
             assistant: assistant.to_string(),
+            user_id: user_id.map(ToOwned::to_owned),
         });
         session.messages.push_back(Message::user(user));
         session.messages.push_back(Message::assistant(assistant));
@@ -71,35 +155,105 @@ impl SessionManager {
             turn_count = session.turns.len(),
             "session updated"
         );
+
+        self.persist(&session).await;
     }
 
-    pub fn clear(&self, session_key: &SessionKey) -> Result<()> {
+    /// Replaces the oldest `compacted_turns` turns with `summary`.
+    pub async fn compact(&self, session_key: &SessionKey, compacted_turns: usize, summary: String) {
+        let session_arc = self.get_or_create(session_key).await;
+        let mut session = session_arc.lock().await;
+
+        let compacted_turns = compacted_turns.min(session.turns.len());
+        session.turns.drain(.. compacted_turns);
+        let compacted_messages = (compacted_turns * 2).min(session.messages.len());
+        session.messages.drain(.. compacted_messages);
+        session.summary = Some(summary);
+
+        debug!(
+            session = %session_key.channel_id,
+            compacted_turns = compacted_turns,
+            turn_count = session.turns.len(),
+            "session compacted"
+        );
+
+        self.persist(&session).await;
+    }
+
+    pub async fn clear(&self, session_key: &SessionKey) -> Result<()> {
         if self.sessions.remove(session_key).is_some() {
             debug!(session = %session_key.channel_id, "session cleared");
         } else {
             debug!(target_session = %session_key.channel_id, "non-existent session");
         }
-        Ok(())
+        self.store.delete_session(session_key).await
     }
 
-    pub fn get_or_create(&self, session_key: &SessionKey) -> Arc<Mutex<Session>> {
+    /// Returns the cached session, loading it from the store the first time it is used.
+    pub async fn get_or_create(&self, session_key: &SessionKey) -> Arc<Mutex<Session>> {
+        if let Some(session) = self.sessions.get(session_key) {
+            return session.value().clone();
+        }
+
+        let session = match self.load(session_key).await {
+            Some(session) => session,
+            None => {
+                debug!(session = %session_key.channel_id, "created new session");
+                Session::new(session_key.clone())
+            }
+        };
+
         self.sessions
             .entry(session_key.clone())
-            .or_insert_with(|| {
-                debug!(session = %session_key.channel_id, "created new session");
-                let now = Utc::now();
-                Arc::new(Mutex::new(Session {
-                    key: session_key.clone(),
-                    messages: VecDeque::new(),
-                    turns: VecDeque::new(),
-                    created_at: now,
-                    last_active: now,
-                    token_count: 0,
-                }))
-            })
+            .or_insert_with(|| Arc::new(Mutex::new(session)))
             .clone()
     }
 
+    /// Sessions with no activity since `idle_since`, both cached and persisted.
+    /// Sessions that are currently locked are in use and skipped.
+    pub async fn idle_sessions(&self, idle_since: DateTime<Utc>) -> Vec<SessionKey> {
+        let mut idle: Vec<_> = self
+            .sessions
+            .iter()
+            .filter(|entry| {
+                entry
+                    .value()
+                    .try_lock()
+                    .is_ok_and(|session| session.last_active < idle_since)
+            })
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        match self.store.list_idle_sessions(idle_since).await {
+            Ok(stored) => idle.extend(
+                stored
+                    .into_iter()
+                    .filter(|key| !self.sessions.contains_key(key)),
+            ),
+            Err(e) => warn!(error = %e, "failed to list persisted idle sessions"),
+        }
+
+        idle
+    }
+
+    /// Like [`Self::clear`], but keeps the session if it received a turn after `idle_since`.
+    pub async fn clear_if_idle(
+        &self,
+        session_key: &SessionKey,
+        idle_since: DateTime<Utc>,
+    ) -> Result<bool> {
+        let session_arc = self.get_or_create(session_key).await;
+        let session = session_arc.lock().await;
+        if session.last_active >= idle_since {
+            return Ok(false);
+        }
+
+        self.sessions.remove(session_key);
+        self.store.delete_session(session_key).await?;
+        debug!(session = %session_key.channel_id, "idle session evicted");
+        Ok(true)
+    }
+
     pub fn all_keys(&self) -> Vec<SessionKey> {
         self.sessions
             .iter()
@@ -107,16 +261,49 @@ impl SessionManager {
             .collect()
     }
 
-    pub fn get(&self, session_key: &SessionKey) -> Result<Arc<Mutex<Session>>> {
-        self.sessions
-            .get(session_key)
-            .map(|entry| {
-                debug!(session = %session_key.channel_id, "found existing session");
-                entry.value().clone()
-            })
-            .ok_or_else(|| {
-                debug!(target_session = %session_key.channel_id, "non-existent session");
-                anyhow::anyhow!("session not found")
-            })
+    pub async fn get(&self, session_key: &SessionKey) -> Result<Arc<Mutex<Session>>> {
+        if let Some(entry) = self.sessions.get(session_key) {
+            debug!(session = %session_key.channel_id, "found existing session");
+            return Ok(entry.value().clone());
+        }
+
+        let Some(session) = self.load(session_key).await else {
+            debug!(target_session = %session_key.channel_id, "non-existent session");
+            anyhow::bail!("session not found");
+        };
+
+        Ok(self
+            .sessions
+            .entry(session_key.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(session)))
+            .clone())
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Option<Session> {
+        match self.store.load_session(session_key).await {
+            Ok(stored) => stored.map(|stored| {
+                debug!(
+                    session = %session_key.channel_id,
+                    turn_count = stored.turns.len(),
+                    "loaded persisted session"
+                );
+                Session::from_stored(session_key.clone(), stored)
+            }),
+            Err(e) => {
+                warn!(session = %session_key.channel_id, error = %e, "failed to load persisted session");
+                None
+            }
+        }
+    }
+
+    /// Called with the session lock held so that writes land in order.
+    async fn persist(&self, session: &Session) {
+        if let Err(e) = self
+            .store
+            .save_session(&session.key, &session.to_stored())
+            .await
+        {
+            warn!(session = %session.key.channel_id, error = %e, "failed to persist session");
+        }
     }
 }
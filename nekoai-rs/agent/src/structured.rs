@@ -0,0 +1,122 @@
+use std::{fmt, future::Future};
+
+use anyhow::Result;
+use rig::completion::Message;
+use schemars::{JsonSchema, schema_for};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::provider::ModelRequest;
+
+/// How many times an unparsable answer is sent back to the model to be fixed.
+const MAX_REPAIR_ATTEMPTS: usize = 2;
+
+/// The model kept answering with JSON that does not match the requested type.
+/// Retrying the same request is unlikely to help, so callers should not.
+#[derive(Debug, Clone)]
+pub struct InvalidStructuredOutput {
+    pub type_name: String,
+    pub error: String,
+}
+
+impl fmt::Display for InvalidStructuredOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "model answer is not a valid {} after {MAX_REPAIR_ATTEMPTS} repair attempts: {}",
+            self.type_name, self.error
+        )
+    }
+}
+
+impl std::error::Error for InvalidStructuredOutput {}
+
+/// Prompts for a JSON value of type `T` and returns it parsed.
+///
+/// The schema of `T` is attached to the request as the provider's response
+/// format and spelled out in the prompt for models that ignore it. Answers are
+/// parsed leniently (code fences and surrounding prose are dropped); one that
+/// still does not parse is sent back with the error for the model to correct.
+/// `prompt` makes the actual call, so any provider or model chain can be used.
+pub async fn prompt_structured<T, F, Fut>(request: ModelRequest, mut prompt: F) -> Result<T>
+where
+    T: DeserializeOwned + JsonSchema,
+    F: FnMut(ModelRequest) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let schema = schema_for!(T);
+    let mut request = ModelRequest {
+        prompt: format!(
+            "{}\n\nRespond with a single JSON value, without code fences or any other text, that matches this JSON schema:\n{}",
+            request.prompt,
+            serde_json::to_string(&schema)?
+        ),
+        ..request
+    }
+    .output_schema(schema);
+
+    let mut repair_attempts = 0;
+    loop {
+        let answer = prompt(request.clone()).await?;
+        let error = match parse_structured::<T>(&answer) {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        if repair_attempts == MAX_REPAIR_ATTEMPTS {
+            return Err(InvalidStructuredOutput {
+                type_name: T::schema_name().into_owned(),
+                error: error.to_string(),
+            }
+            .into());
+        }
+        repair_attempts += 1;
+
+        warn!(
+            error = %error,
+            answer_len = answer.len(),
+            repair_attempt = repair_attempts,
+            "structured answer did not parse, asking the model to correct it"
+        );
+        request.chat_history.push(Message::user(&request.prompt));
+        request.chat_history.push(Message::assistant(&answer));
+        request.prompt = format!(
+            "That answer could not be parsed: {error}. Reply again with only the corrected JSON value, matching the schema."
+        );
+        request.images.clear();
+    }
+}
+
+/// Parses a model answer as `T`, accepting a value wrapped in a code fence or
+/// surrounded by prose. The error is the one of the answer as a whole.
+pub fn parse_structured<T: DeserializeOwned>(answer: &str) -> serde_json::Result<T> {
+    let trimmed = answer.trim();
+    let error = match serde_json::from_str(trimmed) {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+
+    [strip_code_fence(trimmed), outermost_json(trimmed)]
+        .into_iter()
+        .flatten()
+        .find_map(|candidate| serde_json::from_str(candidate).ok())
+        .ok_or(error)
+}
+
+fn strip_code_fence(answer: &str) -> Option<&str> {
+    let body = answer.strip_prefix("```")?.strip_suffix("```")?;
+    // Drop the language tag, e.g. "```json".
+    let (_, body) = body.split_once('\n')?;
+    Some(body.trim())
+}
+
+fn outermost_json(answer: &str) -> Option<&str> {
+    let start = answer.find(['{', '['])?;
+    let close = if answer[start ..].starts_with('{') {
+        '}'
+    } else {
+        ']'
+    };
+    let end = answer.rfind(close)?;
+    (end > start).then(|| &answer[start ..= end])
+}
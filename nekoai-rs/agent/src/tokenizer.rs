@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use nekoai_config::loader::TokenizerKind;
+use tiktoken_rs::CoreBPE;
+use tracing::warn;
+
+/// Counts tokens for prompt budgeting.
+pub trait Tokenizer: Send + Sync {
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// Builds the tokenizer selected in config, falling back to the heuristic one
+/// when the BPE tables cannot be loaded.
+pub fn build_tokenizer(kind: TokenizerKind) -> Arc<dyn Tokenizer> {
+    let bpe = match kind {
+        TokenizerKind::O200kBase => tiktoken_rs::o200k_base(),
+        TokenizerKind::Cl100kBase => tiktoken_rs::cl100k_base(),
+        TokenizerKind::Heuristic => return Arc::new(HeuristicTokenizer),
+    };
+
+    match bpe {
+        Ok(bpe) => Arc::new(BpeTokenizer { bpe }),
+        Err(e) => {
+            warn!(error = %e, "failed to load BPE tokenizer, using heuristic token counts");
+            Arc::new(HeuristicTokenizer)
+        }
+    }
+}
+
+pub struct BpeTokenizer {
+    bpe: CoreBPE,
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+/// Roughly 4 characters per token for ASCII text and one token per character
+/// for everything else (CJK text is usually close to that).
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+            if c.is_ascii() {
+                (ascii + 1, other)
+            } else {
+                (ascii, other + 1)
+            }
+        });
+        ascii.div_ceil(4) + other
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{Days, NaiveDate, Utc};
+use nekoai_config::loader::ModelPrice;
+use nekoai_domain::agent::runtime::current_caller_context;
+use nekoai_infra::{
+    metrics::Metrics,
+    usage::{ModelUsage, UsagePurpose, UsageStore},
+};
+use rig::completion::Usage;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How often the usage recorded since the last save is written to the store.
+const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Prices the token usage reported by providers and records it in [`Metrics`].
+///
+/// The call is billed to the user, guild and session of the current
+/// `CallerContext`, so background work has to run inside one to be attributed.
+#[derive(Clone)]
+pub struct UsageMeter {
+    metrics: Metrics,
+    prices: Arc<HashMap<String, ModelPrice>>,
+}
+
+impl UsageMeter {
+    pub fn new(metrics: Metrics, prices: &[ModelPrice]) -> Self {
+        Self {
+            metrics,
+            prices: Arc::new(
+                prices
+                    .iter()
+                    .map(|price| (price.model.clone(), price.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub(crate) fn record(&self, model: &str, purpose: UsagePurpose, usage: Usage) {
+        let caller = current_caller_context();
+        let cost = self.cost(model, &usage);
+
+        debug!(
+            model = %model,
+            purpose = purpose.as_str(),
+            input_tokens = usage.input_tokens,
+            output_tokens = usage.output_tokens,
+            cost = cost,
+            "model usage recorded"
+        );
+
+        self.metrics.record_model_usage(ModelUsage {
+            model: model.to_string(),
+            purpose,
+            user_id: caller.user_id,
+            guild_id: caller.guild_id,
+            session_key: caller.session_key,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cached_input_tokens: usage.cached_input_tokens,
+            cost,
+        });
+    }
+
+    fn cost(&self, model: &str, usage: &Usage) -> f64 {
+        let Some(price) = self.prices.get(model) else {
+            return 0.0;
+        };
+
+        let cached = usage.cached_input_tokens.min(usage.input_tokens);
+        let uncached = usage.input_tokens - cached;
+        let cached_price = price
+            .cached_input_per_million
+            .unwrap_or(price.input_per_million);
+
+        (uncached as f64 * price.input_per_million
+            + cached as f64 * cached_price
+            + usage.output_tokens as f64 * price.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Keeps the usage totals of [`Metrics`] in a [`UsageStore`] across restarts.
+/// Days older than `retention_days` are dropped from both.
+#[derive(Clone)]
+pub struct UsagePersistence {
+    metrics: Metrics,
+    store: Arc<dyn UsageStore>,
+    retention_days: u32,
+}
+
+impl UsagePersistence {
+    pub fn new(metrics: Metrics, store: Arc<dyn UsageStore>, retention_days: u32) -> Self {
+        Self {
+            metrics,
+            store,
+            retention_days,
+        }
+    }
+
+    /// Loads the stored totals of the days still kept. Failures are logged and
+    /// leave the ledger starting from zero.
+    pub async fn load(&self) {
+        let since = self.first_kept_day().unwrap_or(NaiveDate::MIN);
+        match self
+            .metrics
+            .usage_ledger()
+            .load(self.store.as_ref(), since)
+            .await
+        {
+            Ok(loaded) => info!(loaded = loaded, "loaded stored model usage"),
+            Err(e) => warn!(error = %e, "failed to load stored model usage"),
+        }
+    }
+
+    /// Saves the usage recorded since the last save. On failure it is retried
+    /// with the next save.
+    pub async fn save(&self) {
+        match self.metrics.usage_ledger().save(self.store.as_ref()).await {
+            Ok(saved) if saved > 0 => debug!(saved = saved, "saved model usage"),
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "failed to save model usage"),
+        }
+    }
+
+    /// Saves every `USAGE_SAVE_INTERVAL` and drops expired days once a day,
+    /// until `stop` is cancelled. The final save is left to the caller, after
+    /// the last model calls have finished.
+    pub async fn run(self, stop: CancellationToken) {
+        let mut interval = tokio::time::interval(USAGE_SAVE_INTERVAL);
+        let mut pruned_on = None;
+
+        loop {
+            tokio::select! {
+                _ = stop.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            self.save().await;
+
+            let today = Utc::now().date_naive();
+            if pruned_on != Some(today) {
+                pruned_on = Some(today);
+                self.prune().await;
+            }
+        }
+    }
+
+    async fn prune(&self) {
+        let Some(first_kept_day) = self.first_kept_day() else {
+            return;
+        };
+        match self
+            .metrics
+            .usage_ledger()
+            .prune(self.store.as_ref(), first_kept_day)
+            .await
+        {
+            Ok(deleted) if deleted > 0 => {
+                info!(deleted = deleted, before = %first_kept_day, "deleted expired model usage")
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "failed to delete expired model usage"),
+        }
+    }
+
+    /// `None` when usage is kept forever.
+    fn first_kept_day(&self) -> Option<NaiveDate> {
+        if self.retention_days == 0 {
+            return None;
+        }
+        let today = Utc::now().date_naive();
+        today.checked_sub_days(Days::new(u64::from(self.retention_days) - 1))
+    }
+}
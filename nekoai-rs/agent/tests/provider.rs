@@ -0,0 +1,268 @@
+//! Runs every `ProviderKind` against a local stand-in of its API and checks the
+//! request that reaches it and the usage recorded from its answer.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{Json, Router, body::Bytes, http::Uri};
+use nekoai_agent::{
+    provider::{ModelRequest, build_provider},
+    usage::UsageMeter,
+};
+use nekoai_config::loader::{Parameters, ProviderKind};
+use nekoai_infra::{metrics::Metrics, usage::UsageQuery};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+const MODEL: &str = "test-model";
+const PREAMBLE: &str = "Answer briefly.";
+const PROMPT: &str = "What is the capital of France?";
+const ANSWER: &str = "Paris.";
+
+/// Answers every request with `response` and keeps the path and JSON body.
+struct StandIn {
+    base_url: String,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl StandIn {
+    async fn start(response: Value) -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = Router::new().fallback(move |uri: Uri, body: Bytes| {
+            let recorded = recorded.clone();
+            let response = response.clone();
+            async move {
+                let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((uri.path().to_string(), body));
+                Json(response)
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { base_url, requests }
+    }
+
+    fn take_request(&self) -> (String, Value) {
+        let mut requests = self.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1, "expected exactly one request");
+        requests.remove(0)
+    }
+}
+
+fn parameters(top_p: Option<f64>) -> Parameters {
+    Parameters {
+        max_token: 256,
+        temperature: 0.5,
+        top_p,
+        structured_output: true,
+    }
+}
+
+/// Prompts `kind` at `base_url` once and returns the answer and the metrics the
+/// usage was recorded in.
+async fn prompt(kind: ProviderKind, base_url: &str, top_p: Option<f64>) -> (String, Metrics) {
+    let metrics = Metrics::new();
+    let provider = build_provider(
+        kind,
+        base_url,
+        "test-key",
+        MODEL,
+        parameters(top_p),
+        UsageMeter::new(metrics.clone(), &[]),
+    )
+    .unwrap();
+
+    let answer = provider
+        .prompt(ModelRequest::new(PROMPT).preamble(PREAMBLE))
+        .await
+        .unwrap();
+    (answer, metrics)
+}
+
+/// Token counts recorded for `MODEL`: calls, input, output and cached input.
+fn recorded_usage(metrics: &Metrics) -> (u64, u64, u64, u64) {
+    let usage = metrics.usage(&UsageQuery::default());
+    assert_eq!(usage.len(), 1, "expected usage of a single model");
+    let summary = &usage[0];
+    assert_eq!(summary.key, MODEL);
+    (
+        summary.calls,
+        summary.input_tokens,
+        summary.output_tokens,
+        summary.cached_input_tokens,
+    )
+}
+
+#[tokio::test]
+async fn openai_compatible_sends_chat_completion_and_records_usage() {
+    let stand_in = StandIn::start(json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "created": 0,
+        "model": MODEL,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": ANSWER },
+            "finish_reason": "stop"
+        }],
+        "usage": {
+            "prompt_tokens": 12,
+            "completion_tokens": 5,
+            "total_tokens": 17,
+            "prompt_tokens_details": { "cached_tokens": 2 }
+        }
+    }))
+    .await;
+
+    let (answer, metrics) = prompt(
+        ProviderKind::OpenAICompatible,
+        &stand_in.base_url,
+        Some(0.5),
+    )
+    .await;
+    assert_eq!(answer, ANSWER);
+    assert_eq!(recorded_usage(&metrics), (1, 12, 5, 2));
+
+    let (path, body) = stand_in.take_request();
+    assert_eq!(path, "/chat/completions");
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(body["temperature"], 0.5);
+    assert_eq!(body["top_p"], 0.5);
+    assert_eq!(body["messages"][0]["role"], "system");
+    assert!(
+        body["messages"][0]["content"]
+            .to_string()
+            .contains(PREAMBLE)
+    );
+    let user = &body["messages"][1];
+    assert_eq!(user["role"], "user");
+    assert!(user["content"].to_string().contains(PROMPT));
+
+    prompt(ProviderKind::OpenAICompatible, &stand_in.base_url, None).await;
+    let (_, body) = stand_in.take_request();
+    assert!(body.get("top_p").is_none(), "top_p sent without being set");
+}
+
+#[tokio::test]
+async fn openai_responses_sends_response_request_and_records_usage() {
+    let stand_in = StandIn::start(json!({
+        "id": "resp_1",
+        "object": "response",
+        "created_at": 0,
+        "status": "completed",
+        "error": null,
+        "incomplete_details": null,
+        "instructions": PREAMBLE,
+        "max_output_tokens": 256,
+        "model": MODEL,
+        "usage": {
+            "input_tokens": 12,
+            "input_tokens_details": { "cached_tokens": 2 },
+            "output_tokens": 5,
+            "output_tokens_details": { "reasoning_tokens": 0 },
+            "total_tokens": 17
+        },
+        "output": [{
+            "type": "message",
+            "id": "msg_1",
+            "role": "assistant",
+            "status": "completed",
+            "content": [{ "type": "output_text", "text": ANSWER, "annotations": [] }]
+        }],
+        "tools": []
+    }))
+    .await;
+
+    let (answer, metrics) =
+        prompt(ProviderKind::OpenAIResponses, &stand_in.base_url, Some(0.5)).await;
+    assert_eq!(answer, ANSWER);
+    assert_eq!(recorded_usage(&metrics), (1, 12, 5, 2));
+
+    let (path, body) = stand_in.take_request();
+    assert_eq!(path, "/responses");
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(body["top_p"], 0.5);
+    assert!(body.to_string().contains(PREAMBLE));
+    assert!(body["input"].to_string().contains(PROMPT));
+
+    prompt(ProviderKind::OpenAIResponses, &stand_in.base_url, None).await;
+    let (_, body) = stand_in.take_request();
+    assert!(body.get("top_p").is_none(), "top_p sent without being set");
+}
+
+#[tokio::test]
+async fn anthropic_sends_messages_request_and_records_usage() {
+    let stand_in = StandIn::start(json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": MODEL,
+        "content": [{ "type": "text", "text": ANSWER }],
+        "stop_reason": "end_turn",
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": 12,
+            "cache_read_input_tokens": 2,
+            "cache_creation_input_tokens": 0,
+            "output_tokens": 5
+        }
+    }))
+    .await;
+
+    let (answer, metrics) = prompt(ProviderKind::Anthropic, &stand_in.base_url, Some(0.5)).await;
+    assert_eq!(answer, ANSWER);
+    assert_eq!(recorded_usage(&metrics), (1, 12, 5, 2));
+
+    let (path, body) = stand_in.take_request();
+    assert_eq!(path, "/v1/messages");
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(body["max_tokens"], 256);
+    assert_eq!(body["top_p"], 0.5);
+    assert!(body["system"].to_string().contains(PREAMBLE));
+    assert_eq!(body["messages"][0]["role"], "user");
+    assert!(body["messages"][0]["content"].to_string().contains(PROMPT));
+
+    prompt(ProviderKind::Anthropic, &stand_in.base_url, None).await;
+    let (_, body) = stand_in.take_request();
+    assert!(body.get("top_p").is_none(), "top_p sent without being set");
+}
+
+#[tokio::test]
+async fn ollama_sends_chat_request_and_records_usage() {
+    let stand_in = StandIn::start(json!({
+        "model": MODEL,
+        "created_at": "2026-01-01T00:00:00Z",
+        "message": { "role": "assistant", "content": ANSWER },
+        "done": true,
+        "done_reason": "stop",
+        "prompt_eval_count": 12,
+        "eval_count": 5
+    }))
+    .await;
+
+    let (answer, metrics) = prompt(ProviderKind::Ollama, &stand_in.base_url, Some(0.5)).await;
+    assert_eq!(answer, ANSWER);
+    assert_eq!(recorded_usage(&metrics), (1, 12, 5, 0));
+
+    let (path, body) = stand_in.take_request();
+    assert_eq!(path, "/api/chat");
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(body["options"]["top_p"], 0.5);
+    assert_eq!(body["messages"][0]["role"], "system");
+    assert_eq!(body["messages"][0]["content"], PREAMBLE);
+    assert_eq!(body["messages"][1]["role"], "user");
+    assert_eq!(body["messages"][1]["content"], PROMPT);
+
+    prompt(ProviderKind::Ollama, &stand_in.base_url, None).await;
+    let (_, body) = stand_in.take_request();
+    assert!(
+        body["options"].get("top_p").is_none(),
+        "top_p sent without being set"
+    );
+}
@@ -1 +1,2 @@
 pub mod start;
+pub mod user_data;
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use clap::{ArgMatches, Command};
+use colored::Colorize;
+use nekoai_config::loader::{Config, PersistenceBackend};
+use nekoai_infra::logging::init_tracing;
+use nekoai_memory::store::MemoryStore;
+use tracing::info;
+
+pub fn command() -> Command {
+    Command::new("user-data")
+        .about("Export or erase what NekoAI stores about a user")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("export")
+                .about("Write everything stored about a user as JSON")
+                .arg(user_id_arg())
+                .arg(
+                    clap::Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("File to write the JSON bundle to (default: stdout)")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("erase")
+                .about("Delete everything stored about a user. Refused while the bot is running.")
+                .arg(user_id_arg())
+                .arg(
+                    clap::Arg::new("yes")
+                        .long("yes")
+                        .help("Confirm the erasure, which cannot be undone")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+}
+
+fn user_id_arg() -> clap::Arg {
+    clap::Arg::new("user-id")
+        .help("Discord user ID")
+        .required(true)
+        .num_args(1)
+}
+
+pub async fn run(sub_matches: &ArgMatches) -> Result<()> {
+    let Some((action, matches)) = sub_matches.subcommand() else {
+        bail!("no user-data command specified");
+    };
+    let user_id = user_id(matches)?;
+    if action == "erase" && !matches.get_flag("yes") {
+        bail!("erasing user data cannot be undone; pass --yes to confirm");
+    }
+
+    let _guard = init_tracing()?;
+    let config = Config::load().context("failed to load configuration")?;
+    // The SQLite store is locked while the bot runs; an in-memory one can't tell.
+    if action == "erase" && config.memory.persistence.backend == PersistenceBackend::InMemory {
+        bail!(
+            "erasing user data needs memory.persistence.backend = \"sqlite\"; with \"in_memory\" \
+             the running bot keeps its own copy of the user's conversations"
+        );
+    }
+
+    let memory_store = MemoryStore::new(&config)?;
+    memory_store.initialize().await?;
+
+    match action {
+        "export" => {
+            let export = memory_store.export_user_data(&user_id).await?;
+            let json = serde_json::to_string_pretty(&export)?;
+
+            match matches.get_one::<PathBuf>("output") {
+                Some(path) => {
+                    std::fs::write(path, json)
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                    info!(user_id = %user_id, path = %path.display(), "user data exported");
+                    eprintln!(
+                        "    {} Exported data of user {user_id} to {}",
+                        "✓".green(),
+                        path.display()
+                    );
+                }
+                None => println!("{json}"),
+            }
+            Ok(())
+        }
+        "erase" => {
+            let erased = memory_store.erase_user_data(&user_id).await?;
+            if erased.is_empty() {
+                println!("    {} Nothing was stored about user {user_id}", "i".cyan());
+            } else {
+                println!("    {} Erased data of user {user_id}:", "✓".green());
+                println!("      short-term entries:  {}", erased.short_term_entries);
+                println!("      session turns:       {}", erased.session_turns);
+                println!("      mid-term summaries:  {}", erased.mid_term_summaries);
+                println!("      long-term facts:     {}", erased.long_term_facts);
+                println!("      pending extractions: {}", erased.pending_extractions);
+                println!("      usage records:       {}", erased.usage_records);
+            }
+            Ok(())
+        }
+        _ => bail!("unknown user-data command `{action}`"),
+    }
+}
+
+fn user_id(matches: &ArgMatches) -> Result<String> {
+    let user_id = matches
+        .get_one::<String>("user-id")
+        .map(|id| id.trim())
+        .unwrap_or_default();
+    if user_id.parse::<u64>().is_err() {
+        bail!("`{user_id}` is not a Discord user ID");
+    }
+    Ok(user_id.to_string())
+}
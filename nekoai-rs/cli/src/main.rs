@@ -1,7 +1,7 @@
 mod chat;
 pub mod commands;
 
-use std::process::ExitCode;
+use std::{process::ExitCode, time::Duration};
 
 use anyhow::{Result, bail};
 use clap::Command;
@@ -10,6 +10,35 @@ use indicatif::{ProgressBar, ProgressStyle};
 use nekoai_agent::runtime::{AgentRuntime, RuntimeInitProgress};
 use tracing::{error, info, warn};
 
+/// `docker stop` sends SIGKILL 10 seconds after SIGTERM by default.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Resolves with the name of the first SIGINT / SIGTERM received.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                warn!(error = %e, "failed to install SIGTERM handler");
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "SIGINT"
+    }
+}
+
 fn cli() -> Command {
     Command::new("neko")
         .about("NekoAI")
@@ -68,6 +97,7 @@ fn cli() -> Command {
                         .action(clap::ArgAction::SetTrue),
                 ),
         )
+        .subcommand(commands::user_data::command())
 }
 
 #[tokio::main]
@@ -129,18 +159,32 @@ async fn run() -> Result<()> {
 
             info!("agent runtime initialized");
 
-            let chat_client = chat::ChatClient::initialize(&start_command.config, runtime).await?;
+            runtime.start_session_sweeper();
+
+            let chat_client =
+                chat::ChatClient::initialize(&start_command.config, runtime.clone()).await?;
 
             info!(
                 platform = chat_client.platform_name(),
                 "chat client initialized"
             );
 
-            chat_client.run().await?;
+            let run_result = tokio::select! {
+                result = chat_client.run() => result,
+                signal = shutdown_signal() => {
+                    info!(signal = signal, "shutdown signal received");
+                    Ok(())
+                }
+            };
+
+            println!("    {} Shutting down...", "•".cyan());
+            runtime.shutdown(SHUTDOWN_TIMEOUT).await;
+            run_result?;
 
             info!("application exited successfully");
             Ok(())
         }
+        Some(("user-data", sub_matches)) => commands::user_data::run(sub_matches).await,
         _ => {
             warn!("no command specified");
             println!("Please specify a command. Use --help for more information.");
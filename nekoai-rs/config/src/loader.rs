@@ -27,8 +27,15 @@ pub struct Parameters {
     pub max_token: u64,
     #[serde(default = "default_temperature")]
     pub temperature: f64,
-    #[serde(default = "default_top_p")]
-    pub top_p: f64,
+    /// Nucleus sampling. Only sent when set, since some APIs reject it
+    /// together with `temperature`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Send the JSON schema of structured requests (fact extraction,
+    /// summaries) as the provider's native response format. Turn off for
+    /// servers that reject `response_format`; the schema is still put in the prompt.
+    #[serde(default = "default_structured_output")]
+    pub structured_output: bool,
 }
 
 impl Default for Parameters {
@@ -36,7 +43,8 @@ impl Default for Parameters {
         Self {
             max_token: default_max_token(),
             temperature: default_temperature(),
-            top_p: default_top_p(),
+            top_p: None,
+            structured_output: default_structured_output(),
         }
     }
 }
@@ -49,12 +57,32 @@ const fn default_temperature() -> f64 {
     1.0
 }
 
-const fn default_top_p() -> f64 {
-    0.95
+const fn default_structured_output() -> bool {
+    true
+}
+
+/// Wire protocol used to talk to a language model provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProviderKind {
+    /// OpenAI Chat Completions API and compatible servers (vLLM, LM Studio, Ollama `/v1`, ...).
+    #[default]
+    #[serde(rename = "openai_compatible")]
+    OpenAICompatible,
+    /// OpenAI Responses API.
+    #[serde(rename = "openai_responses")]
+    OpenAIResponses,
+    /// Native Anthropic Messages API.
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    /// Native Ollama chat API (`/api/chat`).
+    #[serde(rename = "ollama")]
+    Ollama,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationModel {
+    #[serde(default)]
+    pub kind: ProviderKind,
     pub provider_base_url: String,
     pub api_key: SecretKey,
     pub model_name: String,
@@ -63,6 +91,8 @@ pub struct ConversationModel {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SummarizerModel {
+    #[serde(default)]
+    pub kind: ProviderKind,
     pub provider_base_url: String,
     pub api_key: SecretKey,
     pub model_name: String,
@@ -80,8 +110,65 @@ pub struct EmbeddingModel {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Provider {
     pub conversation_model: ConversationModel,
+    /// Tried in order when the conversation model fails or its circuit breaker is open.
+    #[serde(default)]
+    pub fallback_conversation_models: Vec<ConversationModel>,
     pub summarizer_model: SummarizerModel,
     pub embedding_model: EmbeddingModel,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Prices used for cost accounting; models without an entry are counted at zero cost.
+    #[serde(default)]
+    pub pricing: Vec<ModelPrice>,
+    /// Days of daily usage totals kept for usage queries and guild token
+    /// quotas; older days are deleted. `0` keeps them forever.
+    #[serde(default = "default_usage_retention_days")]
+    pub usage_retention_days: u32,
+}
+
+const fn default_usage_retention_days() -> u32 {
+    400
+}
+
+/// Price of a model per million tokens, in whatever currency the bill uses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelPrice {
+    /// Matches `model_name` of a conversation, summarizer or fallback model.
+    pub model: String,
+    #[serde(default)]
+    pub input_per_million: f64,
+    #[serde(default)]
+    pub output_per_million: f64,
+    /// Price of input tokens served from the prompt cache; defaults to the input price.
+    #[serde(default)]
+    pub cached_input_per_million: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures before a model is skipped.
+    #[serde(default = "default_circuit_breaker_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long a tripped model is skipped before it is probed again.
+    #[serde(default = "default_circuit_breaker_cooldown_seconds")]
+    pub cooldown_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_circuit_breaker_failure_threshold(),
+            cooldown_seconds: default_circuit_breaker_cooldown_seconds(),
+        }
+    }
+}
+
+const fn default_circuit_breaker_failure_threshold() -> u32 {
+    3
+}
+
+const fn default_circuit_breaker_cooldown_seconds() -> u64 {
+    60
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -121,6 +208,20 @@ pub struct Memory {
     pub mid_term_retention_days: u32,
     #[serde(default = "default_long_term_extraction_interval")]
     pub long_term_extraction_interval: usize,
+    /// Sessions idle for this long are summarized into mid-term memory and
+    /// evicted. `0` disables expiry.
+    #[serde(default = "default_session_idle_timeout_minutes")]
+    pub session_idle_timeout_minutes: u64,
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
+    #[serde(default)]
+    pub consolidation: ConsolidationConfig,
+    #[serde(default)]
+    pub ranking: RankingConfig,
+    #[serde(default)]
+    pub long_term_retention: LongTermRetentionConfig,
+    #[serde(default)]
+    pub recall: RecallConfig,
 }
 
 impl Default for Memory {
@@ -132,10 +233,229 @@ impl Default for Memory {
             long_term_top_k: default_long_term_top_k(),
             mid_term_retention_days: default_mid_term_retention_days(),
             long_term_extraction_interval: default_long_term_extraction_interval(),
+            session_idle_timeout_minutes: default_session_idle_timeout_minutes(),
+            persistence: PersistenceConfig::default(),
+            consolidation: ConsolidationConfig::default(),
+            ranking: RankingConfig::default(),
+            long_term_retention: LongTermRetentionConfig::default(),
+            recall: RecallConfig::default(),
+        }
+    }
+}
+
+/// Where sessions and short-term memory are kept between restarts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceBackend {
+    #[default]
+    Sqlite,
+    /// Nothing survives a restart.
+    InMemory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistenceConfig {
+    #[serde(default)]
+    pub backend: PersistenceBackend,
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            backend: PersistenceBackend::default(),
+            sqlite_path: default_sqlite_path(),
+        }
+    }
+}
+
+/// Before a long-term fact is stored, facts close to it are looked up and the
+/// summarizer model decides whether to keep both, merge them or let the new
+/// fact supersede the old ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsolidationConfig {
+    #[serde(default = "default_consolidation_enabled")]
+    pub enabled: bool,
+    /// Cosine similarity from which an existing fact counts as close.
+    #[serde(default = "default_consolidation_similarity_threshold")]
+    pub similarity_threshold: f32,
+    /// Close facts shown to the summarizer at most.
+    #[serde(default = "default_consolidation_max_candidates")]
+    pub max_candidates: usize,
+}
+
+impl Default for ConsolidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_consolidation_enabled(),
+            similarity_threshold: default_consolidation_similarity_threshold(),
+            max_candidates: default_consolidation_max_candidates(),
+        }
+    }
+}
+
+const fn default_consolidation_enabled() -> bool {
+    true
+}
+
+const fn default_consolidation_similarity_threshold() -> f32 {
+    0.85
+}
+
+const fn default_consolidation_max_candidates() -> usize {
+    5
+}
+
+/// Recalled memories are ranked by a weighted sum of vector similarity,
+/// recency and importance, each between 0 and 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankingConfig {
+    #[serde(default = "default_ranking_similarity_weight")]
+    pub similarity_weight: f32,
+    #[serde(default = "default_ranking_recency_weight")]
+    pub recency_weight: f32,
+    #[serde(default = "default_ranking_importance_weight")]
+    pub importance_weight: f32,
+    /// Days after which the recency of a memory that was not recalled in the
+    /// meantime has halved.
+    #[serde(default = "default_ranking_recency_half_life_days")]
+    pub recency_half_life_days: f32,
+    /// The vector search returns this many times `top_k` candidates to rank.
+    #[serde(default = "default_ranking_candidate_multiplier")]
+    pub candidate_multiplier: usize,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            similarity_weight: default_ranking_similarity_weight(),
+            recency_weight: default_ranking_recency_weight(),
+            importance_weight: default_ranking_importance_weight(),
+            recency_half_life_days: default_ranking_recency_half_life_days(),
+            candidate_multiplier: default_ranking_candidate_multiplier(),
         }
     }
 }
 
+const fn default_ranking_similarity_weight() -> f32 {
+    0.7
+}
+
+const fn default_ranking_recency_weight() -> f32 {
+    0.15
+}
+
+const fn default_ranking_importance_weight() -> f32 {
+    0.15
+}
+
+const fn default_ranking_recency_half_life_days() -> f32 {
+    30.0
+}
+
+const fn default_ranking_candidate_multiplier() -> usize {
+    3
+}
+
+/// How memories are found for a prompt: a keyword search next to the vector
+/// search, fused by reciprocal rank, an optional reranking by the summarizer
+/// model, and minimum scores that keep unrelated memories out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecallConfig {
+    #[serde(default = "default_recall_keyword_search")]
+    pub keyword_search: bool,
+    /// `k` of reciprocal rank fusion. Higher values flatten the difference
+    /// between ranks.
+    #[serde(default = "default_recall_rrf_k")]
+    pub rrf_k: f32,
+    /// Vector search results below this cosine similarity are dropped.
+    #[serde(default = "default_recall_min_similarity")]
+    pub min_similarity: f32,
+    /// Keyword search results below this BM25 score are dropped.
+    #[serde(default = "default_recall_min_keyword_score")]
+    pub min_keyword_score: f32,
+    #[serde(default)]
+    pub rerank: bool,
+    /// Reranked memories below this relevance, between 0 and 1, are dropped.
+    #[serde(default = "default_recall_min_rerank_score")]
+    pub min_rerank_score: f32,
+}
+
+impl Default for RecallConfig {
+    fn default() -> Self {
+        Self {
+            keyword_search: default_recall_keyword_search(),
+            rrf_k: default_recall_rrf_k(),
+            min_similarity: default_recall_min_similarity(),
+            min_keyword_score: default_recall_min_keyword_score(),
+            rerank: false,
+            min_rerank_score: default_recall_min_rerank_score(),
+        }
+    }
+}
+
+const fn default_recall_keyword_search() -> bool {
+    true
+}
+
+const fn default_recall_rrf_k() -> f32 {
+    60.0
+}
+
+const fn default_recall_min_similarity() -> f32 {
+    0.2
+}
+
+const fn default_recall_min_keyword_score() -> f32 {
+    1.0
+}
+
+const fn default_recall_min_rerank_score() -> f32 {
+    0.5
+}
+
+/// Eviction of long-term facts, run by the daily cleanup job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LongTermRetentionConfig {
+    /// Facts neither created nor recalled for this many days are evicted
+    /// unless their importance reaches `keep_importance`. `0` disables this.
+    #[serde(default = "default_long_term_max_idle_days")]
+    pub max_idle_days: u32,
+    #[serde(default = "default_long_term_keep_importance")]
+    pub keep_importance: f32,
+    /// Facts kept per user, or per channel for facts without a user; the ones
+    /// ranked lowest by recency and importance go first. `0` disables this.
+    #[serde(default = "default_long_term_max_facts_per_user")]
+    pub max_facts_per_user: usize,
+}
+
+impl Default for LongTermRetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_days: default_long_term_max_idle_days(),
+            keep_importance: default_long_term_keep_importance(),
+            max_facts_per_user: default_long_term_max_facts_per_user(),
+        }
+    }
+}
+
+const fn default_long_term_max_idle_days() -> u32 {
+    180
+}
+
+const fn default_long_term_keep_importance() -> f32 {
+    0.8
+}
+
+const fn default_long_term_max_facts_per_user() -> usize {
+    500
+}
+
+fn default_sqlite_path() -> String {
+    "data/nekoai.sqlite3".to_string()
+}
+
 pub const DEFAULT_QDRANT_URL: &str = "http://localhost:6334";
 
 fn default_qdrant_url() -> String {
@@ -170,6 +490,10 @@ const fn default_long_term_extraction_interval() -> usize {
     10
 }
 
+const fn default_session_idle_timeout_minutes() -> u64 {
+    60
+}
+
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
         info!("loading configuration file");
@@ -363,6 +687,315 @@ impl Default for WebUiConfig {
     }
 }
 
+/// Tokenizer used to measure prompt size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerKind {
+    /// BPE used by GPT-4o and later OpenAI models.
+    #[default]
+    O200kBase,
+    /// BPE used by GPT-4 / GPT-3.5.
+    Cl100kBase,
+    /// Character based estimate for models without a public tokenizer.
+    Heuristic,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Prompt budget in tokens (system prompt + memories + history + input).
+    #[serde(default = "default_context_max_tokens")]
+    pub max_tokens: usize,
+    /// Fraction of the history budget after which older turns are summarized.
+    #[serde(default = "default_compaction_threshold")]
+    pub compaction_threshold: f32,
+    /// Fraction of `max_tokens` that recalled memories may use.
+    #[serde(default = "default_memory_budget_ratio")]
+    pub memory_budget_ratio: f32,
+    #[serde(default)]
+    pub tokenizer: TokenizerKind,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_context_max_tokens(),
+            compaction_threshold: default_compaction_threshold(),
+            memory_budget_ratio: default_memory_budget_ratio(),
+            tokenizer: TokenizerKind::default(),
+        }
+    }
+}
+
+const fn default_context_max_tokens() -> usize {
+    16384
+}
+
+const fn default_compaction_threshold() -> f32 {
+    0.7
+}
+
+const fn default_memory_budget_ratio() -> f32 {
+    0.25
+}
+
+/// Safety envelope of a single request's model/tool loop. `0` disables a limit,
+/// except for `max_turns` which is always at least 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentLoopConfig {
+    /// Model round-trips per request, including the final answer.
+    #[serde(default = "default_agent_loop_max_turns")]
+    pub max_turns: usize,
+    /// Tool calls per request across all tools.
+    #[serde(default = "default_agent_loop_max_tool_calls")]
+    pub max_tool_calls: u32,
+    /// Calls of any single tool per request.
+    #[serde(default = "default_agent_loop_max_calls_per_tool")]
+    pub max_calls_per_tool: u32,
+    /// Wall-clock limit for the whole request.
+    #[serde(default = "default_agent_loop_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Per-guild overrides; unset fields fall back to the values above.
+    #[serde(default)]
+    pub guilds: Vec<GuildAgentLoopConfig>,
+    #[serde(default)]
+    pub delegation: DelegationConfig,
+}
+
+impl Default for AgentLoopConfig {
+    fn default() -> Self {
+        Self {
+            max_turns: default_agent_loop_max_turns(),
+            max_tool_calls: default_agent_loop_max_tool_calls(),
+            max_calls_per_tool: default_agent_loop_max_calls_per_tool(),
+            timeout_seconds: default_agent_loop_timeout_seconds(),
+            guilds: Vec::new(),
+            delegation: DelegationConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildAgentLoopConfig {
+    pub guild_id: u64,
+    #[serde(default)]
+    pub max_turns: Option<usize>,
+    #[serde(default)]
+    pub max_tool_calls: Option<u32>,
+    #[serde(default)]
+    pub max_calls_per_tool: Option<u32>,
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+}
+
+const fn default_agent_loop_max_turns() -> usize {
+    20
+}
+
+const fn default_agent_loop_max_tool_calls() -> u32 {
+    20
+}
+
+const fn default_agent_loop_max_calls_per_tool() -> u32 {
+    5
+}
+
+const fn default_agent_loop_timeout_seconds() -> u64 {
+    180
+}
+
+/// The `delegate_task` tool, which hands a task to a sub-agent with a fresh
+/// context and gets back only its condensed answer. `0` disables a limit
+/// except for `max_turns`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelegationConfig {
+    #[serde(default = "default_delegation_enabled")]
+    pub enabled: bool,
+    /// Names of the tools the sub-agent may call.
+    #[serde(default = "default_delegation_tools")]
+    pub tools: Vec<String>,
+    #[serde(default = "default_delegation_max_turns")]
+    pub max_turns: usize,
+    #[serde(default = "default_delegation_max_tool_calls")]
+    pub max_tool_calls: u32,
+    #[serde(default = "default_delegation_max_calls_per_tool")]
+    pub max_calls_per_tool: u32,
+    #[serde(default = "default_delegation_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Longer answers of the sub-agent are truncated.
+    #[serde(default = "default_delegation_max_result_chars")]
+    pub max_result_chars: usize,
+}
+
+impl Default for DelegationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_delegation_enabled(),
+            tools: default_delegation_tools(),
+            max_turns: default_delegation_max_turns(),
+            max_tool_calls: default_delegation_max_tool_calls(),
+            max_calls_per_tool: default_delegation_max_calls_per_tool(),
+            timeout_seconds: default_delegation_timeout_seconds(),
+            max_result_chars: default_delegation_max_result_chars(),
+        }
+    }
+}
+
+const fn default_delegation_enabled() -> bool {
+    true
+}
+
+fn default_delegation_tools() -> Vec<String> {
+    vec!["web_search".to_string(), "web_fetch".to_string()]
+}
+
+const fn default_delegation_max_turns() -> usize {
+    10
+}
+
+const fn default_delegation_max_tool_calls() -> u32 {
+    15
+}
+
+const fn default_delegation_max_calls_per_tool() -> u32 {
+    10
+}
+
+const fn default_delegation_timeout_seconds() -> u64 {
+    120
+}
+
+const fn default_delegation_max_result_chars() -> usize {
+    4000
+}
+
+/// Limits for files attached to a user message. Images are passed to the model
+/// as image parts and text files are inlined into the prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentConfig {
+    /// Images per message; `0` ignores images.
+    #[serde(default = "default_attachment_max_images")]
+    pub max_images: usize,
+    #[serde(default = "default_attachment_max_image_bytes")]
+    pub max_image_bytes: u64,
+    /// Longer text files are truncated to this size.
+    #[serde(default = "default_attachment_max_text_bytes")]
+    pub max_text_bytes: usize,
+    /// Inlined text across all files of a message; `0` ignores text files.
+    #[serde(default = "default_attachment_max_total_text_bytes")]
+    pub max_total_text_bytes: usize,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_images: default_attachment_max_images(),
+            max_image_bytes: default_attachment_max_image_bytes(),
+            max_text_bytes: default_attachment_max_text_bytes(),
+            max_total_text_bytes: default_attachment_max_total_text_bytes(),
+        }
+    }
+}
+
+const fn default_attachment_max_images() -> usize {
+    4
+}
+
+const fn default_attachment_max_image_bytes() -> u64 {
+    5 * 1024 * 1024
+}
+
+const fn default_attachment_max_text_bytes() -> usize {
+    32 * 1024
+}
+
+const fn default_attachment_max_total_text_bytes() -> usize {
+    96 * 1024
+}
+
+/// Admission control in front of the agent. `0` disables a limit. The guild
+/// daily request counts are kept in `memory.persistence` and survive a restart,
+/// and the token quota is read from the persisted model usage. User token
+/// buckets live in process memory and start full again on restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests a user can make back to back before the bucket runs dry.
+    #[serde(default = "default_rate_limit_user_burst")]
+    pub user_burst: u32,
+    /// Seconds it takes to get one request back in the bucket.
+    #[serde(default = "default_rate_limit_user_refill_seconds")]
+    pub user_refill_seconds: u64,
+    /// Requests per guild and UTC day.
+    #[serde(default)]
+    pub guild_daily_messages: u32,
+    /// Model tokens (input + output) per guild and UTC day, including
+    /// summarization and extraction.
+    #[serde(default)]
+    pub guild_daily_tokens: u64,
+    /// Members with any of these roles are never limited.
+    #[serde(default)]
+    pub exempt_role_ids: Vec<u64>,
+    /// Per-guild overrides; unset fields fall back to the values above.
+    #[serde(default)]
+    pub guilds: Vec<GuildRateLimitConfig>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            user_burst: default_rate_limit_user_burst(),
+            user_refill_seconds: default_rate_limit_user_refill_seconds(),
+            guild_daily_messages: 0,
+            guild_daily_tokens: 0,
+            exempt_role_ids: Vec::new(),
+            guilds: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildRateLimitConfig {
+    pub guild_id: u64,
+    #[serde(default)]
+    pub daily_messages: Option<u32>,
+    #[serde(default)]
+    pub daily_tokens: Option<u64>,
+    /// Added to the global `exempt_role_ids`.
+    #[serde(default)]
+    pub exempt_role_ids: Vec<u64>,
+}
+
+const fn default_rate_limit_user_burst() -> u32 {
+    5
+}
+
+const fn default_rate_limit_user_refill_seconds() -> u64 {
+    30
+}
+
+/// A named agent profile. Unset fields fall back to the conversation model,
+/// `INSTRUCTION.md` and the full tool set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersonaConfig {
+    pub name: String,
+    /// Shown by `/persona list`.
+    #[serde(default)]
+    pub description: String,
+    /// Replaces `INSTRUCTION.md`; relative to the `.config` directory.
+    #[serde(default)]
+    pub instruction_file: Option<String>,
+    /// Model of the conversation model's provider to use instead.
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<Parameters>,
+    /// Names of the tools the persona may call.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    /// Channels (or threads) that use this persona unless `/persona` picked another.
+    #[serde(default)]
+    pub channel_ids: Vec<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
@@ -375,6 +1008,16 @@ pub struct Config {
     pub tools: ToolPermissions,
     #[serde(default)]
     pub web_ui: WebUiConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub agent_loop: AgentLoopConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub attachments: AttachmentConfig,
+    #[serde(default)]
+    pub personas: Vec<PersonaConfig>,
 }
 
 impl fmt::Debug for SecretKey {
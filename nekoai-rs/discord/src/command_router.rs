@@ -1,6 +1,6 @@
 use nekoai_agent::runtime::AgentRuntime;
 
-use crate::commands::{ask, clear, history};
+use crate::commands::{ask, clear, history, memory, persona};
 
 pub struct Data {
     pub agent_runtime: AgentRuntime,
@@ -36,13 +36,14 @@ pub async fn command_framework(
     guild_id: u64,
     agent_runtime: AgentRuntime,
 ) -> poise::framework::Framework<Data, anyhow::Error> {
-    let commands = vec![ask(), clear(), history()];
+    let commands = vec![ask(), clear(), history(), memory(), persona()];
 
     poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands,
             prefix_options: poise::PrefixFrameworkOptions {
                 prefix: Some("w!".into()),
+                non_command_message: Some(crate::mention::on_non_command_message),
                 ..Default::default()
             },
             on_error: |error| Box::pin(on_error(error)),
@@ -1,10 +1,40 @@
-use nekoai_domain::agent::session::SessionKey;
-use tracing::{debug, error, info};
+use std::{
+    future::IntoFuture,
+    time::{Duration, Instant},
+};
 
-use crate::{command_router::Context, commands::utils::session_resolver};
+use futures::StreamExt;
+use nekoai_agent::{
+    input::UserInput,
+    rate_limit::RateLimited,
+    runtime::{RequestId, ResponseStreamItem},
+};
+use nekoai_domain::agent::{
+    runtime::{CallerContext, with_caller_context},
+    session::SessionKey,
+};
+use poise::{
+    CreateReply, ReplyHandle,
+    serenity_prelude::{
+        Attachment, ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateButton,
+        CreateInteractionResponse,
+    },
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    command_router::Context,
+    commands::utils::{
+        download_attachments, rate_limited_message, resolve_caller_profile, session_resolver,
+    },
+};
 
 #[poise::command(prefix_command, slash_command)]
-pub async fn ask(ctx: Context<'_>, #[description = "Prompt"] prompt: String) -> anyhow::Result<()> {
+pub async fn ask(
+    ctx: Context<'_>,
+    #[description = "Prompt"] prompt: String,
+    #[description = "Image or text file to include"] attachment: Option<Attachment>,
+) -> anyhow::Result<()> {
     if ctx.author().bot {
         debug!(user_id = %ctx.author().id, "ignored bot invocation");
         return Ok(());
@@ -17,18 +47,33 @@ pub async fn ask(ctx: Context<'_>, #[description = "Prompt"] prompt: String) ->
         "processing ask command"
     );
 
-    let _typing = match &ctx {
-        Context::Application(ctx) => {
-            ctx.defer().await?;
-            None
-        }
-        Context::Prefix(ctx) => Some(ctx.channel_id().start_typing(&ctx.serenity_context().http)),
-    };
-
     let guild_id = ctx.guild_id();
     let channel_id = ctx.channel_id();
+    let user_id = ctx.author().id.to_string();
+    let runtime = &ctx.data().agent_runtime;
 
-    let (kind, thread_id) = session_resolver(&ctx, channel_id, guild_id).await;
+    let (nick, role_ids) = match ctx.author_member().await {
+        Some(member) => (member.nick.clone(), member.roles.clone()),
+        None => (None, Vec::new()),
+    };
+    let header = format!(
+        "**{}**:\n\n{}\n\n**Assistant**:\n\n",
+        ctx.author()
+            .global_name
+            .clone()
+            .unwrap_or_else(|| ctx.author().name.clone()),
+        prompt
+    );
+
+    // Answer right away so slash commands don't hit the interaction deadline
+    // while memory recall and generation are running.
+    let stop_button_id = format!("ask-stop-{}", ctx.id());
+    let mut reply = StreamedReply::new(ctx, stop_button_id.clone());
+    reply
+        .render(&format!("{header}{THINKING_PLACEHOLDER}"))
+        .await?;
+
+    let (kind, thread_id) = session_resolver(ctx.http(), channel_id, guild_id).await;
 
     let session_key = SessionKey {
         guild_id,
@@ -39,52 +84,217 @@ pub async fn ask(ctx: Context<'_>, #[description = "Prompt"] prompt: String) ->
 
     debug!(session = %session_key.channel_id, "session key resolved");
 
-    let user_id = ctx.author().id.to_string();
-    let reply = match ctx
-        .data()
-        .agent_runtime
-        .submit(session_key, Some(user_id.clone()), prompt.clone())
-        .await
+    let profile = resolve_caller_profile(
+        ctx.serenity_context(),
+        guild_id,
+        channel_id,
+        ctx.author(),
+        nick.as_deref(),
+        &role_ids,
+        ctx.locale(),
+    )
+    .await;
+
+    // Prefix invocations forward every file attached to the message.
+    let attachments = match ctx {
+        poise::Context::Prefix(prefix) => prefix.msg.attachments.clone(),
+        poise::Context::Application(_) => attachment.into_iter().collect(),
+    };
+    let input =
+        UserInput::new(prompt).attachments(download_attachments(runtime, &attachments).await);
+
+    let caller_context = CallerContext {
+        profile,
+        ..Default::default()
+    };
+    let request_id = RequestId::new();
+    let mut stream = match with_caller_context(
+        caller_context,
+        runtime.submit_stream(request_id, session_key.clone(), Some(user_id), input),
+    )
+    .await
     {
-        Ok(response) => {
-            info!(
-                response_len = response.content.len(),
-                "agent response generated"
-            );
-            format!(
-                "**{}**:\n\n{}\n\n**Assistant**:\n\n{}\n",
-                ctx.author()
-                    .global_name
-                    .clone()
-                    .unwrap_or_else(|| ctx.author().name.clone()),
-                prompt,
-                response.content
-            )
-        }
+        Ok(stream) => stream,
         Err(err) => {
-            error!(error = %err, "failed to generate agent response");
-            "An error occurred while processing your request. Please try again later.".to_string()
+            reply.remove_stop_button();
+            match err.downcast_ref::<RateLimited>() {
+                Some(limited) => reply.render(&rate_limited_message(limited)).await?,
+                None => {
+                    error!(error = %err, "failed to start agent response stream");
+                    reply.render(ERROR_MESSAGE).await?;
+                }
+            }
+            return Ok(());
         }
     };
 
-    let chunks = split_message(&reply);
-    info!(chunk_count = chunks.len(), "sending discord reply chunks");
-    for chunk in chunks {
-        match ctx {
-            Context::Application(ctx) => {
-                ctx.say(chunk).await?;
+    let mut streamed = String::new();
+    let mut last_render = Instant::now();
+
+    // Only the author can stop their own request.
+    let mut stop_pressed = ComponentInteractionCollector::new(ctx)
+        .custom_ids(vec![stop_button_id])
+        .author_id(ctx.author().id)
+        .into_future();
+    let mut stop_requested = false;
+
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            interaction = &mut stop_pressed, if !stop_requested => {
+                stop_requested = true;
+                if let Some(interaction) = interaction {
+                    if let Err(err) = interaction
+                        .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                        .await
+                    {
+                        warn!(error = %err, "failed to acknowledge stop button");
+                    }
+                    info!(session = %session_key.channel_id, "stop requested from discord");
+                    runtime.cancel(request_id);
+                }
+                continue;
             }
-            Context::Prefix(ctx) => {
-                ctx.channel_id()
-                    .say(&ctx.serenity_context().http, chunk)
+        };
+        let Some(item) = item else {
+            break;
+        };
+
+        match item {
+            Ok(ResponseStreamItem::Chunk(chunk)) => {
+                streamed.push_str(&chunk);
+                if last_render.elapsed() >= STREAM_EDIT_INTERVAL {
+                    if let Err(err) = reply.render(&format!("{header}{streamed}")).await {
+                        warn!(error = %err, "failed to update streamed reply");
+                    }
+                    last_render = Instant::now();
+                }
+            }
+            Ok(ResponseStreamItem::Completed(response)) => {
+                info!(
+                    response_len = response.content.len(),
+                    cancelled = response.cancelled,
+                    "agent response generated"
+                );
+                reply.remove_stop_button();
+                reply
+                    .render(&format!("{header}{}\n", response.content))
                     .await?;
+                info!(
+                    message_count = reply.message_count(),
+                    "discord reply completed"
+                );
+                return Ok(());
+            }
+            Err(err) => {
+                error!(error = %err, "failed to generate agent response");
+                reply.remove_stop_button();
+                reply.render(ERROR_MESSAGE).await?;
+                return Ok(());
             }
         }
     }
 
+    warn!("agent response stream ended without a final response");
+    reply.remove_stop_button();
+    reply.render(ERROR_MESSAGE).await?;
+
     Ok(())
 }
 
+const THINKING_PLACEHOLDER: &str = "*Thinking…*";
+pub(crate) const ERROR_MESSAGE: &str =
+    "An error occurred while processing your request. Please try again later.";
+/// Minimum delay between two edits of a streamed reply, to stay clear of
+/// Discord's message edit rate limit.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// A reply that spans one or more Discord messages and is re-rendered in place
+/// as the text grows.
+///
+/// Each render splits the full text with [`split_message`], edits messages whose
+/// content changed, posts new messages when the text rolls over the length limit
+/// and deletes trailing messages that are no longer needed.
+///
+/// The first message carries a "Stop" button until [`StreamedReply::remove_stop_button`]
+/// is called; the next render then edits it away.
+struct StreamedReply<'a> {
+    ctx: Context<'a>,
+    messages: Vec<(ReplyHandle<'a>, String)>,
+    stop_button_id: Option<String>,
+    stop_button_removed: bool,
+}
+
+impl<'a> StreamedReply<'a> {
+    fn new(ctx: Context<'a>, stop_button_id: String) -> Self {
+        Self {
+            ctx,
+            messages: Vec::new(),
+            stop_button_id: Some(stop_button_id),
+            stop_button_removed: false,
+        }
+    }
+
+    fn remove_stop_button(&mut self) {
+        if self.stop_button_id.take().is_some() {
+            self.stop_button_removed = true;
+        }
+    }
+
+    fn reply(&self, index: usize, chunk: &str) -> CreateReply {
+        let reply = CreateReply::default().content(chunk);
+        if index != 0 {
+            return reply;
+        }
+
+        let components = match &self.stop_button_id {
+            Some(id) => vec![CreateActionRow::Buttons(vec![
+                CreateButton::new(id.clone())
+                    .label("Stop")
+                    .style(ButtonStyle::Danger),
+            ])],
+            None => Vec::new(),
+        };
+        reply.components(components)
+    }
+
+    fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    async fn render(&mut self, text: &str) -> Result<(), serenity::Error> {
+        let chunks = split_message(text);
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let components_changed = index == 0 && self.stop_button_removed;
+            let reply = self.reply(index, chunk);
+
+            match self.messages.get_mut(index) {
+                Some((_, content)) if content == chunk && !components_changed => {}
+                Some((handle, content)) => {
+                    handle.edit(self.ctx, reply).await?;
+                    *content = chunk.to_string();
+                    if index == 0 {
+                        self.stop_button_removed = false;
+                    }
+                }
+                None => {
+                    let handle = self.ctx.send(reply).await?;
+                    self.messages.push((handle, chunk.to_string()));
+                }
+            }
+        }
+
+        while self.messages.len() > chunks.len() {
+            if let Some((handle, _)) = self.messages.pop() {
+                handle.delete(self.ctx).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 const DISCORD_MAX_LENGTH: usize = 2000;
 
 pub fn split_message(text: &str) -> Vec<&str> {
@@ -101,10 +311,11 @@ pub fn split_message(text: &str) -> Vec<&str> {
             break;
         }
 
-        let split_at = remaining[.. DISCORD_MAX_LENGTH]
+        let limit = remaining.floor_char_boundary(DISCORD_MAX_LENGTH);
+        let split_at = remaining[.. limit]
             .rfind('\n')
             .map(|pos| pos + 1)
-            .unwrap_or(DISCORD_MAX_LENGTH);
+            .unwrap_or(limit);
 
         let (chunk, rest) = remaining.split_at(split_at);
         chunks.push(chunk);
@@ -21,7 +21,7 @@ pub async fn clear(ctx: Context<'_>) -> anyhow::Result<()> {
     let guild_id = ctx.guild_id();
     let channel_id = ctx.channel_id();
 
-    let (kind, thread_id) = session_resolver(&ctx, channel_id, guild_id).await;
+    let (kind, thread_id) = session_resolver(ctx.http(), channel_id, guild_id).await;
 
     let session_key = SessionKey {
         guild_id,
@@ -0,0 +1,389 @@
+use nekoai_memory::{long_term::SUPERSEDED_KEY, store::MemoryEntry};
+use poise::CreateReply;
+use serenity::all::User;
+use tracing::{error, info};
+
+use crate::command_router::Context;
+
+/// Facts shown per page of `/memory list`.
+const PAGE_SIZE: usize = 10;
+/// Facts returned by `/memory search`.
+const SEARCH_LIMIT: usize = 10;
+/// Longer facts are cut short in listings.
+const MAX_FACT_CHARS: usize = 120;
+
+#[poise::command(
+    slash_command,
+    subcommands("list", "search", "forget", "forget_all", "admin"),
+    subcommand_required
+)]
+pub async fn memory(_: Context<'_>) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Lists what NekoAI remembers about you, newest first.
+#[poise::command(slash_command)]
+pub async fn list(
+    ctx: Context<'_>,
+    #[description = "Page number, starting at 1"]
+    #[min = 1]
+    page: Option<usize>,
+) -> anyhow::Result<()> {
+    let user_id = ctx.author().id.to_string();
+    list_facts(ctx, &user_id, None, page.unwrap_or(1), "You have").await
+}
+
+/// Searches what NekoAI remembers about you.
+#[poise::command(slash_command)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "What to look for"] query: String,
+) -> anyhow::Result<()> {
+    ctx.defer_ephemeral().await?;
+    let user_id = ctx.author().id.to_string();
+    let result = ctx
+        .data()
+        .agent_runtime
+        .memory_store()
+        .search_user_facts(&user_id, None, &query, SEARCH_LIMIT)
+        .await;
+    reply_search(ctx, result).await
+}
+
+/// Forgets one thing NekoAI remembers about you, by the ID shown in `/memory list`.
+#[poise::command(slash_command)]
+pub async fn forget(
+    ctx: Context<'_>,
+    #[description = "ID of the memory"] id: String,
+) -> anyhow::Result<()> {
+    ctx.defer_ephemeral().await?;
+    let user_id = ctx.author().id.to_string();
+    forget_fact(ctx, id.trim(), |fact| {
+        metadata_str(fact, "user_id") == Some(user_id.as_str())
+    })
+    .await
+}
+
+/// Forgets everything NekoAI remembers about you, in every server.
+#[poise::command(slash_command, rename = "forget-all")]
+pub async fn forget_all(
+    ctx: Context<'_>,
+    #[description = "Set to true to confirm; this cannot be undone"] confirm: bool,
+) -> anyhow::Result<()> {
+    if !confirm {
+        say_private(
+            ctx,
+            "Nothing was forgotten. Run the command with `confirm: true` to proceed.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.defer_ephemeral().await?;
+    let user_id = ctx.author().id.to_string();
+    forget_user(
+        ctx,
+        &user_id,
+        None,
+        "everything NekoAI remembered about you",
+    )
+    .await
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("admin_list", "admin_search", "admin_forget", "admin_forget_user"),
+    subcommand_required
+)]
+pub async fn admin(_: Context<'_>) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Lists what NekoAI remembers about a member in this server.
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "list",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn admin_list(
+    ctx: Context<'_>,
+    #[description = "Member"] user: User,
+    #[description = "Page number, starting at 1"]
+    #[min = 1]
+    page: Option<usize>,
+) -> anyhow::Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    let heading = format!("{} has", user.name);
+    list_facts(
+        ctx,
+        &user.id.to_string(),
+        Some(&guild_id.to_string()),
+        page.unwrap_or(1),
+        &heading,
+    )
+    .await
+}
+
+/// Searches what NekoAI remembers in this server, optionally about one member.
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "search",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn admin_search(
+    ctx: Context<'_>,
+    #[description = "What to look for"] query: String,
+    #[description = "Only memories about this member"] user: Option<User>,
+) -> anyhow::Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = guild_id.to_string();
+    let memory_store = ctx.data().agent_runtime.memory_store();
+    let result = match user {
+        Some(user) => {
+            memory_store
+                .search_user_facts(&user.id.to_string(), Some(&guild_id), &query, SEARCH_LIMIT)
+                .await
+        }
+        None => {
+            memory_store
+                .search_guild_facts(&guild_id, &query, SEARCH_LIMIT)
+                .await
+        }
+    };
+    reply_search(ctx, result).await
+}
+
+/// Forgets one memory learned in this server, by its ID.
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "forget",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn admin_forget(
+    ctx: Context<'_>,
+    #[description = "ID of the memory"] id: String,
+) -> anyhow::Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = guild_id.to_string();
+    forget_fact(ctx, id.trim(), |fact| {
+        metadata_str(fact, "guild_id") == Some(guild_id.as_str())
+    })
+    .await
+}
+
+/// Forgets everything NekoAI learned about a member in this server.
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "forget-user",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn admin_forget_user(
+    ctx: Context<'_>,
+    #[description = "Member"] user: User,
+    #[description = "Set to true to confirm; this cannot be undone"] confirm: bool,
+) -> anyhow::Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    if !confirm {
+        say_private(
+            ctx,
+            "Nothing was forgotten. Run the command with `confirm: true` to proceed.",
+        )
+        .await?;
+        return Ok(());
+    }
+    ctx.defer_ephemeral().await?;
+
+    let what = format!(
+        "everything NekoAI learned about {} in this server",
+        user.name
+    );
+    forget_user(
+        ctx,
+        &user.id.to_string(),
+        Some(&guild_id.to_string()),
+        &what,
+    )
+    .await
+}
+
+async fn list_facts(
+    ctx: Context<'_>,
+    user_id: &str,
+    guild_id: Option<&str>,
+    page: usize,
+    heading: &str,
+) -> anyhow::Result<()> {
+    ctx.defer_ephemeral().await?;
+    let page = page.max(1);
+
+    // One more than needed tells whether there is a next page.
+    let facts = match ctx
+        .data()
+        .agent_runtime
+        .memory_store()
+        .list_user_facts(user_id, guild_id, page * PAGE_SIZE + 1)
+        .await
+    {
+        Ok(facts) => facts,
+        Err(err) => {
+            error!(error = %err, "failed to list memories");
+            say_private(ctx, "Failed to load memories.").await?;
+            return Ok(());
+        }
+    };
+
+    let shown: Vec<&MemoryEntry> = facts
+        .iter()
+        .skip((page - 1) * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .collect();
+    if shown.is_empty() {
+        let message = if page == 1 {
+            format!("{heading} no memories.")
+        } else {
+            format!("There is no page {page}.")
+        };
+        say_private(ctx, &message).await?;
+        return Ok(());
+    }
+
+    let mut message = format!("{heading} these memories (page {page}):\n");
+    for fact in shown {
+        message.push_str(&format_fact(fact));
+        message.push('\n');
+    }
+    if facts.len() > page * PAGE_SIZE {
+        message.push_str(&format!("Use `page: {}` to see more.", page + 1));
+    }
+    say_private(ctx, &message).await
+}
+
+async fn reply_search(
+    ctx: Context<'_>,
+    result: anyhow::Result<Vec<MemoryEntry>>,
+) -> anyhow::Result<()> {
+    match result {
+        Ok(facts) if facts.is_empty() => say_private(ctx, "No matching memories.").await,
+        Ok(facts) => {
+            let message = facts.iter().map(format_fact).collect::<Vec<_>>().join("\n");
+            say_private(ctx, &message).await
+        }
+        Err(err) => {
+            error!(error = %err, "failed to search memories");
+            say_private(ctx, "Failed to search memories.").await
+        }
+    }
+}
+
+/// Deletes the fact if `allowed` accepts it. A fact that is not allowed is
+/// reported as missing so that IDs of other users' memories are not confirmed.
+async fn forget_fact(
+    ctx: Context<'_>,
+    id: &str,
+    allowed: impl FnOnce(&MemoryEntry) -> bool,
+) -> anyhow::Result<()> {
+    let memory_store = ctx.data().agent_runtime.memory_store();
+    let fact = match memory_store.get_fact(id).await {
+        Ok(fact) => fact.filter(|fact| allowed(fact)),
+        // Malformed IDs are rejected by the vector database.
+        Err(err) => {
+            info!(id = %id, error = %err, "failed to look up memory");
+            None
+        }
+    };
+    let Some(fact) = fact else {
+        return say_private(ctx, "No memory with that ID was found.").await;
+    };
+
+    match memory_store.forget_fact(&fact.id).await {
+        Ok(()) => {
+            info!(user_id = %ctx.author().id, id = %fact.id, "memory forgotten");
+            say_private(ctx, &format!("Forgot: {}", truncate(&fact.content))).await
+        }
+        Err(err) => {
+            error!(error = %err, "failed to forget memory");
+            say_private(ctx, "Failed to forget the memory.").await
+        }
+    }
+}
+
+async fn forget_user(
+    ctx: Context<'_>,
+    user_id: &str,
+    guild_id: Option<&str>,
+    what: &str,
+) -> anyhow::Result<()> {
+    match ctx
+        .data()
+        .agent_runtime
+        .memory_store()
+        .forget_user_facts(user_id, guild_id)
+        .await
+    {
+        Ok(deleted) => {
+            info!(
+                user_id = %ctx.author().id,
+                target_user_id = user_id,
+                guild_id = ?guild_id,
+                deleted = deleted,
+                "user memories forgotten"
+            );
+            say_private(ctx, &format!("Forgot {what} ({deleted} memories).")).await
+        }
+        Err(err) => {
+            error!(error = %err, "failed to forget user memories");
+            say_private(ctx, "Failed to forget the memories.").await
+        }
+    }
+}
+
+fn format_fact(fact: &MemoryEntry) -> String {
+    // Superseded facts are no longer recalled but are kept until forgotten.
+    let superseded = fact
+        .metadata
+        .get(SUPERSEDED_KEY)
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false);
+    format!(
+        "`{}` ({}{}) {}",
+        fact.id,
+        fact.created_at.format("%Y-%m-%d"),
+        if superseded { ", outdated" } else { "" },
+        truncate(&fact.content)
+    )
+}
+
+fn truncate(content: &str) -> String {
+    let content = content.replace('\n', " ");
+    match content.char_indices().nth(MAX_FACT_CHARS) {
+        Some((end, _)) => format!("{}…", &content[.. end]),
+        None => content,
+    }
+}
+
+fn metadata_str<'a>(fact: &'a MemoryEntry, key: &str) -> Option<&'a str> {
+    fact.metadata.get(key).and_then(|value| value.as_str())
+}
+
+async fn say_private(ctx: Context<'_>, content: &str) -> anyhow::Result<()> {
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
@@ -1,8 +1,12 @@
 pub mod ask;
 pub mod clear;
 pub mod history;
+pub mod memory;
+pub mod persona;
 pub mod utils;
 
 pub use ask::ask;
 pub use clear::clear;
 pub use history::history;
+pub use memory::memory;
+pub use persona::persona;
@@ -0,0 +1,138 @@
+use nekoai_agent::persona::{DEFAULT_PERSONA, UnknownPersona};
+use nekoai_domain::agent::session::SessionKey;
+use tracing::{debug, error, info};
+
+use crate::{command_router::Context, commands::utils::session_resolver};
+
+#[poise::command(
+    slash_command,
+    subcommands("list", "show", "set", "clear"),
+    subcommand_required
+)]
+pub async fn persona(_: Context<'_>) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Lists the configured personas.
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> anyhow::Result<()> {
+    let personas = ctx.data().agent_runtime.personas().list();
+    if personas.is_empty() {
+        ctx.say("No personas are configured.").await?;
+        return Ok(());
+    }
+
+    let lines = personas
+        .iter()
+        .map(|persona| {
+            if persona.description.is_empty() {
+                format!("- **{}**", persona.name)
+            } else {
+                format!("- **{}**: {}", persona.name, persona.description)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    ctx.say(lines).await?;
+    Ok(())
+}
+
+/// Shows the persona answering in this channel.
+#[poise::command(slash_command)]
+pub async fn show(ctx: Context<'_>) -> anyhow::Result<()> {
+    let guild_id = ctx.guild_id();
+    let channel_id = ctx.channel_id();
+    let (kind, thread_id) = session_resolver(ctx.http(), channel_id, guild_id).await;
+    let session_key = SessionKey {
+        guild_id,
+        channel_id,
+        thread_id,
+        kind,
+    };
+    // A thread without a persona of its own uses its channel's.
+    let parent_channel_id = match thread_id {
+        Some(_) => ctx
+            .guild_channel()
+            .await
+            .and_then(|channel| channel.parent_id)
+            .map(|id| id.get()),
+        None => None,
+    };
+
+    let message = match ctx
+        .data()
+        .agent_runtime
+        .personas()
+        .resolve(&session_key, parent_channel_id)
+    {
+        Some(persona) => format!("This channel uses the persona **{}**.", persona.name),
+        None => "This channel uses the default persona.".to_string(),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Picks the persona for this channel or thread.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_CHANNELS")]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "Persona name, or `default` for the default persona"]
+    #[autocomplete = "autocomplete_persona"]
+    name: String,
+) -> anyhow::Result<()> {
+    select(ctx, Some(name.trim())).await
+}
+
+/// Drops the persona picked for this channel or thread.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_CHANNELS")]
+pub async fn clear(ctx: Context<'_>) -> anyhow::Result<()> {
+    select(ctx, None).await
+}
+
+async fn select(ctx: Context<'_>, name: Option<&str>) -> anyhow::Result<()> {
+    info!(
+        user_id = %ctx.author().id,
+        channel_id = %ctx.channel_id(),
+        persona = ?name,
+        "processing persona command"
+    );
+
+    let personas = ctx.data().agent_runtime.personas();
+    match personas.select(ctx.channel_id().get(), name).await {
+        Ok(()) => {
+            let message = match name {
+                Some(name) => format!("This channel now uses the persona **{name}**."),
+                None => "The persona picked for this channel was cleared.".to_string(),
+            };
+            ctx.say(message).await?;
+        }
+        Err(err) => match err.downcast_ref::<UnknownPersona>() {
+            Some(UnknownPersona(name)) => {
+                debug!(persona = %name, "unknown persona requested");
+                ctx.say(format!(
+                    "There is no persona named `{name}`. See `/persona list`."
+                ))
+                .await?;
+            }
+            None => {
+                error!(error = %err, "failed to change the channel persona");
+                ctx.say("Failed to change the persona.").await?;
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn autocomplete_persona<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    ctx.data()
+        .agent_runtime
+        .personas()
+        .list()
+        .iter()
+        .map(|persona| persona.name.clone())
+        .chain([DEFAULT_PERSONA.to_string()])
+        .filter(move |name| name.starts_with(partial))
+}
@@ -0,0 +1,39 @@
+use nekoai_agent::{input::Attachment, runtime::AgentRuntime};
+use tracing::{debug, warn};
+
+/// Downloads the attachments the runtime's attachment policy accepts. The rest
+/// are passed on without data so that the model is told they were skipped.
+pub async fn download_attachments(
+    runtime: &AgentRuntime,
+    attachments: &[serenity::all::Attachment],
+) -> Vec<Attachment> {
+    let policy = runtime.attachment_policy();
+    let mut downloaded = Vec::with_capacity(attachments.len());
+
+    for attachment in attachments {
+        let size = u64::from(attachment.size);
+        let content_type = attachment.content_type.as_deref();
+
+        let data = if policy.accepts(&attachment.filename, content_type, size) {
+            match attachment.download().await {
+                Ok(data) => Some(data),
+                Err(err) => {
+                    warn!(filename = %attachment.filename, error = %err, "failed to download attachment");
+                    None
+                }
+            }
+        } else {
+            debug!(filename = %attachment.filename, size, "skipped attachment");
+            None
+        };
+
+        downloaded.push(Attachment {
+            filename: attachment.filename.clone(),
+            content_type: attachment.content_type.clone(),
+            size,
+            data,
+        });
+    }
+
+    downloaded
+}
@@ -0,0 +1,117 @@
+use nekoai_domain::agent::runtime::CallerProfile;
+use serenity::all::{
+    Channel, ChannelId, ChannelType, Context, GuildChannel, GuildId, RoleId, User,
+};
+use tracing::debug;
+
+/// Resolves the names used by the instruction placeholders, from the cache
+/// where possible and over HTTP otherwise. `locale` is the invoking user's
+/// locale when the interaction carries one; the guild's is used otherwise.
+pub async fn resolve_caller_profile(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    user: &User,
+    nick: Option<&str>,
+    role_ids: &[RoleId],
+    locale: Option<&str>,
+) -> CallerProfile {
+    let mut profile = CallerProfile {
+        user_name: Some(
+            nick.map(ToOwned::to_owned)
+                .or_else(|| user.global_name.clone())
+                .unwrap_or_else(|| user.name.clone()),
+        ),
+        locale: locale.map(ToOwned::to_owned),
+        role_ids: role_ids.iter().map(|id| id.get()).collect(),
+        ..Default::default()
+    };
+
+    let Some(guild_id) = guild_id else {
+        return profile;
+    };
+
+    let cached_guild = ctx.cache.guild(guild_id).map(|guild| {
+        let roles: Vec<String> = role_ids
+            .iter()
+            .filter_map(|id| guild.roles.get(id).map(|role| role.name.clone()))
+            .collect();
+        (guild.name.clone(), roles, guild.preferred_locale.clone())
+    });
+    let (guild_name, roles, guild_locale) = match cached_guild {
+        Some(guild) => guild,
+        None => match guild_id.to_partial_guild(&ctx.http).await {
+            Ok(guild) => {
+                let roles = role_ids
+                    .iter()
+                    .filter_map(|id| guild.roles.get(id).map(|role| role.name.clone()))
+                    .collect();
+                (guild.name, roles, guild.preferred_locale)
+            }
+            Err(e) => {
+                debug!(guild_id = %guild_id, error = %e, "failed to fetch guild for caller profile");
+                return profile;
+            }
+        },
+    };
+    profile.guild_name = Some(guild_name);
+    profile.roles = roles;
+    profile.locale = profile.locale.or(Some(guild_locale));
+
+    let Some(channel) = guild_channel(ctx, guild_id, channel_id).await else {
+        return profile;
+    };
+    profile.channel_name = Some(channel.name.clone());
+
+    let category_id = if is_thread(&channel) {
+        profile.parent_channel_id = channel.parent_id.map(|id| id.get());
+        match channel.parent_id {
+            Some(parent_id) => guild_channel(ctx, guild_id, parent_id)
+                .await
+                .and_then(|parent| parent.parent_id),
+            None => None,
+        }
+    } else {
+        channel.parent_id
+    };
+    if let Some(category_id) = category_id {
+        profile.category = guild_channel(ctx, guild_id, category_id)
+            .await
+            .map(|category| category.name);
+    }
+
+    profile
+}
+
+async fn guild_channel(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<GuildChannel> {
+    let cached = ctx.cache.guild(guild_id).and_then(|guild| {
+        guild
+            .channels
+            .get(&channel_id)
+            .or_else(|| guild.threads.iter().find(|thread| thread.id == channel_id))
+            .cloned()
+    });
+    if cached.is_some() {
+        return cached;
+    }
+
+    match channel_id.to_channel(&ctx.http).await {
+        Ok(Channel::Guild(channel)) => Some(channel),
+        Ok(_) => None,
+        Err(e) => {
+            debug!(channel_id = %channel_id, error = %e, "failed to fetch channel for caller profile");
+            None
+        }
+    }
+}
+
+fn is_thread(channel: &GuildChannel) -> bool {
+    matches!(
+        channel.kind,
+        ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
+    )
+}
@@ -1,2 +1,8 @@
+pub mod attachments;
+pub mod caller_profile;
+pub mod rate_limited;
 pub mod session_resolver;
+pub use attachments::download_attachments;
+pub use caller_profile::resolve_caller_profile;
+pub use rate_limited::rate_limited_message;
 pub use session_resolver::session_resolver;
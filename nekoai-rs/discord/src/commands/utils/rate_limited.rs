@@ -0,0 +1,18 @@
+use nekoai_agent::rate_limit::{RateLimitScope, RateLimited};
+
+/// Tells the user which limit they hit and when it resets, as a Discord timestamp.
+pub fn rate_limited_message(limited: &RateLimited) -> String {
+    let resets = format!("<t:{}:R>", limited.resets_at.timestamp());
+    match limited.scope {
+        RateLimitScope::User => {
+            format!("You're asking a little too fast. You can ask again {resets}.")
+        }
+        RateLimitScope::GuildMessages => format!(
+            "This server has used all {} requests for today. The quota resets {resets}.",
+            limited.limit
+        ),
+        RateLimitScope::GuildTokens => {
+            format!("This server has used up today's token quota. It resets {resets}.")
+        }
+    }
+}
@@ -1,14 +1,12 @@
 use nekoai_domain::agent::session::SessionKind;
-use serenity::all::{Channel, ChannelId, ChannelType, GuildId};
-
-use crate::command_router::Context;
+use serenity::all::{Channel, ChannelId, ChannelType, GuildId, Http};
 
 pub async fn session_resolver(
-    ctx: &Context<'_>,
+    http: &Http,
     channel_id: ChannelId,
     guild_id: Option<GuildId>,
 ) -> (SessionKind, Option<ChannelId>) {
-    let (kind, thread_id) = match channel_id.to_channel(&ctx.serenity_context().http).await {
+    let (kind, thread_id) = match channel_id.to_channel(http).await {
         Ok(Channel::Guild(guild_channel)) => match guild_channel.kind {
             ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread => {
                 (SessionKind::Thread, Some(guild_channel.id))
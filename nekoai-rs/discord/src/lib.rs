@@ -2,3 +2,4 @@ pub mod client;
 pub mod command_router;
 pub mod commands;
 pub mod handler;
+pub mod mention;
@@ -0,0 +1,127 @@
+use nekoai_agent::{input::UserInput, rate_limit::RateLimited, runtime::RequestId};
+use nekoai_domain::agent::{
+    runtime::{CallerContext, with_caller_context},
+    session::SessionKey,
+};
+use serenity::all::{Context, Message};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    command_router::Data,
+    commands::{
+        ask::{ERROR_MESSAGE, split_message},
+        utils::{
+            download_attachments, rate_limited_message, resolve_caller_profile, session_resolver,
+        },
+    },
+};
+
+/// Registered as the prefix framework's `non_command_message` callback, so it
+/// only sees messages that did not invoke a command.
+pub fn on_non_command_message<'a>(
+    framework: &'a poise::FrameworkContext<'a, Data, anyhow::Error>,
+    ctx: &'a Context,
+    msg: &'a Message,
+) -> poise::BoxFuture<'a, anyhow::Result<()>> {
+    Box::pin(async move {
+        if msg.author.bot || !msg.mentions_user_id(framework.bot_id) {
+            return Ok(());
+        }
+        answer_mention(framework.user_data, ctx, msg, framework.bot_id.get()).await
+    })
+}
+
+/// Answers a message that mentions the bot, including its attachments.
+async fn answer_mention(
+    data: &Data,
+    ctx: &Context,
+    msg: &Message,
+    bot_id: u64,
+) -> anyhow::Result<()> {
+    let prompt = msg
+        .content
+        .replace(&format!("<@{bot_id}>"), "")
+        .replace(&format!("<@!{bot_id}>"), "")
+        .trim()
+        .to_string();
+    if prompt.is_empty() && msg.attachments.is_empty() {
+        debug!(user_id = %msg.author.id, "ignored empty mention");
+        return Ok(());
+    }
+
+    info!(
+        user_id = %msg.author.id,
+        channel_id = %msg.channel_id,
+        prompt_len = prompt.len(),
+        attachment_count = msg.attachments.len(),
+        "processing mention"
+    );
+
+    let runtime = &data.agent_runtime;
+    let user_id = msg.author.id.to_string();
+    let role_ids = msg
+        .member
+        .as_ref()
+        .map(|member| member.roles.clone())
+        .unwrap_or_default();
+
+    let typing = msg.channel_id.start_typing(&ctx.http);
+
+    let (kind, thread_id) = session_resolver(&ctx.http, msg.channel_id, msg.guild_id).await;
+    let session_key = SessionKey {
+        guild_id: msg.guild_id,
+        channel_id: msg.channel_id,
+        thread_id,
+        kind,
+    };
+
+    let profile = resolve_caller_profile(
+        ctx,
+        msg.guild_id,
+        msg.channel_id,
+        &msg.author,
+        msg.member
+            .as_ref()
+            .and_then(|member| member.nick.as_deref()),
+        &role_ids,
+        None,
+    )
+    .await;
+    let caller_context = CallerContext {
+        profile,
+        ..Default::default()
+    };
+
+    let input =
+        UserInput::new(prompt).attachments(download_attachments(runtime, &msg.attachments).await);
+    let content = match with_caller_context(
+        caller_context,
+        runtime.submit(RequestId::new(), session_key, Some(user_id), input),
+    )
+    .await
+    {
+        Ok(response) => response.content,
+        Err(err) => match err.downcast_ref::<RateLimited>() {
+            Some(limited) => rate_limited_message(limited),
+            None => {
+                error!(error = %err, "failed to answer mention");
+                ERROR_MESSAGE.to_string()
+            }
+        },
+    };
+    typing.stop();
+
+    for (index, chunk) in split_message(&content).into_iter().enumerate() {
+        let sent = if index == 0 {
+            msg.reply(ctx, chunk).await
+        } else {
+            msg.channel_id.say(ctx, chunk).await
+        };
+        if let Err(err) = sent {
+            warn!(error = %err, "failed to send mention reply");
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -1,9 +1,32 @@
 use std::cell::RefCell;
 
+use crate::agent::session::SessionKey;
+
 #[derive(Clone, Debug, Default)]
 pub struct CallerContext {
     pub user_id: Option<u64>,
     pub guild_id: Option<u64>,
+    pub session_key: Option<SessionKey>,
+    pub profile: CallerProfile,
+}
+
+/// Names the chat frontend resolved for a request, used to fill the
+/// instruction placeholders. Whatever could not be resolved is left empty.
+#[derive(Clone, Debug, Default)]
+pub struct CallerProfile {
+    pub guild_name: Option<String>,
+    pub channel_name: Option<String>,
+    /// Name of the category the channel (or the thread's channel) is in.
+    pub category: Option<String>,
+    /// The channel a thread belongs to.
+    pub parent_channel_id: Option<u64>,
+    /// Server nickname, else global display name, else username.
+    pub user_name: Option<String>,
+    pub roles: Vec<String>,
+    /// IDs of the user's roles in the guild, for rate limit exemptions.
+    pub role_ids: Vec<u64>,
+    /// Discord locale such as `ja` or `en-US`.
+    pub locale: Option<String>,
 }
 
 tokio::task_local! {
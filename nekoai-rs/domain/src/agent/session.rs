@@ -1,13 +1,13 @@
 use serenity::all::{ChannelId, GuildId};
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum SessionKind {
     GuildChannel,
     Thread,
     DirectMessage,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct SessionKey {
     pub guild_id: Option<GuildId>,
     pub channel_id: ChannelId,
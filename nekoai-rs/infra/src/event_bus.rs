@@ -45,6 +45,14 @@ pub enum AgentEvent {
     ErrorOccurred {
         session_key: SessionKey,
         error: String,
+        /// Model that produced the error, when it came from a specific model call.
+        model: Option<String>,
+    },
+    /// A request gave up and returned `error` to its caller. The failed model
+    /// calls behind it were each reported as `ErrorOccurred` already.
+    RequestFailed {
+        session_key: SessionKey,
+        error: String,
     },
 }
 
@@ -1,9 +1,9 @@
 use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    Router,
+    Json, Router,
     body::Body,
-    extract::State,
+    extract::{Query, State},
     http::{HeaderMap, Method, StatusCode},
     middleware::{self, Next},
     response::sse::Event,
@@ -13,7 +13,10 @@ use nekoai_config::loader::WebUiConfig;
 use tokio_stream::{StreamExt, wrappers::BroadcastStream};
 use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
-use crate::web_ui_agent::WebUiAgent;
+use crate::{
+    usage::{UsageQuery, UsageSummary},
+    web_ui_agent::WebUiAgent,
+};
 
 #[derive(Clone)]
 pub struct HttpServerState {
@@ -94,6 +97,7 @@ impl HttpServer {
         let app = Router::new()
             .route("/api/events", get(sse_handler))
             .route("/api/metrics", get(metrics_handler))
+            .route("/api/usage", get(usage_handler))
             .layer(auth_mw)
             .layer(cors)
             .with_state(state);
@@ -133,3 +137,11 @@ async fn sse_handler(
 async fn metrics_handler(State(state): State<HttpServerState>) -> String {
     state.agent.metrics().collect_prometheus()
 }
+
+/// Token usage and cost, e.g. `/api/usage?group_by=guild&since=2026-10-01`.
+async fn usage_handler(
+    State(state): State<HttpServerState>,
+    Query(query): Query<UsageQuery>,
+) -> Json<Vec<UsageSummary>> {
+    Json(state.agent.metrics().usage(&query))
+}
@@ -1,6 +1,7 @@
 pub mod event_bus;
 pub mod logging;
 pub mod metrics;
+pub mod usage;
 pub mod web_ui_agent;
 
 #[cfg(feature = "web-ui")]
@@ -9,10 +9,27 @@ use std::{
 use dashmap::DashMap;
 use tokio::time::Instant;
 
+use crate::usage::{ModelUsage, UsageLedger, UsageQuery, UsageSummary};
+
+/// Upper bounds (in seconds) of the tool latency histogram buckets.
+const TOOL_LATENCY_BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Debug, Default)]
+struct ToolStats {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    latency_buckets: [AtomicU64; TOOL_LATENCY_BUCKETS.len()],
+    latency_sum_micros: AtomicU64,
+}
+
 #[derive(Clone, Debug)]
 pub struct Metrics {
     messages_total: Arc<AtomicU64>,
-    tool_calls_total: Arc<DashMap<String, AtomicU64>>,
+    tool_stats: Arc<DashMap<String, ToolStats>>,
+    extraction_queue_depth: Arc<AtomicU64>,
+    extraction_failures_total: Arc<AtomicU64>,
+    rate_limited: Arc<DashMap<String, AtomicU64>>,
+    usage: Arc<UsageLedger>,
     response_latencies: Arc<Mutex<Vec<f64>>>,
     start_time: Instant,
 }
@@ -21,7 +38,11 @@ impl Metrics {
     pub fn new() -> Self {
         Self {
             messages_total: Arc::new(AtomicU64::new(0)),
-            tool_calls_total: Arc::new(DashMap::new()),
+            tool_stats: Arc::new(DashMap::new()),
+            extraction_queue_depth: Arc::new(AtomicU64::new(0)),
+            extraction_failures_total: Arc::new(AtomicU64::new(0)),
+            rate_limited: Arc::new(DashMap::new()),
+            usage: Arc::new(UsageLedger::default()),
             response_latencies: Arc::new(Mutex::new(Vec::new())),
             start_time: Instant::now(),
         }
@@ -31,13 +52,55 @@ impl Metrics {
         self.messages_total.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn record_tool_call(&self, tool_name: &str) {
-        self.tool_calls_total
-            .entry(tool_name.to_string())
-            .or_insert_with(|| AtomicU64::new(0))
+    pub fn record_tool_call(&self, tool_name: &str, duration: std::time::Duration, success: bool) {
+        let stats = self.tool_stats.entry(tool_name.to_string()).or_default();
+
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let secs = duration.as_secs_f64();
+        if let Some(index) = TOOL_LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            stats.latency_buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        stats.latency_sum_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn set_extraction_queue_depth(&self, depth: u64) {
+        self.extraction_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn record_extraction_failure(&self) {
+        self.extraction_failures_total
             .fetch_add(1, Ordering::Relaxed);
     }
 
+    /// A request turned away by the rate limiter; `scope` names the limit it hit.
+    pub fn record_rate_limited(&self, scope: &str) {
+        self.rate_limited
+            .entry(scope.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_model_usage(&self, usage: ModelUsage) {
+        self.usage.record(usage);
+    }
+
+    /// The ledger behind [`Self::usage`], for loading and saving it.
+    pub fn usage_ledger(&self) -> &UsageLedger {
+        &self.usage
+    }
+
+    /// Token usage and cost, filtered and grouped as requested.
+    pub fn usage(&self, query: &UsageQuery) -> Vec<UsageSummary> {
+        self.usage.query(query)
+    }
+
     pub fn record_latency(&self, duration: std::time::Duration) {
         let secs = duration.as_secs_f64();
         if let Ok(mut latencies) = self.response_latencies.lock() {
@@ -59,12 +122,89 @@ impl Metrics {
 
         output.push_str("# HELP nekoai_tool_calls_total Total tool calls by tool name\n");
         output.push_str("# TYPE nekoai_tool_calls_total counter\n");
-        for entry in self.tool_calls_total.iter() {
+        for entry in self.tool_stats.iter() {
             let name = entry.key();
-            let count = entry.value().load(Ordering::Relaxed);
+            let count = entry.value().calls.load(Ordering::Relaxed);
             let _ = writeln!(output, "nekoai_tool_calls_total{{tool=\"{name}\"}} {count}");
         }
 
+        output.push_str("# HELP nekoai_tool_errors_total Failed tool calls by tool name\n");
+        output.push_str("# TYPE nekoai_tool_errors_total counter\n");
+        for entry in self.tool_stats.iter() {
+            let name = entry.key();
+            let count = entry.value().errors.load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "nekoai_tool_errors_total{{tool=\"{name}\"}} {count}"
+            );
+        }
+
+        output.push_str("# HELP nekoai_tool_latency_seconds Tool call latency in seconds\n");
+        output.push_str("# TYPE nekoai_tool_latency_seconds histogram\n");
+        for entry in self.tool_stats.iter() {
+            let name = entry.key();
+            let stats = entry.value();
+
+            let mut cumulative = 0;
+            for (bound, bucket) in TOOL_LATENCY_BUCKETS.iter().zip(&stats.latency_buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    output,
+                    "nekoai_tool_latency_seconds_bucket{{tool=\"{name}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+
+            let count = stats.calls.load(Ordering::Relaxed);
+            let sum = stats.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(
+                output,
+                "nekoai_tool_latency_seconds_bucket{{tool=\"{name}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                output,
+                "nekoai_tool_latency_seconds_sum{{tool=\"{name}\"}} {sum}"
+            );
+            let _ = writeln!(
+                output,
+                "nekoai_tool_latency_seconds_count{{tool=\"{name}\"}} {count}"
+            );
+        }
+
+        output.push_str(
+            "# HELP nekoai_extraction_queue_depth Long-term extraction jobs waiting or running\n",
+        );
+        output.push_str("# TYPE nekoai_extraction_queue_depth gauge\n");
+        let _ = writeln!(
+            output,
+            "nekoai_extraction_queue_depth {}",
+            self.extraction_queue_depth.load(Ordering::Relaxed)
+        );
+
+        output.push_str(
+            "# HELP nekoai_extraction_failures_total Failed long-term extraction attempts\n",
+        );
+        output.push_str("# TYPE nekoai_extraction_failures_total counter\n");
+        let _ = writeln!(
+            output,
+            "nekoai_extraction_failures_total {}",
+            self.extraction_failures_total.load(Ordering::Relaxed)
+        );
+
+        output.push_str(
+            "# HELP nekoai_rate_limited_total Requests rejected by the rate limiter by scope\n",
+        );
+        output.push_str("# TYPE nekoai_rate_limited_total counter\n");
+        for entry in self.rate_limited.iter() {
+            let scope = entry.key();
+            let count = entry.value().load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "nekoai_rate_limited_total{{scope=\"{scope}\"}} {count}"
+            );
+        }
+
+        self.usage.write_prometheus(&mut output);
+
         output.push_str("# HELP nekoai_response_latency_seconds Response latency in seconds\n");
         output.push_str("# TYPE nekoai_response_latency_seconds gauge\n");
         if let Ok(latencies) = self.response_latencies.lock()
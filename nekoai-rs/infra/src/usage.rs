@@ -0,0 +1,449 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use dashmap::DashMap;
+use nekoai_domain::agent::session::SessionKey;
+use serde::{Deserialize, Serialize};
+
+/// Which part of the runtime made a model call.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum UsagePurpose {
+    #[default]
+    Conversation,
+    Summarization,
+    Extraction,
+    /// A sub-agent run by the `delegate_task` tool.
+    Delegation,
+    /// Rescoring recalled memories against the message.
+    Reranking,
+}
+
+impl UsagePurpose {
+    const ALL: [Self; 5] = [
+        Self::Conversation,
+        Self::Summarization,
+        Self::Extraction,
+        Self::Delegation,
+        Self::Reranking,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Conversation => "conversation",
+            Self::Summarization => "summarization",
+            Self::Extraction => "extraction",
+            Self::Delegation => "delegation",
+            Self::Reranking => "reranking",
+        }
+    }
+}
+
+impl FromStr for UsagePurpose {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|purpose| purpose.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown usage purpose `{s}`"))
+    }
+}
+
+/// Tokens and cost of a single model call, with who it is billed to.
+#[derive(Debug, Clone)]
+pub struct ModelUsage {
+    pub model: String,
+    pub purpose: UsagePurpose,
+    pub user_id: Option<u64>,
+    pub guild_id: Option<u64>,
+    pub session_key: Option<SessionKey>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Part of `input_tokens` served from the provider's prompt cache.
+    pub cached_input_tokens: u64,
+    /// In the currency of `provider.pricing`; zero for models without a price.
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageGroupBy {
+    User,
+    Guild,
+    Session,
+    #[default]
+    Model,
+    Purpose,
+    Day,
+}
+
+/// Filters and grouping for [`UsageLedger::query`]. Every filter is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub group_by: UsageGroupBy,
+    pub user_id: Option<u64>,
+    pub guild_id: Option<u64>,
+    /// Matches the channel or the thread of the session.
+    pub channel_id: Option<u64>,
+    pub model: Option<String>,
+    pub purpose: Option<UsagePurpose>,
+    /// First day (UTC) to include.
+    pub since: Option<NaiveDate>,
+    /// Last day (UTC) to include.
+    pub until: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageSummary {
+    /// Value of the grouped dimension, or `"unknown"` when the calls had none.
+    pub key: String,
+    pub calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_input_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageSummary {
+    fn add(&mut self, totals: &UsageTotals) {
+        self.calls += totals.calls;
+        self.input_tokens += totals.input_tokens;
+        self.output_tokens += totals.output_tokens;
+        self.cached_input_tokens += totals.cached_input_tokens;
+        self.cost += totals.cost;
+    }
+}
+
+/// Totals of the calls of one day, model, purpose, user, guild and session,
+/// as they are stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRecord {
+    pub day: NaiveDate,
+    pub model: String,
+    pub purpose: UsagePurpose,
+    pub user_id: Option<u64>,
+    pub guild_id: Option<u64>,
+    pub session_key: Option<SessionKey>,
+    pub calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_input_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageRecord {
+    fn new(key: UsageKey, totals: UsageTotals) -> Self {
+        Self {
+            day: key.day,
+            model: key.model,
+            purpose: key.purpose,
+            user_id: key.user_id,
+            guild_id: key.guild_id,
+            session_key: key.session_key,
+            calls: totals.calls,
+            input_tokens: totals.input_tokens,
+            output_tokens: totals.output_tokens,
+            cached_input_tokens: totals.cached_input_tokens,
+            cost: totals.cost,
+        }
+    }
+
+    fn split(self) -> (UsageKey, UsageTotals) {
+        (
+            UsageKey {
+                day: self.day,
+                model: self.model,
+                purpose: self.purpose,
+                user_id: self.user_id,
+                guild_id: self.guild_id,
+                session_key: self.session_key,
+            },
+            UsageTotals {
+                calls: self.calls,
+                input_tokens: self.input_tokens,
+                output_tokens: self.output_tokens,
+                cached_input_tokens: self.cached_input_tokens,
+                cost: self.cost,
+            },
+        )
+    }
+}
+
+/// Durable storage of the [`UsageLedger`] totals, so that they survive a
+/// restart.
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    /// Adds each record to the stored totals with the same day, model,
+    /// purpose, user, guild and session.
+    async fn add_usage(&self, records: &[UsageRecord]) -> Result<()>;
+    /// Stored totals of `since` and later days.
+    async fn load_usage(&self, since: NaiveDate) -> Result<Vec<UsageRecord>>;
+    /// Deletes the totals of days before `day` and returns how many there were.
+    async fn delete_usage_before(&self, day: NaiveDate) -> Result<u64>;
+    /// Stored totals of the calls billed to `user_id`.
+    async fn list_user_usage(&self, user_id: u64) -> Result<Vec<UsageRecord>>;
+    /// Deletes the totals of the calls billed to `user_id` and returns how many
+    /// there were.
+    async fn delete_user_usage(&self, user_id: u64) -> Result<u64>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct UsageKey {
+    day: NaiveDate,
+    model: String,
+    purpose: UsagePurpose,
+    user_id: Option<u64>,
+    guild_id: Option<u64>,
+    session_key: Option<SessionKey>,
+}
+
+impl UsageKey {
+    fn matches(&self, query: &UsageQuery) -> bool {
+        query.user_id.is_none_or(|id| self.user_id == Some(id))
+            && query.guild_id.is_none_or(|id| self.guild_id == Some(id))
+            && query.channel_id.is_none_or(|id| {
+                self.session_key.as_ref().is_some_and(|key| {
+                    key.channel_id.get() == id || key.thread_id.is_some_and(|t| t.get() == id)
+                })
+            })
+            && query
+                .model
+                .as_ref()
+                .is_none_or(|model| &self.model == model)
+            && query.purpose.is_none_or(|purpose| self.purpose == purpose)
+            && query.since.is_none_or(|since| self.day >= since)
+            && query.until.is_none_or(|until| self.day <= until)
+    }
+
+    fn group(&self, group_by: UsageGroupBy) -> String {
+        let unknown = || "unknown".to_string();
+        match group_by {
+            UsageGroupBy::User => self
+                .user_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(unknown),
+            UsageGroupBy::Guild => self
+                .guild_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(unknown),
+            UsageGroupBy::Session => self
+                .session_key
+                .as_ref()
+                .map(session_label)
+                .unwrap_or_else(unknown),
+            UsageGroupBy::Model => self.model.clone(),
+            UsageGroupBy::Purpose => self.purpose.as_str().to_string(),
+            UsageGroupBy::Day => self.day.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct UsageTotals {
+    calls: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    cached_input_tokens: u64,
+    cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.calls += other.calls;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cached_input_tokens += other.cached_input_tokens;
+        self.cost += other.cost;
+    }
+}
+
+/// Token usage aggregated per day, model, purpose, user, guild and session.
+/// Queried from process memory; [`UsageLedger::load`] and
+/// [`UsageLedger::save`] keep it in a [`UsageStore`] across restarts.
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    totals: DashMap<UsageKey, UsageTotals>,
+    /// Recorded since the last save.
+    unsaved: Mutex<HashMap<UsageKey, UsageTotals>>,
+}
+
+impl UsageLedger {
+    pub fn record(&self, usage: ModelUsage) {
+        let key = UsageKey {
+            day: Utc::now().date_naive(),
+            model: usage.model,
+            purpose: usage.purpose,
+            user_id: usage.user_id,
+            guild_id: usage.guild_id,
+            session_key: usage.session_key,
+        };
+        let call = UsageTotals {
+            calls: 1,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cached_input_tokens: usage.cached_input_tokens,
+            cost: usage.cost,
+        };
+
+        self.unsaved().entry(key.clone()).or_default().add(&call);
+        self.totals.entry(key).or_default().add(&call);
+    }
+
+    /// Adds the totals of `since` and later days stored in `store`. Returns
+    /// how many were loaded.
+    pub async fn load(&self, store: &dyn UsageStore, since: NaiveDate) -> Result<usize> {
+        let records = store.load_usage(since).await?;
+        let loaded = records.len();
+        for record in records {
+            let (key, totals) = record.split();
+            self.totals.entry(key).or_default().add(&totals);
+        }
+        Ok(loaded)
+    }
+
+    /// Adds what was recorded since the last save to `store`. On failure it is
+    /// kept for the next save. Returns how many totals were saved.
+    pub async fn save(&self, store: &dyn UsageStore) -> Result<usize> {
+        let unsaved = std::mem::take(&mut *self.unsaved());
+        if unsaved.is_empty() {
+            return Ok(0);
+        }
+
+        let records: Vec<_> = unsaved
+            .into_iter()
+            .map(|(key, totals)| UsageRecord::new(key, totals))
+            .collect();
+        if let Err(e) = store.add_usage(&records).await {
+            let mut unsaved = self.unsaved();
+            for record in records {
+                let (key, totals) = record.split();
+                unsaved.entry(key).or_default().add(&totals);
+            }
+            return Err(e);
+        }
+        Ok(records.len())
+    }
+
+    /// Drops the totals of days before `day`, here and in `store`. Returns how
+    /// many were deleted from `store`.
+    pub async fn prune(&self, store: &dyn UsageStore, day: NaiveDate) -> Result<u64> {
+        self.totals.retain(|key, _| key.day >= day);
+        store.delete_usage_before(day).await
+    }
+
+    fn unsaved(&self) -> std::sync::MutexGuard<'_, HashMap<UsageKey, UsageTotals>> {
+        self.unsaved.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Totals of the matching calls, one row per group, most expensive first.
+    pub fn query(&self, query: &UsageQuery) -> Vec<UsageSummary> {
+        let mut groups: BTreeMap<String, UsageSummary> = BTreeMap::new();
+        for entry in self
+            .totals
+            .iter()
+            .filter(|entry| entry.key().matches(query))
+        {
+            let key = entry.key().group(query.group_by);
+            groups
+                .entry(key.clone())
+                .or_insert_with(|| UsageSummary {
+                    key,
+                    ..Default::default()
+                })
+                .add(entry.value());
+        }
+
+        let mut summaries: Vec<_> = groups.into_values().collect();
+        summaries.sort_by(|a, b| {
+            b.cost
+                .total_cmp(&a.cost)
+                .then(b.input_tokens.cmp(&a.input_tokens))
+        });
+        summaries
+    }
+
+    /// Per-user and per-session totals are left to [`UsageLedger::query`] to keep
+    /// the label cardinality bounded.
+    pub(crate) fn write_prometheus(&self, output: &mut String) {
+        let mut series: BTreeMap<(String, UsagePurpose, Option<u64>), UsageSummary> =
+            BTreeMap::new();
+        for entry in self.totals.iter() {
+            let key = entry.key();
+            series
+                .entry((key.model.clone(), key.purpose, key.guild_id))
+                .or_default()
+                .add(entry.value());
+        }
+
+        output
+            .push_str("# HELP nekoai_model_calls_total Model calls by model, purpose and guild\n");
+        output.push_str("# TYPE nekoai_model_calls_total counter\n");
+        for ((model, purpose, guild), totals) in &series {
+            let labels = usage_labels(model, *purpose, *guild);
+            let _ = writeln!(
+                output,
+                "nekoai_model_calls_total{{{labels}}} {}",
+                totals.calls
+            );
+        }
+
+        output.push_str(
+            "# HELP nekoai_model_tokens_total Model tokens by model, purpose, guild and direction\n",
+        );
+        output.push_str("# TYPE nekoai_model_tokens_total counter\n");
+        for ((model, purpose, guild), totals) in &series {
+            let labels = usage_labels(model, *purpose, *guild);
+            for (direction, tokens) in [
+                ("input", totals.input_tokens),
+                ("output", totals.output_tokens),
+                ("cached_input", totals.cached_input_tokens),
+            ] {
+                let _ = writeln!(
+                    output,
+                    "nekoai_model_tokens_total{{{labels},direction=\"{direction}\"}} {tokens}"
+                );
+            }
+        }
+
+        output.push_str("# HELP nekoai_model_cost_total Model cost by model, purpose and guild\n");
+        output.push_str("# TYPE nekoai_model_cost_total counter\n");
+        for ((model, purpose, guild), totals) in &series {
+            let labels = usage_labels(model, *purpose, *guild);
+            let _ = writeln!(
+                output,
+                "nekoai_model_cost_total{{{labels}}} {}",
+                totals.cost
+            );
+        }
+    }
+}
+
+fn usage_labels(model: &str, purpose: UsagePurpose, guild_id: Option<u64>) -> String {
+    let guild = guild_id.map(|id| id.to_string()).unwrap_or_default();
+    format!(
+        "model=\"{model}\",purpose=\"{}\",guild=\"{guild}\"",
+        purpose.as_str()
+    )
+}
+
+fn session_label(session_key: &SessionKey) -> String {
+    let mut label = match session_key.guild_id {
+        Some(guild_id) => format!("{guild_id}/{}", session_key.channel_id),
+        None => format!("dm/{}", session_key.channel_id),
+    };
+    if let Some(thread_id) = session_key.thread_id {
+        let _ = write!(label, "/{thread_id}");
+    }
+    label
+}
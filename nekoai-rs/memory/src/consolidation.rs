@@ -0,0 +1,25 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::store::MemoryEntry;
+
+/// What to do with a newly extracted fact, given the stored facts close to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Consolidation {
+    /// The new fact says something the close facts do not; store it as well.
+    Keep,
+    /// The new fact repeats or extends the fact `id`, whose content becomes
+    /// `content`. Nothing new is stored.
+    Merge { id: String, content: String },
+    /// The new fact replaces the facts `ids`, for example a changed preference.
+    /// It is stored and they are marked as superseded by it.
+    Supersede { ids: Vec<String> },
+}
+
+/// Decides how a new long-term fact relates to the facts already stored, so
+/// that repeated extractions do not pile up near-identical facts.
+#[async_trait]
+pub trait FactConsolidator: Send + Sync {
+    /// `nearby` holds the close facts, most similar first, and is never empty.
+    async fn consolidate(&self, fact: &str, nearby: &[MemoryEntry]) -> Result<Consolidation>;
+}
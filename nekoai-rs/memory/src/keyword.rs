@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::vector_db::SparseVector;
+
+/// BM25 term frequency saturation.
+const K1: f32 = 1.2;
+/// BM25 length normalization.
+const B: f32 = 0.75;
+/// Length in terms that documents are normalized against. Memories are short
+/// facts and summaries, and the real average is not known when storing.
+const AVERAGE_TERMS: f32 = 32.0;
+
+/// Terms of `text` for keyword search. Words of letters, digits, `_` and `-`
+/// are kept whole and lowercased, so that names like `neko_chan`, `project-x`
+/// or `E0433` match exactly. Runs of CJK characters, which are not separated
+/// by spaces, become overlapping pairs of characters.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut cjk = Vec::new();
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            push_word(&mut word, &mut terms);
+            cjk.push(c);
+        } else if c.is_alphanumeric() || c == '_' || c == '-' {
+            push_cjk(&mut cjk, &mut terms);
+            word.extend(c.to_lowercase());
+        } else {
+            push_word(&mut word, &mut terms);
+            push_cjk(&mut cjk, &mut terms);
+        }
+    }
+    push_word(&mut word, &mut terms);
+    push_cjk(&mut cjk, &mut terms);
+
+    terms
+}
+
+/// Term weights of a stored document: the BM25 term frequency part. The IDF
+/// part depends on the whole collection and is applied at search time.
+pub fn document_vector(text: &str) -> SparseVector {
+    let terms = tokenize(text);
+    let length_norm = 1.0 - B + B * terms.len() as f32 / AVERAGE_TERMS;
+
+    let mut frequencies: HashMap<u32, f32> = HashMap::new();
+    for term in &terms {
+        *frequencies.entry(term_index(term)).or_default() += 1.0;
+    }
+    frequencies
+        .into_iter()
+        .map(|(index, tf)| (index, tf * (K1 + 1.0) / (tf + K1 * length_norm)))
+        .collect()
+}
+
+/// Terms of a query, each weighted once however often it appears.
+pub fn query_vector(text: &str) -> SparseVector {
+    let mut indices: Vec<u32> = tokenize(text).iter().map(|term| term_index(term)).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    let values = vec![1.0; indices.len()];
+    SparseVector { indices, values }
+}
+
+/// 32-bit FNV-1a. Collisions only merge the weights of unrelated terms.
+fn term_index(term: &str) -> u32 {
+    let mut hash = 2166136261u32;
+    for &byte in term.as_bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+fn push_word(word: &mut String, terms: &mut Vec<String>) {
+    let trimmed = word.trim_matches(|c| c == '_' || c == '-');
+    // Single letters are mostly noise; single digits may be versions or IDs.
+    let mut chars = trimmed.chars();
+    if let Some(first) = chars.next()
+        && (chars.next().is_some() || first.is_numeric())
+    {
+        terms.push(trimmed.to_string());
+    }
+    word.clear();
+}
+
+fn push_cjk(run: &mut Vec<char>, terms: &mut Vec<String>) {
+    match run.len() {
+        0 => {}
+        1 => terms.push(run[0].to_string()),
+        _ => terms.extend(run.windows(2).map(|pair| pair.iter().collect::<String>())),
+    }
+    run.clear();
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}' // Hangul
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+        | '\u{FF66}'..='\u{FF9F}' // Halfwidth Katakana
+    )
+}
@@ -1,7 +1,13 @@
 // crates/memory/src/lib.rs
+pub mod consolidation;
 pub mod embedding;
+pub mod keyword;
 pub mod long_term;
 pub mod mid_term;
+pub mod persistence;
+pub mod ranking;
+pub mod rerank;
 pub mod short_term;
 pub mod store;
+pub mod user_data;
 pub mod vector_db;
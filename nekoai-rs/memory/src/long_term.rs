@@ -2,6 +2,7 @@ use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use nekoai_config::loader::{LongTermRetentionConfig, RankingConfig};
 use nekoai_domain::agent::session::SessionKey;
 use serde_json::json;
 use tracing::{debug, info};
@@ -9,13 +10,29 @@ use uuid::Uuid;
 
 use crate::{
     embedding::Embedder,
+    keyword::document_vector,
+    ranking::{DEFAULT_IMPORTANCE, recency, record_access},
     store::MemoryEntry,
     vector_db::{
-        FilterCondition, SearchFilter, SearchResult, VectorDbClient,
+        FilterCondition, KeywordSearchRequest, SearchFilter, SearchResult, SparseVector,
+        VectorDbClient,
         qdrant::{session_kind_value, session_scope_filter},
+        scroll_all,
     },
 };
 
+/// Payload flag of facts that a newer fact replaced.
+pub const SUPERSEDED_KEY: &str = "superseded";
+
+/// A fact to store, as extracted from a conversation.
+#[derive(Debug, Clone)]
+pub struct NewFact {
+    pub content: String,
+    pub tags: Vec<String>,
+    /// Between 0 and 1.
+    pub importance: f32,
+}
+
 pub struct LongTermMemory {
     db: Arc<dyn VectorDbClient>,
     embedder: Arc<dyn Embedder>,
@@ -45,15 +62,26 @@ impl LongTermMemory {
         &self,
         session_key: &SessionKey,
         user_id: Option<&str>,
-        fact: String,
-        tags: Vec<String>,
-    ) -> Result<()> {
-        let embedding = self.embedder.embed(&fact).await;
+        fact: NewFact,
+    ) -> Result<String> {
+        let embedding = self.embedder.embed(&fact.content).await;
+        self.store_with_embedding(session_key, user_id, fact, embedding)
+            .await
+    }
+
+    /// Stores a fact whose embedding was already computed and returns its ID.
+    pub async fn store_with_embedding(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        fact: NewFact,
+        embedding: Vec<f32>,
+    ) -> Result<String> {
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().timestamp();
 
-        let mut payload = HashMap::with_capacity(7);
-        payload.insert("content".to_string(), json!(fact));
+        let mut payload = HashMap::with_capacity(8);
+        payload.insert("content".to_string(), json!(fact.content));
         payload.insert(
             "guild_id".to_string(),
             json!(session_key.guild_id.map(|g| g.to_string())),
@@ -67,7 +95,11 @@ impl LongTermMemory {
             json!(session_kind_value(&session_key.kind)),
         );
         payload.insert("created_at".to_string(), json!(now));
-        payload.insert("tags".to_string(), json!(tags));
+        payload.insert("tags".to_string(), json!(fact.tags));
+        payload.insert(
+            "importance".to_string(),
+            json!(fact.importance.clamp(0.0, 1.0)),
+        );
 
         if let Some(uid) = user_id {
             payload.insert("user_id".to_string(), json!(uid));
@@ -78,11 +110,94 @@ impl LongTermMemory {
                 collection: &self.collection,
                 id: &id,
                 vector: embedding,
+                keywords: Some(document_vector(&fact.content)),
                 payload,
             })
             .await?;
 
         debug!(id = %id, session = %session_key.channel_id, "stored long-term fact");
+        Ok(id)
+    }
+
+    /// Facts that are not superseded and at least `threshold` similar to
+    /// `embedding`, most similar first: the user's facts when `user_id` is
+    /// given, otherwise the session's.
+    pub async fn find_similar(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        embedding: &[f32],
+        threshold: f32,
+        limit: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        let filter = match user_id {
+            Some(user_id) => user_filter(user_id, None),
+            None => session_scope_filter(session_key),
+        };
+        let mut entries = self
+            .search_with_filter_embedding(embedding, filter, limit)
+            .await?;
+        entries.retain(|entry| entry.score >= threshold);
+        Ok(entries)
+    }
+
+    /// Replaces the content of the fact `id` with that of `fact`, adding its
+    /// tags and keeping the higher importance. Returns `false` when the fact no
+    /// longer exists.
+    pub async fn merge(&self, id: &str, fact: NewFact, embedding: Vec<f32>) -> Result<bool> {
+        let Some(existing) = self.db.get(&self.collection, id).await? else {
+            return Ok(false);
+        };
+
+        let mut payload = existing.payload;
+        let mut merged_tags: Vec<String> = payload
+            .get("tags")
+            .and_then(|tags| serde_json::from_value(tags.clone()).ok())
+            .unwrap_or_default();
+        for tag in fact.tags {
+            if !merged_tags.contains(&tag) {
+                merged_tags.push(tag);
+            }
+        }
+        let importance = payload
+            .get("importance")
+            .and_then(|importance| importance.as_f64())
+            .map_or(DEFAULT_IMPORTANCE, |importance| importance as f32)
+            .max(fact.importance.clamp(0.0, 1.0));
+        let keywords = document_vector(&fact.content);
+        payload.insert("content".to_string(), json!(fact.content));
+        payload.insert("tags".to_string(), json!(merged_tags));
+        payload.insert("importance".to_string(), json!(importance));
+        payload.insert("updated_at".to_string(), json!(Utc::now().timestamp()));
+
+        self.db
+            .upsert(crate::vector_db::UpsertRequest {
+                collection: &self.collection,
+                id,
+                vector: embedding,
+                keywords: Some(keywords),
+                payload,
+            })
+            .await?;
+
+        debug!(id = %id, "merged long-term fact");
+        Ok(true)
+    }
+
+    /// Marks facts as replaced by the fact `by`. They stay stored, and listed,
+    /// but are left out of searches.
+    pub async fn mark_superseded(&self, ids: &[String], by: &str) -> Result<()> {
+        let now = Utc::now().timestamp();
+        for id in ids {
+            let payload = HashMap::from([
+                (SUPERSEDED_KEY.to_string(), json!(true)),
+                ("superseded_by".to_string(), json!(by)),
+                ("superseded_at".to_string(), json!(now)),
+            ]);
+            self.db.set_payload(&self.collection, id, payload).await?;
+        }
+
+        debug!(superseded = ids.len(), by = %by, "marked long-term facts as superseded");
         Ok(())
     }
 
@@ -99,6 +214,7 @@ impl LongTermMemory {
                 value: json!(guild_id),
             }],
             should: vec![],
+            must_not: vec![],
         };
 
         self.search_with_filter(query, filter, top_k).await
@@ -117,11 +233,62 @@ impl LongTermMemory {
                 value: json!(user_id),
             }],
             should: vec![],
+            must_not: vec![],
         };
 
         self.search_with_filter(query, filter, top_k).await
     }
 
+    /// Search long-term memories of a user within a guild (for admins)
+    pub async fn search_by_user_in_guild(
+        &self,
+        user_id: &str,
+        guild_id: &str,
+        query: &str,
+        top_k: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        self.search_with_filter(query, user_filter(user_id, Some(guild_id)), top_k)
+            .await
+    }
+
+    /// Facts about a user, newest first, at most `limit`. `guild_id` narrows
+    /// them to the facts learned in that guild.
+    pub async fn list_by_user(
+        &self,
+        user_id: &str,
+        guild_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        // Scrolling returns points in no particular order, so all of the user's
+        // facts are read before the newest are picked. `evict` caps how many
+        // there are.
+        let points = scroll_all(
+            self.db.as_ref(),
+            &self.collection,
+            user_filter(user_id, guild_id),
+            usize::MAX,
+        )
+        .await?;
+
+        let mut entries: Vec<_> = points.into_iter().map(search_result_to_entry).collect();
+        // By ID among facts of the same second, so that pages do not overlap.
+        entries.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<MemoryEntry>> {
+        Ok(self
+            .db
+            .get(&self.collection, id)
+            .await?
+            .map(search_result_to_entry))
+    }
+
     pub async fn search(
         &self,
         session_key: &SessionKey,
@@ -145,6 +312,29 @@ impl LongTermMemory {
             .await
     }
 
+    /// Facts of the session matching `keywords` by BM25, best first. Superseded
+    /// facts are left out.
+    pub async fn search_keywords(
+        &self,
+        session_key: &SessionKey,
+        keywords: &SparseVector,
+        top_k: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        let mut filter = session_scope_filter(session_key);
+        filter.must_not.push(superseded_condition());
+        let results = self
+            .db
+            .keyword_search(KeywordSearchRequest {
+                collection: &self.collection,
+                query: keywords.clone(),
+                filter: Some(filter),
+                top_k,
+            })
+            .await?;
+
+        Ok(results.into_iter().map(search_result_to_entry).collect())
+    }
+
     async fn search_with_filter(
         &self,
         query: &str,
@@ -159,9 +349,10 @@ impl LongTermMemory {
     async fn search_with_filter_embedding(
         &self,
         embedding: &[f32],
-        filter: SearchFilter,
+        mut filter: SearchFilter,
         top_k: usize,
     ) -> Result<Vec<MemoryEntry>> {
+        filter.must_not.push(superseded_condition());
         let results = self
             .db
             .search(crate::vector_db::SearchRequest {
@@ -175,12 +366,116 @@ impl LongTermMemory {
         Ok(results.into_iter().map(search_result_to_entry).collect())
     }
 
+    pub async fn record_access(&self, entries: &[MemoryEntry]) -> Result<()> {
+        record_access(self.db.as_ref(), &self.collection, entries).await
+    }
+
+    /// Deletes facts that are no longer worth keeping and returns how many:
+    /// facts idle for `max_idle_days` unless important enough (superseded
+    /// facts always are not), then the lowest ranked facts of every user with
+    /// more than `max_facts_per_user`.
+    pub async fn evict(
+        &self,
+        retention: &LongTermRetentionConfig,
+        ranking: &RankingConfig,
+    ) -> Result<u64> {
+        if retention.max_idle_days == 0 && retention.max_facts_per_user == 0 {
+            return Ok(0);
+        }
+
+        let now = Utc::now();
+        let points = scroll_all(
+            self.db.as_ref(),
+            &self.collection,
+            SearchFilter::default(),
+            usize::MAX,
+        )
+        .await?;
+
+        // What a fact is worth keeping for, regardless of any query.
+        let worth = |entry: &MemoryEntry| {
+            if entry.metadata.get(SUPERSEDED_KEY) == Some(&json!(true)) {
+                return None;
+            }
+            Some(
+                ranking.recency_weight * recency(entry, now, ranking.recency_half_life_days)
+                    + ranking.importance_weight * entry.importance,
+            )
+        };
+
+        let mut evicted = Vec::new();
+        let mut by_owner: HashMap<String, Vec<(Option<f32>, String)>> = HashMap::new();
+        for entry in points.into_iter().map(search_result_to_entry) {
+            let worth = worth(&entry);
+            if retention.max_idle_days > 0 {
+                let last_active = entry
+                    .last_accessed_at
+                    .map_or(entry.created_at, |accessed| accessed.max(entry.created_at));
+                let idle = (now - last_active).num_days() >= retention.max_idle_days as i64;
+                if idle && (worth.is_none() || entry.importance < retention.keep_importance) {
+                    evicted.push(entry.id);
+                    continue;
+                }
+            }
+
+            let owner = match metadata_str(&entry, "user_id") {
+                Some(user_id) => format!("user:{user_id}"),
+                None => format!(
+                    "channel:{}",
+                    metadata_str(&entry, "channel_id").unwrap_or_default()
+                ),
+            };
+            by_owner.entry(owner).or_default().push((worth, entry.id));
+        }
+
+        if retention.max_facts_per_user > 0 {
+            for facts in by_owner.values_mut() {
+                if facts.len() <= retention.max_facts_per_user {
+                    continue;
+                }
+                // Superseded facts (`None`) sort first.
+                facts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                let excess = facts.len() - retention.max_facts_per_user;
+                evicted.extend(facts.drain(.. excess).map(|(_, id)| id));
+            }
+        }
+
+        for id in &evicted {
+            self.db.delete(&self.collection, id).await?;
+        }
+
+        if !evicted.is_empty() {
+            info!(evicted = evicted.len(), "evicted long-term facts");
+        }
+
+        Ok(evicted.len() as u64)
+    }
+
     pub async fn delete(&self, id: &str) -> Result<()> {
         self.db.delete(&self.collection, id).await?;
         debug!(id = %id, "deleted long-term fact");
         Ok(())
     }
 
+    /// Deletes the facts about a user, only those learned in `guild_id` if given.
+    pub async fn delete_by_user(&self, user_id: &str, guild_id: Option<&str>) -> Result<u64> {
+        let deleted = self
+            .db
+            .delete_by_filter(&self.collection, user_filter(user_id, guild_id))
+            .await?;
+
+        if deleted > 0 {
+            info!(
+                user_id = user_id,
+                guild_id = ?guild_id,
+                deleted = deleted,
+                "cleared long-term memories for user"
+            );
+        }
+
+        Ok(deleted)
+    }
+
     pub async fn delete_by_channel(&self, channel_id: &str) -> Result<u64> {
         let filter = SearchFilter {
             must: vec![FilterCondition::Match {
@@ -188,6 +483,7 @@ impl LongTermMemory {
                 value: json!(channel_id),
             }],
             should: vec![],
+            must_not: vec![],
         };
 
         let deleted = self.db.delete_by_filter(&self.collection, filter).await?;
@@ -204,6 +500,37 @@ impl LongTermMemory {
     }
 }
 
+fn user_filter(user_id: &str, guild_id: Option<&str>) -> SearchFilter {
+    let mut must = vec![FilterCondition::Match {
+        key: "user_id".to_string(),
+        value: json!(user_id),
+    }];
+    if let Some(guild_id) = guild_id {
+        must.push(FilterCondition::Match {
+            key: "guild_id".to_string(),
+            value: json!(guild_id),
+        });
+    }
+
+    SearchFilter {
+        must,
+        should: vec![],
+        must_not: vec![],
+    }
+}
+
+/// Matches facts that a newer fact replaced.
+fn superseded_condition() -> FilterCondition {
+    FilterCondition::Match {
+        key: SUPERSEDED_KEY.to_string(),
+        value: json!(true),
+    }
+}
+
+fn metadata_str<'a>(entry: &'a MemoryEntry, key: &str) -> Option<&'a str> {
+    entry.metadata.get(key).and_then(|value| value.as_str())
+}
+
 pub(crate) fn search_result_to_entry(r: SearchResult) -> MemoryEntry {
     let content = r
         .payload
@@ -211,17 +538,33 @@ pub(crate) fn search_result_to_entry(r: SearchResult) -> MemoryEntry {
         .and_then(|v| v.as_str())
         .unwrap_or("")
         .to_string();
-    let created_at = r
+    let timestamp = |key: &str| {
+        r.payload
+            .get(key)
+            .and_then(|v| v.as_i64())
+            .and_then(|ts| DateTime::from_timestamp(ts, 0))
+    };
+    let created_at = timestamp("created_at").unwrap_or_default();
+    let last_accessed_at = timestamp("last_accessed_at");
+    let importance = r
+        .payload
+        .get("importance")
+        .and_then(|v| v.as_f64())
+        .map_or(DEFAULT_IMPORTANCE, |importance| importance as f32);
+    let access_count = r
         .payload
-        .get("created_at")
-        .and_then(|v| v.as_i64())
-        .and_then(|ts| DateTime::from_timestamp(ts, 0))
-        .unwrap_or_default();
+        .get("access_count")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
 
     MemoryEntry {
+        id: r.id,
         content,
         score: r.score,
         created_at,
+        importance,
+        last_accessed_at,
+        access_count,
         metadata: r.payload,
     }
 }
@@ -9,12 +9,15 @@ use uuid::Uuid;
 
 use crate::{
     embedding::Embedder,
+    keyword::document_vector,
     long_term::search_result_to_entry,
+    ranking::record_access,
     short_term::ShortTermEntry,
     store::MemoryEntry,
     vector_db::{
-        FilterCondition, SearchFilter, VectorDbClient,
+        FilterCondition, KeywordSearchRequest, SearchFilter, SparseVector, VectorDbClient,
         qdrant::{session_kind_value, session_scope_filter},
+        scroll_all,
     },
 };
 
@@ -57,10 +60,19 @@ impl MidTermMemory {
         summary: String,
     ) -> Result<()> {
         let embedding = self.embedder.embed(&summary).await;
+        let keywords = document_vector(&summary);
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().timestamp();
 
-        let mut payload = HashMap::with_capacity(6);
+        // Users who took part, so that their summaries can be exported or erased.
+        let mut user_ids: Vec<&str> = Vec::new();
+        for user_id in messages.iter().filter_map(|entry| entry.user_id.as_deref()) {
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+
+        let mut payload = HashMap::with_capacity(7);
         payload.insert("content".to_string(), json!(summary));
         payload.insert(
             "guild_id".to_string(),
@@ -76,12 +88,14 @@ impl MidTermMemory {
         );
         payload.insert("created_at".to_string(), json!(now));
         payload.insert("message_count".to_string(), json!(messages.len()));
+        payload.insert("user_ids".to_string(), json!(user_ids));
 
         self.db
             .upsert(crate::vector_db::UpsertRequest {
                 collection: &self.collection,
                 id: &id,
                 vector: embedding,
+                keywords: Some(keywords),
                 payload,
             })
             .await?;
@@ -123,6 +137,63 @@ impl MidTermMemory {
         Ok(results.into_iter().map(search_result_to_entry).collect())
     }
 
+    /// Summaries of the session matching `keywords` by BM25, best first.
+    pub async fn search_keywords(
+        &self,
+        session_key: &SessionKey,
+        keywords: &SparseVector,
+        top_k: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        let results = self
+            .db
+            .keyword_search(KeywordSearchRequest {
+                collection: &self.collection,
+                query: keywords.clone(),
+                filter: Some(session_scope_filter(session_key)),
+                top_k,
+            })
+            .await?;
+
+        Ok(results.into_iter().map(search_result_to_entry).collect())
+    }
+
+    pub async fn record_access(&self, entries: &[MemoryEntry]) -> Result<()> {
+        record_access(self.db.as_ref(), &self.collection, entries).await
+    }
+
+    /// Summaries of conversations `user_id` took part in, newest first.
+    pub async fn list_by_user(&self, user_id: &str) -> Result<Vec<MemoryEntry>> {
+        let points = scroll_all(
+            self.db.as_ref(),
+            &self.collection,
+            user_filter(user_id),
+            usize::MAX,
+        )
+        .await?;
+
+        let mut entries: Vec<_> = points.into_iter().map(search_result_to_entry).collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.created_at));
+        Ok(entries)
+    }
+
+    /// Deletes the summaries of conversations `user_id` took part in.
+    pub async fn delete_by_user(&self, user_id: &str) -> Result<u64> {
+        let deleted = self
+            .db
+            .delete_by_filter(&self.collection, user_filter(user_id))
+            .await?;
+
+        if deleted > 0 {
+            info!(
+                user_id = user_id,
+                deleted = deleted,
+                "cleared mid-term summaries for user"
+            );
+        }
+
+        Ok(deleted)
+    }
+
     pub async fn delete_old_entries(&self) -> Result<u64> {
         let cutoff = Utc::now().timestamp() - (self.retention_days as i64 * 24 * 60 * 60);
         let filter = SearchFilter {
@@ -132,6 +203,7 @@ impl MidTermMemory {
                 gt: None,
             }],
             should: vec![],
+            must_not: vec![],
         };
 
         let deleted = self.db.delete_by_filter(&self.collection, filter).await?;
@@ -143,3 +215,15 @@ impl MidTermMemory {
         Ok(deleted)
     }
 }
+
+/// Matches summaries whose `user_ids` contain `user_id`.
+fn user_filter(user_id: &str) -> SearchFilter {
+    SearchFilter {
+        must: vec![FilterCondition::Match {
+            key: "user_ids".to_string(),
+            value: json!(user_id),
+        }],
+        should: vec![],
+        must_not: vec![],
+    }
+}
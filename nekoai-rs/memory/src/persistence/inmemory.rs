@@ -0,0 +1,273 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use dashmap::DashMap;
+use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::usage::{UsageRecord, UsageStore};
+
+use super::{
+    ChannelPersonaStore, ConversationStore, ExtractionJob, ExtractionQueue, NewExtractionJob,
+    RateLimitStore, StoredSession,
+};
+use crate::short_term::ShortTermEntry;
+
+/// Keeps everything in process memory; nothing survives a restart.
+pub struct InMemoryConversationStore {
+    sessions: DashMap<SessionKey, StoredSession>,
+    short_term: DashMap<SessionKey, Vec<ShortTermEntry>>,
+    extraction_jobs: Mutex<ExtractionJobs>,
+    channel_personas: DashMap<u64, String>,
+    guild_messages: DashMap<(u64, NaiveDate), u64>,
+}
+
+#[derive(Default)]
+struct ExtractionJobs {
+    next_id: i64,
+    /// Jobs with the time they become due, in insertion order.
+    jobs: Vec<(ExtractionJob, DateTime<Utc>)>,
+}
+
+impl InMemoryConversationStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: DashMap::new(),
+            short_term: DashMap::new(),
+            extraction_jobs: Mutex::new(ExtractionJobs::default()),
+            channel_personas: DashMap::new(),
+            guild_messages: DashMap::new(),
+        }
+    }
+
+    fn extraction_jobs(&self) -> std::sync::MutexGuard<'_, ExtractionJobs> {
+        self.extraction_jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for InMemoryConversationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ConversationStore for InMemoryConversationStore {
+    async fn load_session(&self, session_key: &SessionKey) -> Result<Option<StoredSession>> {
+        Ok(self
+            .sessions
+            .get(session_key)
+            .map(|entry| entry.value().clone()))
+    }
+
+    async fn save_session(&self, session_key: &SessionKey, session: &StoredSession) -> Result<()> {
+        self.sessions.insert(session_key.clone(), session.clone());
+        Ok(())
+    }
+
+    async fn delete_session(&self, session_key: &SessionKey) -> Result<()> {
+        self.sessions.remove(session_key);
+        Ok(())
+    }
+
+    async fn list_session_keys(&self) -> Result<Vec<SessionKey>> {
+        let mut keys: Vec<SessionKey> = self
+            .sessions
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for entry in self.short_term.iter() {
+            if !keys.contains(entry.key()) {
+                keys.push(entry.key().clone());
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn list_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<Vec<SessionKey>> {
+        Ok(self
+            .sessions
+            .iter()
+            .filter(|entry| entry.value().last_active < idle_since)
+            .map(|entry| entry.key().clone())
+            .collect())
+    }
+
+    async fn load_short_term(&self, session_key: &SessionKey) -> Result<Vec<ShortTermEntry>> {
+        Ok(self
+            .short_term
+            .get(session_key)
+            .map(|entry| entry.value().clone())
+            .unwrap_or_default())
+    }
+
+    async fn save_short_term(
+        &self,
+        session_key: &SessionKey,
+        entries: &[ShortTermEntry],
+    ) -> Result<()> {
+        self.short_term
+            .insert(session_key.clone(), entries.to_vec());
+        Ok(())
+    }
+
+    async fn delete_short_term(&self, session_key: &SessionKey) -> Result<()> {
+        self.short_term.remove(session_key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChannelPersonaStore for InMemoryConversationStore {
+    async fn load_channel_personas(&self) -> Result<Vec<(u64, String)>> {
+        Ok(self
+            .channel_personas
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect())
+    }
+
+    async fn save_channel_persona(&self, channel_id: u64, persona: Option<&str>) -> Result<()> {
+        match persona {
+            Some(persona) => {
+                self.channel_personas
+                    .insert(channel_id, persona.to_string());
+            }
+            None => {
+                self.channel_personas.remove(&channel_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryConversationStore {
+    async fn load_guild_message_counts(&self, day: NaiveDate) -> Result<Vec<(u64, u64)>> {
+        Ok(self
+            .guild_messages
+            .iter()
+            .filter(|entry| entry.key().1 == day)
+            .map(|entry| (entry.key().0, *entry.value()))
+            .collect())
+    }
+
+    async fn add_guild_message(&self, guild_id: u64, day: NaiveDate) -> Result<()> {
+        *self.guild_messages.entry((guild_id, day)).or_default() += 1;
+        Ok(())
+    }
+
+    async fn delete_guild_message_counts_before(&self, day: NaiveDate) -> Result<u64> {
+        let before = self.guild_messages.len();
+        self.guild_messages
+            .retain(|(_, counted_on), _| *counted_on >= day);
+        Ok((before - self.guild_messages.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl ExtractionQueue for InMemoryConversationStore {
+    async fn push(&self, job: NewExtractionJob) -> Result<()> {
+        let mut queue = self.extraction_jobs();
+        queue.next_id += 1;
+        let id = queue.next_id;
+        queue.jobs.push((
+            ExtractionJob {
+                id,
+                session_key: job.session_key,
+                user_ids: job.user_ids,
+                conversation: job.conversation,
+                attempts: 0,
+            },
+            Utc::now(),
+        ));
+        Ok(())
+    }
+
+    async fn claim_next(&self, lease_until: DateTime<Utc>) -> Result<Option<ExtractionJob>> {
+        let now = Utc::now();
+        let mut queue = self.extraction_jobs();
+        Ok(queue
+            .jobs
+            .iter_mut()
+            .find(|(_, due)| *due <= now)
+            .map(|(job, due)| {
+                *due = lease_until;
+                job.clone()
+            }))
+    }
+
+    async fn complete(&self, id: i64) -> Result<()> {
+        self.extraction_jobs().jobs.retain(|(job, _)| job.id != id);
+        Ok(())
+    }
+
+    async fn reschedule(
+        &self,
+        id: i64,
+        attempts: u32,
+        next_attempt_at: DateTime<Utc>,
+        _last_error: &str,
+    ) -> Result<()> {
+        if let Some((job, due)) = self
+            .extraction_jobs()
+            .jobs
+            .iter_mut()
+            .find(|(job, _)| job.id == id)
+        {
+            job.attempts = attempts;
+            *due = next_attempt_at;
+        }
+        Ok(())
+    }
+
+    async fn depth(&self) -> Result<usize> {
+        Ok(self.extraction_jobs().jobs.len())
+    }
+
+    async fn list_user_jobs(&self, user_id: &str) -> Result<Vec<ExtractionJob>> {
+        Ok(self
+            .extraction_jobs()
+            .jobs
+            .iter()
+            .filter(|(job, _)| job.user_ids.iter().any(|id| id == user_id))
+            .map(|(job, _)| job.clone())
+            .collect())
+    }
+
+    async fn delete_user_jobs(&self, user_id: &str) -> Result<u64> {
+        let mut queue = self.extraction_jobs();
+        let before = queue.jobs.len();
+        queue
+            .jobs
+            .retain(|(job, _)| !job.user_ids.iter().any(|id| id == user_id));
+        Ok((before - queue.jobs.len()) as u64)
+    }
+}
+
+/// The usage ledger already holds everything in memory and nothing survives a
+/// restart, so usage is not kept a second time.
+#[async_trait]
+impl UsageStore for InMemoryConversationStore {
+    async fn add_usage(&self, _records: &[UsageRecord]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_usage(&self, _since: NaiveDate) -> Result<Vec<UsageRecord>> {
+        Ok(Vec::new())
+    }
+
+    async fn delete_usage_before(&self, _day: NaiveDate) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn list_user_usage(&self, _user_id: u64) -> Result<Vec<UsageRecord>> {
+        Ok(Vec::new())
+    }
+
+    async fn delete_user_usage(&self, _user_id: u64) -> Result<u64> {
+        Ok(0)
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use nekoai_config::loader::{PersistenceBackend, PersistenceConfig};
+use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::usage::UsageStore;
+use serde::{Deserialize, Serialize};
+
+use crate::short_term::ShortTermEntry;
+
+pub mod inmemory;
+pub mod sqlite;
+
+/// Durable storage for conversation sessions and short-term memory, so that
+/// both survive a restart. Mid-term and long-term memory live in the vector DB.
+#[async_trait]
+pub trait ConversationStore: Send + Sync {
+    async fn load_session(&self, session_key: &SessionKey) -> Result<Option<StoredSession>>;
+    async fn save_session(&self, session_key: &SessionKey, session: &StoredSession) -> Result<()>;
+    async fn delete_session(&self, session_key: &SessionKey) -> Result<()>;
+    /// Sessions with stored history or short-term entries.
+    async fn list_session_keys(&self) -> Result<Vec<SessionKey>>;
+    /// Sessions whose `last_active` is older than `idle_since`.
+    async fn list_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<Vec<SessionKey>>;
+    async fn load_short_term(&self, session_key: &SessionKey) -> Result<Vec<ShortTermEntry>>;
+    async fn save_short_term(
+        &self,
+        session_key: &SessionKey,
+        entries: &[ShortTermEntry],
+    ) -> Result<()>;
+    async fn delete_short_term(&self, session_key: &SessionKey) -> Result<()>;
+}
+
+/// Durable persona picks made for channels with `/persona`.
+#[async_trait]
+pub trait ChannelPersonaStore: Send + Sync {
+    /// Every pick, as `(channel_id, persona)`.
+    async fn load_channel_personas(&self) -> Result<Vec<(u64, String)>>;
+    /// Picks `persona` for the channel, or removes the pick when `None`.
+    async fn save_channel_persona(&self, channel_id: u64, persona: Option<&str>) -> Result<()>;
+}
+
+/// Durable queue of long-term extraction jobs. Jobs stay in the queue until
+/// they are completed, so a crash or restart only delays them.
+#[async_trait]
+pub trait ExtractionQueue: Send + Sync {
+    async fn push(&self, job: NewExtractionJob) -> Result<()>;
+    /// Takes the oldest due job and hides it until `lease_until`, after which it
+    /// becomes due again unless it was completed or rescheduled.
+    async fn claim_next(&self, lease_until: DateTime<Utc>) -> Result<Option<ExtractionJob>>;
+    async fn complete(&self, id: i64) -> Result<()>;
+    async fn reschedule(
+        &self,
+        id: i64,
+        attempts: u32,
+        next_attempt_at: DateTime<Utc>,
+        last_error: &str,
+    ) -> Result<()>;
+    async fn depth(&self) -> Result<usize>;
+    /// Queued jobs for conversations `user_id` took part in.
+    async fn list_user_jobs(&self, user_id: &str) -> Result<Vec<ExtractionJob>>;
+    /// Drops the queued jobs for conversations `user_id` took part in and returns how many there were.
+    async fn delete_user_jobs(&self, user_id: &str) -> Result<u64>;
+}
+
+/// Durable request counts of the guild daily quotas, so that a restart doesn't
+/// reset them.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Requests admitted per guild on `day`, as `(guild_id, count)`.
+    async fn load_guild_message_counts(&self, day: NaiveDate) -> Result<Vec<(u64, u64)>>;
+    /// Counts one more admitted request of the guild on `day`.
+    async fn add_guild_message(&self, guild_id: u64, day: NaiveDate) -> Result<()>;
+    /// Drops the request counts of the days before `day`.
+    async fn delete_guild_message_counts_before(&self, day: NaiveDate) -> Result<u64>;
+}
+
+#[derive(Debug, Clone)]
+pub struct NewExtractionJob {
+    pub session_key: SessionKey,
+    /// Users who took part in the conversation, in order of appearance.
+    pub user_ids: Vec<String>,
+    pub conversation: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractionJob {
+    pub id: i64,
+    pub session_key: SessionKey,
+    /// Users who took part in the conversation, in order of appearance.
+    pub user_ids: Vec<String>,
+    pub conversation: String,
+    /// Failed attempts so far.
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredSession {
+    pub turns: Vec<StoredTurn>,
+    pub summary: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTurn {
+    pub user: String,
+    pub assistant: String,
+    /// Turns stored before this was recorded have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+/// All stores are backed by the same database.
+#[derive(Clone)]
+pub struct PersistentStores {
+    pub conversations: Arc<dyn ConversationStore>,
+    pub channel_personas: Arc<dyn ChannelPersonaStore>,
+    pub extraction_queue: Arc<dyn ExtractionQueue>,
+    pub rate_limits: Arc<dyn RateLimitStore>,
+    pub usage: Arc<dyn UsageStore>,
+}
+
+impl PersistentStores {
+    pub fn in_memory() -> Self {
+        let store = Arc::new(inmemory::InMemoryConversationStore::new());
+        Self {
+            conversations: store.clone(),
+            channel_personas: store.clone(),
+            extraction_queue: store.clone(),
+            rate_limits: store.clone(),
+            usage: store,
+        }
+    }
+}
+
+pub fn build_stores(config: &PersistenceConfig) -> Result<PersistentStores> {
+    match config.backend {
+        PersistenceBackend::Sqlite => {
+            let store = Arc::new(
+                sqlite::SqliteConversationStore::open(&config.sqlite_path)
+                    .with_context(|| format!("failed to open {}", config.sqlite_path))?,
+            );
+            Ok(PersistentStores {
+                conversations: store.clone(),
+                channel_personas: store.clone(),
+                extraction_queue: store.clone(),
+                rate_limits: store.clone(),
+                usage: store,
+            })
+        }
+        PersistenceBackend::InMemory => Ok(PersistentStores::in_memory()),
+    }
+}
+
+/// Stable string form of a session key, used as the primary key on disk.
+pub(crate) fn storage_key(session_key: &SessionKey) -> Result<String> {
+    serde_json::to_string(session_key).context("failed to serialize session key")
+}
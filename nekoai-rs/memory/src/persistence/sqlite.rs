@@ -0,0 +1,774 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::usage::{UsageRecord, UsageStore};
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::{info, warn};
+
+use super::{
+    ChannelPersonaStore, ConversationStore, ExtractionJob, ExtractionQueue, NewExtractionJob,
+    RateLimitStore, StoredSession, StoredTurn, storage_key,
+};
+use crate::short_term::ShortTermEntry;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    session_key TEXT PRIMARY KEY,
+    turns TEXT NOT NULL,
+    summary TEXT,
+    created_at INTEGER NOT NULL,
+    last_active INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS short_term_entries (
+    session_key TEXT PRIMARY KEY,
+    entries TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS extraction_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_key TEXT NOT NULL,
+    user_ids TEXT NOT NULL DEFAULT '[]',
+    conversation TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS extraction_queue_due ON extraction_queue (next_attempt_at);
+CREATE TABLE IF NOT EXISTS channel_personas (
+    channel_id TEXT PRIMARY KEY,
+    persona TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS guild_daily_messages (
+    guild_id TEXT NOT NULL,
+    day TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, day)
+);
+CREATE TABLE IF NOT EXISTS model_usage (
+    day TEXT NOT NULL,
+    model TEXT NOT NULL,
+    purpose TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    guild_id TEXT NOT NULL,
+    session_key TEXT NOT NULL,
+    calls INTEGER NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    cached_input_tokens INTEGER NOT NULL,
+    cost REAL NOT NULL,
+    PRIMARY KEY (day, model, purpose, user_id, guild_id, session_key)
+);
+";
+
+/// Stands for a missing user, guild or session in `model_usage`, whose key
+/// columns can't be NULL because NULLs never conflict.
+const NO_VALUE: &str = "";
+
+/// Single-file SQLite store. Every call runs on the blocking thread pool.
+///
+/// Only one process can open the database at a time: the running bot caches
+/// sessions and pending extractions, so changes made behind its back (such as
+/// `neko user-data erase`) would be overwritten or re-extracted.
+pub struct SqliteConversationStore {
+    conn: Arc<Mutex<Connection>>,
+    /// Released when the store is dropped.
+    _lock: File,
+}
+
+impl SqliteConversationStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        let lock = lock_database(path)?;
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)
+            .context("failed to create conversation tables")?;
+        migrate_extraction_queue(&conn).context("failed to migrate extraction_queue")?;
+
+        info!(path = %path.display(), "sqlite conversation store opened");
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            _lock: lock,
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&conn)
+        })
+        .await
+        .map_err(|e| anyhow!("sqlite task panicked: {e}"))?
+    }
+}
+
+#[async_trait]
+impl ConversationStore for SqliteConversationStore {
+    async fn load_session(&self, session_key: &SessionKey) -> Result<Option<StoredSession>> {
+        let key = storage_key(session_key)?;
+        self.with_conn(move |conn| {
+            let row = conn
+                .query_row(
+                    "SELECT turns, summary, created_at, last_active FROM sessions WHERE session_key = ?1",
+                    params![key],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, i64>(2)?,
+                            row.get::<_, i64>(3)?,
+                        ))
+                    },
+                )
+                .optional()?;
+
+            let Some((turns, summary, created_at, last_active)) = row else {
+                return Ok(None);
+            };
+            let turns: Vec<StoredTurn> =
+                serde_json::from_str(&turns).context("failed to decode stored turns")?;
+
+            Ok(Some(StoredSession {
+                turns,
+                summary,
+                created_at: from_millis(created_at),
+                last_active: from_millis(last_active),
+            }))
+        })
+        .await
+    }
+
+    async fn save_session(&self, session_key: &SessionKey, session: &StoredSession) -> Result<()> {
+        let key = storage_key(session_key)?;
+        let turns = serde_json::to_string(&session.turns)?;
+        let summary = session.summary.clone();
+        let created_at = session.created_at.timestamp_millis();
+        let last_active = session.last_active.timestamp_millis();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO sessions (session_key, turns, summary, created_at, last_active)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(session_key) DO UPDATE SET
+                     turns = excluded.turns,
+                     summary = excluded.summary,
+                     last_active = excluded.last_active",
+                params![key, turns, summary, created_at, last_active],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_session(&self, session_key: &SessionKey) -> Result<()> {
+        let key = storage_key(session_key)?;
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM sessions WHERE session_key = ?1", params![key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<Vec<SessionKey>> {
+        let idle_since = idle_since.timestamp_millis();
+        let keys = self
+            .with_conn(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT session_key FROM sessions WHERE last_active < ?1")?;
+                let keys = stmt
+                    .query_map(params![idle_since], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(keys)
+            })
+            .await?;
+
+        Ok(decode_session_keys(&keys))
+    }
+
+    async fn list_session_keys(&self) -> Result<Vec<SessionKey>> {
+        let keys = self
+            .with_conn(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT session_key FROM sessions UNION SELECT session_key FROM short_term_entries",
+                )?;
+                let keys = stmt
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(keys)
+            })
+            .await?;
+
+        Ok(decode_session_keys(&keys))
+    }
+
+    async fn load_short_term(&self, session_key: &SessionKey) -> Result<Vec<ShortTermEntry>> {
+        let key = storage_key(session_key)?;
+        self.with_conn(move |conn| {
+            let entries = conn
+                .query_row(
+                    "SELECT entries FROM short_term_entries WHERE session_key = ?1",
+                    params![key],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+
+            match entries {
+                Some(entries) => serde_json::from_str(&entries)
+                    .context("failed to decode stored short-term entries"),
+                None => Ok(Vec::new()),
+            }
+        })
+        .await
+    }
+
+    async fn save_short_term(
+        &self,
+        session_key: &SessionKey,
+        entries: &[ShortTermEntry],
+    ) -> Result<()> {
+        let key = storage_key(session_key)?;
+        let entries = serde_json::to_string(entries)?;
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO short_term_entries (session_key, entries) VALUES (?1, ?2)
+                 ON CONFLICT(session_key) DO UPDATE SET entries = excluded.entries",
+                params![key, entries],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_short_term(&self, session_key: &SessionKey) -> Result<()> {
+        let key = storage_key(session_key)?;
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM short_term_entries WHERE session_key = ?1",
+                params![key],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ChannelPersonaStore for SqliteConversationStore {
+    async fn load_channel_personas(&self) -> Result<Vec<(u64, String)>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT channel_id, persona FROM channel_personas")?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut personas = Vec::with_capacity(rows.len());
+            for (channel_id, persona) in rows {
+                match channel_id.parse() {
+                    Ok(channel_id) => personas.push((channel_id, persona)),
+                    Err(_) => {
+                        warn!(channel_id = %channel_id, "skipping invalid channel persona row")
+                    }
+                }
+            }
+            Ok(personas)
+        })
+        .await
+    }
+
+    async fn save_channel_persona(&self, channel_id: u64, persona: Option<&str>) -> Result<()> {
+        let channel_id = channel_id.to_string();
+        let persona = persona.map(ToOwned::to_owned);
+        self.with_conn(move |conn| {
+            match persona {
+                Some(persona) => conn.execute(
+                    "INSERT INTO channel_personas (channel_id, persona) VALUES (?1, ?2)
+                     ON CONFLICT(channel_id) DO UPDATE SET persona = excluded.persona",
+                    params![channel_id, persona],
+                )?,
+                None => conn.execute(
+                    "DELETE FROM channel_personas WHERE channel_id = ?1",
+                    params![channel_id],
+                )?,
+            };
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for SqliteConversationStore {
+    async fn load_guild_message_counts(&self, day: NaiveDate) -> Result<Vec<(u64, u64)>> {
+        let day = day.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT guild_id, count FROM guild_daily_messages WHERE day = ?1")?;
+            let rows = stmt
+                .query_map(params![day], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut counts = Vec::with_capacity(rows.len());
+            for (guild_id, count) in rows {
+                match guild_id.parse() {
+                    Ok(guild_id) => counts.push((guild_id, count.max(0) as u64)),
+                    Err(_) => {
+                        warn!(guild_id = %guild_id, "skipping invalid guild message count row")
+                    }
+                }
+            }
+            Ok(counts)
+        })
+        .await
+    }
+
+    async fn add_guild_message(&self, guild_id: u64, day: NaiveDate) -> Result<()> {
+        let guild_id = guild_id.to_string();
+        let day = day.to_string();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO guild_daily_messages (guild_id, day, count) VALUES (?1, ?2, 1)
+                 ON CONFLICT(guild_id, day) DO UPDATE SET count = count + 1",
+                params![guild_id, day],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_guild_message_counts_before(&self, day: NaiveDate) -> Result<u64> {
+        let day = day.to_string();
+        self.with_conn(move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM guild_daily_messages WHERE day < ?1",
+                params![day],
+            )?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ExtractionQueue for SqliteConversationStore {
+    async fn push(&self, job: NewExtractionJob) -> Result<()> {
+        let key = storage_key(&job.session_key)?;
+        let now = Utc::now().timestamp_millis();
+
+        let user_ids = serde_json::to_string(&job.user_ids)?;
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO extraction_queue (session_key, user_ids, conversation, next_attempt_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?4)",
+                params![key, user_ids, job.conversation, now],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn claim_next(&self, lease_until: DateTime<Utc>) -> Result<Option<ExtractionJob>> {
+        let now = Utc::now().timestamp_millis();
+        let lease_until = lease_until.timestamp_millis();
+
+        let row = self
+            .with_conn(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                let row = tx
+                    .query_row(
+                        "SELECT id, session_key, user_ids, conversation, attempts FROM extraction_queue
+                         WHERE next_attempt_at <= ?1 ORDER BY id LIMIT 1",
+                        params![now],
+                        JobRow::read,
+                    )
+                    .optional()?;
+
+                if let Some(row) = &row {
+                    tx.execute(
+                        "UPDATE extraction_queue SET next_attempt_at = ?1 WHERE id = ?2",
+                        params![lease_until, row.id],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(row)
+            })
+            .await?;
+
+        row.map(JobRow::decode).transpose()
+    }
+
+    async fn complete(&self, id: i64) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM extraction_queue WHERE id = ?1", params![id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn reschedule(
+        &self,
+        id: i64,
+        attempts: u32,
+        next_attempt_at: DateTime<Utc>,
+        last_error: &str,
+    ) -> Result<()> {
+        let next_attempt_at = next_attempt_at.timestamp_millis();
+        let last_error = last_error.to_string();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE extraction_queue SET attempts = ?1, next_attempt_at = ?2, last_error = ?3
+                 WHERE id = ?4",
+                params![attempts, next_attempt_at, last_error, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn depth(&self) -> Result<usize> {
+        self.with_conn(|conn| {
+            let count: i64 =
+                conn.query_row("SELECT COUNT(*) FROM extraction_queue", [], |row| {
+                    row.get(0)
+                })?;
+            Ok(usize::try_from(count).unwrap_or_default())
+        })
+        .await
+    }
+
+    async fn list_user_jobs(&self, user_id: &str) -> Result<Vec<ExtractionJob>> {
+        let user_id = user_id.to_string();
+        let rows = self
+            .with_conn(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, session_key, user_ids, conversation, attempts FROM extraction_queue
+                     WHERE EXISTS (SELECT 1 FROM json_each(user_ids) WHERE value = ?1)
+                     ORDER BY id",
+                )?;
+                let rows = stmt
+                    .query_map(params![user_id], JobRow::read)?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter().map(JobRow::decode).collect()
+    }
+
+    async fn delete_user_jobs(&self, user_id: &str) -> Result<u64> {
+        let user_id = user_id.to_string();
+        self.with_conn(move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM extraction_queue
+                 WHERE EXISTS (SELECT 1 FROM json_each(user_ids) WHERE value = ?1)",
+                params![user_id],
+            )?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl UsageStore for SqliteConversationStore {
+    async fn add_usage(&self, records: &[UsageRecord]) -> Result<()> {
+        let rows = records
+            .iter()
+            .map(UsageRow::encode)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.with_conn(move |conn| {
+            let tx = conn.unchecked_transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO model_usage (day, model, purpose, user_id, guild_id, session_key,
+                         calls, input_tokens, output_tokens, cached_input_tokens, cost)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                     ON CONFLICT(day, model, purpose, user_id, guild_id, session_key) DO UPDATE SET
+                         calls = calls + excluded.calls,
+                         input_tokens = input_tokens + excluded.input_tokens,
+                         output_tokens = output_tokens + excluded.output_tokens,
+                         cached_input_tokens = cached_input_tokens + excluded.cached_input_tokens,
+                         cost = cost + excluded.cost",
+                )?;
+                for row in rows {
+                    let [calls, input_tokens, output_tokens, cached_input_tokens] = row.counts;
+                    stmt.execute(params![
+                        row.day,
+                        row.model,
+                        row.purpose,
+                        row.user_id,
+                        row.guild_id,
+                        row.session_key,
+                        calls,
+                        input_tokens,
+                        output_tokens,
+                        cached_input_tokens,
+                        row.cost
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_usage(&self, since: NaiveDate) -> Result<Vec<UsageRecord>> {
+        self.select_usage("day >= ?1", since.to_string()).await
+    }
+
+    async fn delete_usage_before(&self, day: NaiveDate) -> Result<u64> {
+        let day = day.to_string();
+        self.with_conn(move |conn| {
+            let deleted = conn.execute("DELETE FROM model_usage WHERE day < ?1", params![day])?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+
+    async fn list_user_usage(&self, user_id: u64) -> Result<Vec<UsageRecord>> {
+        self.select_usage("user_id = ?1", user_id.to_string()).await
+    }
+
+    async fn delete_user_usage(&self, user_id: u64) -> Result<u64> {
+        let user_id = user_id.to_string();
+        self.with_conn(move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM model_usage WHERE user_id = ?1",
+                params![user_id],
+            )?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+}
+
+impl SqliteConversationStore {
+    /// `model_usage` rows matching `filter`, whose only parameter is `value`.
+    async fn select_usage(&self, filter: &'static str, value: String) -> Result<Vec<UsageRecord>> {
+        let rows = self
+            .with_conn(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT day, model, purpose, user_id, guild_id, session_key,
+                         calls, input_tokens, output_tokens, cached_input_tokens, cost
+                     FROM model_usage WHERE {filter}"
+                ))?;
+                let rows = stmt
+                    .query_map(params![value], |row| {
+                        Ok(UsageRow {
+                            day: row.get(0)?,
+                            model: row.get(1)?,
+                            purpose: row.get(2)?,
+                            user_id: row.get(3)?,
+                            guild_id: row.get(4)?,
+                            session_key: row.get(5)?,
+                            counts: [row.get(6)?, row.get(7)?, row.get(8)?, row.get(9)?],
+                            cost: row.get(10)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        let mut records = Vec::with_capacity(rows.len());
+        for row in rows {
+            let day = row.day.clone();
+            match row.decode() {
+                Ok(record) => records.push(record),
+                Err(e) => warn!(day = %day, error = %e, "skipping unreadable model usage row"),
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// An `extraction_queue` row as its columns are stored.
+struct JobRow {
+    id: i64,
+    session_key: String,
+    /// JSON array.
+    user_ids: String,
+    conversation: String,
+    attempts: u32,
+}
+
+impl JobRow {
+    /// Reads `id, session_key, user_ids, conversation, attempts`.
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            session_key: row.get(1)?,
+            user_ids: row.get(2)?,
+            conversation: row.get(3)?,
+            attempts: row.get(4)?,
+        })
+    }
+
+    fn decode(self) -> Result<ExtractionJob> {
+        let id = self.id;
+        let session_key = serde_json::from_str(&self.session_key)
+            .with_context(|| format!("failed to decode session key of extraction job {id}"))?;
+        let user_ids = serde_json::from_str(&self.user_ids)
+            .with_context(|| format!("failed to decode user IDs of extraction job {id}"))?;
+        Ok(ExtractionJob {
+            id,
+            session_key,
+            user_ids,
+            conversation: self.conversation,
+            attempts: self.attempts,
+        })
+    }
+}
+
+/// A `model_usage` row as its columns are stored.
+struct UsageRow {
+    day: String,
+    model: String,
+    purpose: String,
+    user_id: String,
+    guild_id: String,
+    session_key: String,
+    /// Calls, input, output and cached input tokens.
+    counts: [i64; 4],
+    cost: f64,
+}
+
+impl UsageRow {
+    fn encode(record: &UsageRecord) -> Result<Self> {
+        Ok(Self {
+            day: record.day.to_string(),
+            model: record.model.clone(),
+            purpose: record.purpose.as_str().to_string(),
+            user_id: optional_id(record.user_id),
+            guild_id: optional_id(record.guild_id),
+            session_key: match &record.session_key {
+                Some(session_key) => storage_key(session_key)?,
+                None => NO_VALUE.to_string(),
+            },
+            counts: [
+                record.calls,
+                record.input_tokens,
+                record.output_tokens,
+                record.cached_input_tokens,
+            ]
+            .map(|count| i64::try_from(count).unwrap_or(i64::MAX)),
+            cost: record.cost,
+        })
+    }
+
+    fn decode(self) -> Result<UsageRecord> {
+        let [calls, input_tokens, output_tokens, cached_input_tokens] = self
+            .counts
+            .map(|count| u64::try_from(count).unwrap_or_default());
+        Ok(UsageRecord {
+            day: self.day.parse()?,
+            model: self.model,
+            purpose: self.purpose.parse()?,
+            user_id: parse_optional_id(&self.user_id)?,
+            guild_id: parse_optional_id(&self.guild_id)?,
+            session_key: match self.session_key.as_str() {
+                NO_VALUE => None,
+                key => Some(serde_json::from_str(key)?),
+            },
+            calls,
+            input_tokens,
+            output_tokens,
+            cached_input_tokens,
+            cost: self.cost,
+        })
+    }
+}
+
+/// Takes an exclusive lock on `<path>.lock`, failing when another process holds it.
+fn lock_database(path: &Path) -> Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open {}", lock_path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => bail!(
+            "{} is in use by another NekoAI process; stop the bot first",
+            path.display()
+        ),
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("failed to lock {}", lock_path.display()))
+        }
+    }
+}
+
+/// Jobs queued before every participant was recorded name a single `user_id`.
+fn migrate_extraction_queue(conn: &Connection) -> Result<()> {
+    let has_user_ids: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('extraction_queue') WHERE name = 'user_ids'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_user_ids {
+        conn.execute_batch(
+            "ALTER TABLE extraction_queue ADD COLUMN user_ids TEXT NOT NULL DEFAULT '[]';
+             UPDATE extraction_queue SET user_ids = json_array(user_id) WHERE user_id IS NOT NULL;",
+        )?;
+        info!("migrated extraction_queue to user_ids");
+    }
+    Ok(())
+}
+
+fn optional_id(id: Option<u64>) -> String {
+    id.map(|id| id.to_string())
+        .unwrap_or_else(|| NO_VALUE.to_string())
+}
+
+fn parse_optional_id(id: &str) -> Result<Option<u64>> {
+    match id {
+        NO_VALUE => Ok(None),
+        id => Ok(Some(id.parse()?)),
+    }
+}
+
+fn decode_session_keys(keys: &[String]) -> Vec<SessionKey> {
+    keys.iter()
+        .filter_map(|key| match serde_json::from_str(key) {
+            Ok(session_key) => Some(session_key),
+            Err(e) => {
+                warn!(key = %key, error = %e, "skipping stored session with unreadable key");
+                None
+            }
+        })
+        .collect()
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_else(Utc::now)
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use nekoai_config::loader::RankingConfig;
+use serde_json::json;
+
+use crate::{store::MemoryEntry, vector_db::VectorDbClient};
+
+/// Importance of memories stored without one: mid-term summaries and facts
+/// extracted before importance was recorded.
+pub const DEFAULT_IMPORTANCE: f32 = 0.5;
+
+const SECONDS_PER_DAY: f32 = 24.0 * 60.0 * 60.0;
+
+/// Between 0 and 1, halving every `half_life_days` since the memory was last
+/// recalled, or created if it never was.
+pub fn recency(entry: &MemoryEntry, now: DateTime<Utc>, half_life_days: f32) -> f32 {
+    if half_life_days <= 0.0 {
+        return 1.0;
+    }
+    let last_active = entry
+        .last_accessed_at
+        .map_or(entry.created_at, |accessed| accessed.max(entry.created_at));
+    let idle_days = (now - last_active).num_seconds().max(0) as f32 / SECONDS_PER_DAY;
+    0.5_f32.powf(idle_days / half_life_days)
+}
+
+/// Blend of the search score in `entry.score`, recency and importance.
+pub fn relevance(entry: &MemoryEntry, config: &RankingConfig, now: DateTime<Utc>) -> f32 {
+    config.similarity_weight * entry.score
+        + config.recency_weight * recency(entry, now, config.recency_half_life_days)
+        + config.importance_weight * entry.importance
+}
+
+/// Orders search candidates by `relevance`, most relevant first, and keeps
+/// `top_k` of them. Their `score` is left as it is.
+pub fn rank(
+    mut entries: Vec<MemoryEntry>,
+    config: &RankingConfig,
+    top_k: usize,
+) -> Vec<MemoryEntry> {
+    let now = Utc::now();
+    entries.sort_by(|a, b| relevance(b, config, now).total_cmp(&relevance(a, config, now)));
+    entries.truncate(top_k);
+    entries
+}
+
+/// Reciprocal rank fusion of result lists, each best first. An entry scores
+/// the sum of `1 / (k + rank)` over the lists it is in, divided by the best
+/// possible sum so that the score is between 0 and 1. Best first.
+pub fn reciprocal_rank_fusion(lists: Vec<Vec<MemoryEntry>>, k: f32) -> Vec<MemoryEntry> {
+    let best = lists.len() as f32 / (k + 1.0);
+    let mut fused: Vec<MemoryEntry> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for list in lists {
+        for (rank, mut entry) in list.into_iter().enumerate() {
+            let score = 1.0 / (k + rank as f32 + 1.0) / best;
+            match positions.get(&entry.id) {
+                Some(&position) => fused[position].score += score,
+                None => {
+                    positions.insert(entry.id.clone(), fused.len());
+                    entry.score = score;
+                    fused.push(entry);
+                }
+            }
+        }
+    }
+
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+/// Sets `last_accessed_at` and increments `access_count` of recalled memories.
+/// Concurrent recalls of the same memory may count once.
+pub(crate) async fn record_access(
+    db: &dyn VectorDbClient,
+    collection: &str,
+    entries: &[MemoryEntry],
+) -> Result<()> {
+    let now = Utc::now().timestamp();
+    for entry in entries {
+        let payload = HashMap::from([
+            ("last_accessed_at".to_string(), json!(now)),
+            ("access_count".to_string(), json!(entry.access_count + 1)),
+        ]);
+        db.set_payload(collection, &entry.id, payload).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::store::MemoryEntry;
+
+/// Scores how relevant recalled memories are to the message they were
+/// recalled for, more precisely than the searches that found them.
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    /// One score between 0 and 1 per candidate, in the order of `candidates`,
+    /// which is never empty.
+    async fn rerank(&self, query: &str, candidates: &[MemoryEntry]) -> Result<Vec<f32>>;
+}
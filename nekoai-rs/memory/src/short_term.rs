@@ -1,44 +1,84 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, sync::Arc};
 
+use anyhow::Result;
 use chrono::Utc;
 use dashmap::DashMap;
 use nekoai_domain::agent::session::SessionKey;
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
-#[derive(Debug, Clone)]
+use crate::persistence::ConversationStore;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Role {
     User,
     Assistant,
     Tool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShortTermEntry {
     pub role: Role,
     pub content: String,
     pub timestamp: i64,
+    /// The user who took the turn; set on both the user and the assistant entry.
+    /// Entries stored before this was recorded have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
 }
 
 pub struct ShortTermMemory {
     pub store: DashMap<SessionKey, VecDeque<ShortTermEntry>>,
     pub max_entry: usize,
+    persistence: Arc<dyn ConversationStore>,
 }
 
 impl ShortTermMemory {
-    pub fn new(max_entry: usize) -> Self {
+    pub fn new(max_entry: usize, persistence: Arc<dyn ConversationStore>) -> Self {
         Self {
             store: DashMap::new(),
             max_entry,
+            persistence,
+        }
+    }
+
+    /// Loads the persisted entries of a session the first time it is touched.
+    async fn ensure_loaded(&self, session_key: &SessionKey) {
+        if self.store.contains_key(session_key) {
+            return;
+        }
+
+        let entries = self
+            .persistence
+            .load_short_term(session_key)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(session = %session_key.channel_id, error = %e, "failed to load short-term memory");
+                Vec::new()
+            });
+        if !entries.is_empty() {
+            debug!(session = %session_key.channel_id, entry_count = entries.len(), "loaded persisted short-term memory");
         }
+
+        self.store
+            .entry(session_key.clone())
+            .or_insert_with(|| entries.into());
     }
 
-    pub fn push_turn(&self, session_key: &SessionKey, user: &str, assistant: &str) {
+    pub async fn push_turn(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        user: &str,
+        assistant: &str,
+    ) {
         debug!(
             session = %session_key.channel_id,
             max_entry = self.max_entry,
             "storing short-term conversation turn"
         );
 
+        self.ensure_loaded(session_key).await;
         let timestamp = Utc::now().timestamp();
         let capacity = self.max_entry.max(2);
 
@@ -51,11 +91,13 @@ impl ShortTermMemory {
             role: Role::User,
             content: user.to_string(),
             timestamp,
+            user_id: user_id.map(ToOwned::to_owned),
         });
         queue.push_back(ShortTermEntry {
             role: Role::Assistant,
             content: assistant.to_string(),
             timestamp,
+            user_id: user_id.map(ToOwned::to_owned),
         });
 
         // Drain excess entries from the front in a single shot.
@@ -65,20 +107,81 @@ impl ShortTermMemory {
         }
 
         debug!(session = %session_key.channel_id, entry_count = queue.len(), "short-term memory updated");
+
+        let snapshot: Vec<_> = queue.iter().cloned().collect();
+        drop(queue);
+        if let Err(e) = self
+            .persistence
+            .save_short_term(session_key, &snapshot)
+            .await
+        {
+            warn!(session = %session_key.channel_id, error = %e, "failed to persist short-term memory");
+        }
     }
 
-    pub fn get_count(&self, session_key: &SessionKey) -> usize {
+    pub async fn get_count(&self, session_key: &SessionKey) -> usize {
+        self.ensure_loaded(session_key).await;
         self.store.get(session_key).map(|v| v.len()).unwrap_or(0)
     }
 
-    pub fn get_messages(&self, session_key: &SessionKey) -> Vec<ShortTermEntry> {
+    pub async fn get_messages(&self, session_key: &SessionKey) -> Vec<ShortTermEntry> {
+        self.ensure_loaded(session_key).await;
         self.store
             .get(session_key)
             .map(|v| v.iter().cloned().collect())
             .unwrap_or_default()
     }
 
-    pub fn clear(&self, session_key: &SessionKey) {
+    /// Entries of the turns `user_id` took in a session.
+    pub async fn get_user_messages(
+        &self,
+        session_key: &SessionKey,
+        user_id: &str,
+    ) -> Vec<ShortTermEntry> {
+        self.ensure_loaded(session_key).await;
+        self.store
+            .get(session_key)
+            .map(|v| {
+                v.iter()
+                    .filter(|entry| entry.user_id.as_deref() == Some(user_id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Removes the turns `user_id` took in a session and returns how many
+    /// entries were removed.
+    pub async fn erase_user(&self, session_key: &SessionKey, user_id: &str) -> Result<usize> {
+        self.ensure_loaded(session_key).await;
+        let Some(mut queue) = self.store.get_mut(session_key) else {
+            return Ok(0);
+        };
+
+        let before = queue.len();
+        queue.retain(|entry| entry.user_id.as_deref() != Some(user_id));
+        let removed = before - queue.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        let snapshot: Vec<_> = queue.iter().cloned().collect();
+        drop(queue);
+        if snapshot.is_empty() {
+            self.persistence.delete_short_term(session_key).await?;
+        } else {
+            self.persistence
+                .save_short_term(session_key, &snapshot)
+                .await?;
+        }
+        debug!(session = %session_key.channel_id, removed = removed, "erased user entries from short-term memory");
+        Ok(removed)
+    }
+
+    pub async fn clear(&self, session_key: &SessionKey) {
         self.store.remove(session_key);
+        if let Err(e) = self.persistence.delete_short_term(session_key).await {
+            warn!(session = %session_key.channel_id, error = %e, "failed to delete persisted short-term memory");
+        }
     }
 }
@@ -2,29 +2,47 @@ use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use nekoai_config::loader::Config as AppConfig;
+use nekoai_config::loader::{
+    Config as AppConfig, ConsolidationConfig, LongTermRetentionConfig, RankingConfig, RecallConfig,
+};
 use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::usage::UsageStore;
 use serde_json::Value;
 use tokio::time::{Duration, interval};
 use tracing::{debug, info, warn};
 
 use crate::{
+    consolidation::{Consolidation, FactConsolidator},
     embedding::Embedder,
-    long_term::LongTermMemory,
+    keyword::query_vector,
+    long_term::{LongTermMemory, NewFact},
     mid_term::MidTermMemory,
+    persistence::{
+        ChannelPersonaStore, ConversationStore, ExtractionQueue, PersistentStores, RateLimitStore,
+        build_stores,
+    },
+    ranking::{rank, reciprocal_rank_fusion},
+    rerank::Reranker,
     short_term::{ShortTermEntry, ShortTermMemory},
+    user_data::{SessionExport, ShortTermExport, UserDataErasure, UserDataExport},
+    vector_db::SparseVector,
 };
 
 pub struct MemoryStore {
     short_term_memory: ShortTermMemory,
+    stores: PersistentStores,
     mid_term: Arc<MidTermMemory>,
     long_term: Arc<LongTermMemory>,
     embedder: Arc<dyn Embedder>,
     mid_term_top_k: usize,
     long_term_top_k: usize,
+    consolidation: ConsolidationConfig,
+    ranking: RankingConfig,
+    long_term_retention: LongTermRetentionConfig,
+    recall: RecallConfig,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct RecalledMemory {
     pub mid_term: Vec<MemoryEntry>,
     pub long_term: Vec<MemoryEntry>,
@@ -32,15 +50,27 @@ pub struct RecalledMemory {
 
 #[derive(Clone, Debug)]
 pub struct MemoryEntry {
+    /// Point ID in the vector database.
+    pub id: String,
     pub content: String,
     pub score: f32,
     pub created_at: DateTime<Utc>,
+    /// Between 0 and 1, given at extraction.
+    pub importance: f32,
+    /// When the memory was last recalled into a prompt, if ever.
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    /// How many times the memory was recalled into a prompt.
+    pub access_count: u64,
     pub metadata: HashMap<String, Value>,
 }
 
 impl MemoryStore {
     pub fn new(config: &AppConfig) -> Result<Self> {
-        let short_term_memory = ShortTermMemory::new(config.memory.short_term_max_entries);
+        let stores = build_stores(&config.memory.persistence)?;
+        let short_term_memory = ShortTermMemory::new(
+            config.memory.short_term_max_entries,
+            stores.conversations.clone(),
+        );
         let vector_db = Arc::new(
             crate::vector_db::qdrant::QdrantClient::new(
                 config.memory.vector_db.url.clone(),
@@ -89,6 +119,7 @@ impl MemoryStore {
 
         Ok(Self {
             short_term_memory,
+            stores,
             mid_term: Arc::new(MidTermMemory::new(
                 vector_db.clone(),
                 embedder.clone(),
@@ -103,6 +134,10 @@ impl MemoryStore {
             embedder,
             mid_term_top_k: config.memory.mid_term_top_k,
             long_term_top_k: config.memory.long_term_top_k,
+            consolidation: config.memory.consolidation.clone(),
+            ranking: config.memory.ranking.clone(),
+            long_term_retention: config.memory.long_term_retention.clone(),
+            recall: config.memory.recall.clone(),
         })
     }
 
@@ -114,7 +149,8 @@ impl MemoryStore {
         mid_term_top_k: usize,
         long_term_top_k: usize,
     ) -> Self {
-        let short_term_memory = ShortTermMemory::new(short_term_max);
+        let stores = PersistentStores::in_memory();
+        let short_term_memory = ShortTermMemory::new(short_term_max, stores.conversations.clone());
         info!(
             short_term_max = short_term_max,
             mid_term_top_k = mid_term_top_k,
@@ -124,11 +160,16 @@ impl MemoryStore {
 
         Self {
             short_term_memory,
+            stores,
             mid_term,
             long_term,
             embedder,
             mid_term_top_k,
             long_term_top_k,
+            consolidation: ConsolidationConfig::default(),
+            ranking: RankingConfig::default(),
+            long_term_retention: LongTermRetentionConfig::default(),
+            recall: RecallConfig::default(),
         }
     }
 
@@ -139,7 +180,37 @@ impl MemoryStore {
         Ok(())
     }
 
-    pub fn push_short_term(&self, session_key: &SessionKey, user: &str, assistant: &str) {
+    /// Store shared with the session manager so that sessions persist alongside short-term memory.
+    pub fn conversation_store(&self) -> Arc<dyn ConversationStore> {
+        self.stores.conversations.clone()
+    }
+
+    /// Where the persona registry keeps the picks made with `/persona`.
+    pub fn channel_persona_store(&self) -> Arc<dyn ChannelPersonaStore> {
+        self.stores.channel_personas.clone()
+    }
+
+    pub fn extraction_queue(&self) -> Arc<dyn ExtractionQueue> {
+        self.stores.extraction_queue.clone()
+    }
+
+    /// Where the rate limiter keeps the guild daily request counts.
+    pub fn rate_limit_store(&self) -> Arc<dyn RateLimitStore> {
+        self.stores.rate_limits.clone()
+    }
+
+    /// Where the model usage totals of `Metrics` are kept across restarts.
+    pub fn usage_store(&self) -> Arc<dyn UsageStore> {
+        self.stores.usage.clone()
+    }
+
+    pub async fn push_short_term(
+        &self,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        user: &str,
+        assistant: &str,
+    ) {
         debug!(
             session = %session_key.channel_id,
             user_len = user.len(),
@@ -147,30 +218,69 @@ impl MemoryStore {
             "pushing conversation turn to short-term memory"
         );
         self.short_term_memory
-            .push_turn(session_key, user, assistant);
+            .push_turn(session_key, user_id, user, assistant)
+            .await;
     }
 
-    pub async fn recall(&self, session_key: &SessionKey, query: &str) -> RecalledMemory {
+    /// Searches both tiers by vector and, with `recall.keyword_search`, by
+    /// keywords for `ranking.candidate_multiplier` times their `top_k`
+    /// candidates each. Results below the minimum scores are dropped, the two
+    /// searches are fused by reciprocal rank, and with a `reranker` and
+    /// `recall.rerank` the candidates are rescored by it. The `top_k` most
+    /// relevant by `ranking::rank` are kept and their access is recorded in
+    /// the background.
+    pub async fn recall(
+        &self,
+        session_key: &SessionKey,
+        query: &str,
+        reranker: Option<&dyn Reranker>,
+    ) -> RecalledMemory {
         let query_embedding = self.embedder.embed(query).await;
-        let (mid_term_result, long_term_result) = tokio::join!(
+        let keywords = if self.recall.keyword_search {
+            query_vector(query)
+        } else {
+            SparseVector::default()
+        };
+        let multiplier = self.ranking.candidate_multiplier.max(1);
+        let (mid_term_top_k, long_term_top_k) = (
+            self.mid_term_top_k * multiplier,
+            self.long_term_top_k * multiplier,
+        );
+        let (mid_term_vector, long_term_vector, mid_term_keyword, long_term_keyword) = tokio::join!(
             self.mid_term
-                .search_with_embedding(session_key, &query_embedding, self.mid_term_top_k,),
-            self.long_term.search_with_embedding(
-                session_key,
-                &query_embedding,
-                self.long_term_top_k,
-            )
+                .search_with_embedding(session_key, &query_embedding, mid_term_top_k),
+            self.long_term
+                .search_with_embedding(session_key, &query_embedding, long_term_top_k),
+            self.mid_term
+                .search_keywords(session_key, &keywords, mid_term_top_k),
+            self.long_term
+                .search_keywords(session_key, &keywords, long_term_top_k)
         );
 
-        let mid_term = mid_term_result.unwrap_or_else(|e| {
-            warn!(error = %e, "failed to search mid-term memory");
-            vec![]
-        });
+        let mut mid_term = self.fuse("mid-term", mid_term_vector, mid_term_keyword);
+        let mut long_term = self.fuse("long-term", long_term_vector, long_term_keyword);
 
-        let long_term = long_term_result.unwrap_or_else(|e| {
-            warn!(error = %e, "failed to search long-term memory");
-            vec![]
-        });
+        if let Some(reranker) = reranker.filter(|_| self.recall.rerank) {
+            self.rerank(reranker, query, &mut mid_term, &mut long_term)
+                .await;
+        }
+
+        let mid_term = rank(mid_term, &self.ranking, self.mid_term_top_k);
+        let long_term = rank(long_term, &self.ranking, self.long_term_top_k);
+
+        if !mid_term.is_empty() || !long_term.is_empty() {
+            let (mid_term_memory, long_term_memory) =
+                (self.mid_term.clone(), self.long_term.clone());
+            let (mid_term, long_term) = (mid_term.clone(), long_term.clone());
+            tokio::spawn(async move {
+                if let Err(e) = mid_term_memory.record_access(&mid_term).await {
+                    warn!(error = %e, "failed to record mid-term memory access");
+                }
+                if let Err(e) = long_term_memory.record_access(&long_term).await {
+                    warn!(error = %e, "failed to record long-term memory access");
+                }
+            });
+        }
 
         debug!(
             session = %session_key.channel_id,
@@ -185,8 +295,72 @@ impl MemoryStore {
         }
     }
 
-    pub fn should_summarize(&self, session_key: &SessionKey) -> bool {
-        self.short_term_memory.get_count(session_key) >= self.short_term_memory.max_entry
+    /// Drops results below the minimum scores and fuses the rest. Fused even
+    /// without keyword results, so that the scores are on the same scale for
+    /// every query.
+    fn fuse(
+        &self,
+        tier: &str,
+        vector: Result<Vec<MemoryEntry>>,
+        keyword: Result<Vec<MemoryEntry>>,
+    ) -> Vec<MemoryEntry> {
+        let mut vector = vector.unwrap_or_else(|e| {
+            warn!(tier = tier, error = %e, "failed to search memory by vector");
+            vec![]
+        });
+        let mut keyword = keyword.unwrap_or_else(|e| {
+            warn!(tier = tier, error = %e, "failed to search memory by keywords");
+            vec![]
+        });
+        vector.retain(|entry| entry.score >= self.recall.min_similarity);
+        keyword.retain(|entry| entry.score >= self.recall.min_keyword_score);
+
+        reciprocal_rank_fusion(vec![vector, keyword], self.recall.rrf_k)
+    }
+
+    /// Replaces the scores of both tiers' candidates with the reranker's and
+    /// drops those below `recall.min_rerank_score`. On failure the candidates
+    /// are left as they are.
+    async fn rerank(
+        &self,
+        reranker: &dyn Reranker,
+        query: &str,
+        mid_term: &mut Vec<MemoryEntry>,
+        long_term: &mut Vec<MemoryEntry>,
+    ) {
+        let candidates: Vec<MemoryEntry> =
+            mid_term.iter().chain(long_term.iter()).cloned().collect();
+        if candidates.is_empty() {
+            return;
+        }
+
+        let scores = match reranker.rerank(query, &candidates).await {
+            Ok(scores) if scores.len() == candidates.len() => scores,
+            Ok(scores) => {
+                warn!(
+                    candidates = candidates.len(),
+                    scores = scores.len(),
+                    "reranker scored a different number of memories"
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to rerank memories");
+                return;
+            }
+        };
+
+        let (mid_term_scores, long_term_scores) = scores.split_at(mid_term.len());
+        for (entries, scores) in [(mid_term, mid_term_scores), (long_term, long_term_scores)] {
+            for (entry, score) in entries.iter_mut().zip(scores) {
+                entry.score = score.clamp(0.0, 1.0);
+            }
+            entries.retain(|entry| entry.score >= self.recall.min_rerank_score);
+        }
+    }
+
+    pub async fn should_summarize(&self, session_key: &SessionKey) -> bool {
+        self.short_term_memory.get_count(session_key).await >= self.short_term_memory.max_entry
     }
 
     pub async fn promote_to_mid_term(
@@ -194,49 +368,344 @@ impl MemoryStore {
         session_key: &SessionKey,
         summary: String,
     ) -> Result<()> {
-        let messages = self.short_term_memory.get_messages(session_key);
+        let messages = self.short_term_memory.get_messages(session_key).await;
 
         self.mid_term
             .store_summary(session_key, &messages, summary)
             .await?;
 
-        self.short_term_memory.clear(session_key);
+        self.short_term_memory.clear(session_key).await;
 
         debug!(session = %session_key.channel_id, "promoted short-term to mid-term");
         Ok(())
     }
 
+    /// Stores extracted facts. With a `consolidator` and `memory.consolidation`
+    /// enabled, each fact is first compared with the stored facts close to it,
+    /// including the ones stored earlier in the same batch.
     pub async fn extract_long_term(
         &self,
         session_key: &SessionKey,
         user_id: Option<&str>,
-        facts: Vec<(String, Vec<String>)>,
+        facts: Vec<NewFact>,
+        consolidator: Option<&dyn FactConsolidator>,
     ) -> Result<()> {
+        let consolidator = consolidator.filter(|_| self.consolidation.enabled);
         let fact_count = facts.len();
-        for (fact, tags) in facts {
-            self.long_term
-                .store(session_key, user_id, fact, tags)
-                .await?;
+        let (mut merged, mut superseded) = (0, 0);
+
+        for fact in facts {
+            let embedding = self.embedder.embed(&fact.content).await;
+            let decision = match consolidator {
+                Some(consolidator) => {
+                    self.consolidate(
+                        consolidator,
+                        session_key,
+                        user_id,
+                        &fact.content,
+                        &embedding,
+                    )
+                    .await
+                }
+                None => Consolidation::Keep,
+            };
+
+            match decision {
+                Consolidation::Keep => {
+                    self.long_term
+                        .store_with_embedding(session_key, user_id, fact, embedding)
+                        .await?;
+                }
+                Consolidation::Merge { id, content } => {
+                    let embedding = if content == fact.content {
+                        embedding
+                    } else {
+                        self.embedder.embed(&content).await
+                    };
+                    let fact = NewFact { content, ..fact };
+                    if self
+                        .long_term
+                        .merge(&id, fact.clone(), embedding.clone())
+                        .await?
+                    {
+                        merged += 1;
+                    } else {
+                        // Deleted in the meantime; keep what was learned.
+                        self.long_term
+                            .store_with_embedding(session_key, user_id, fact, embedding)
+                            .await?;
+                    }
+                }
+                Consolidation::Supersede { ids } => {
+                    let id = self
+                        .long_term
+                        .store_with_embedding(session_key, user_id, fact, embedding)
+                        .await?;
+                    self.long_term.mark_superseded(&ids, &id).await?;
+                    superseded += ids.len();
+                }
+            }
         }
 
-        debug!(session = %session_key.channel_id, fact_count = fact_count, "extracted long-term facts");
+        debug!(
+            session = %session_key.channel_id,
+            fact_count = fact_count,
+            merged = merged,
+            superseded = superseded,
+            "extracted long-term facts"
+        );
         Ok(())
     }
 
-    pub fn get_short_term_messages(&self, session_key: &SessionKey) -> Vec<ShortTermEntry> {
-        self.short_term_memory.get_messages(session_key)
+    /// Asks `consolidator` about a fact when close facts exist. Decisions that
+    /// name facts which were not offered, and failures, fall back to keeping it.
+    async fn consolidate(
+        &self,
+        consolidator: &dyn FactConsolidator,
+        session_key: &SessionKey,
+        user_id: Option<&str>,
+        fact: &str,
+        embedding: &[f32],
+    ) -> Consolidation {
+        let nearby = match self
+            .long_term
+            .find_similar(
+                session_key,
+                user_id,
+                embedding,
+                self.consolidation.similarity_threshold,
+                self.consolidation.max_candidates,
+            )
+            .await
+        {
+            Ok(nearby) if nearby.is_empty() => return Consolidation::Keep,
+            Ok(nearby) => nearby,
+            Err(e) => {
+                warn!(error = %e, "failed to look up similar long-term facts");
+                return Consolidation::Keep;
+            }
+        };
+
+        let decision = match consolidator.consolidate(fact, &nearby).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                warn!(error = %e, "failed to consolidate long-term fact");
+                return Consolidation::Keep;
+            }
+        };
+
+        let offered = |id: &String| nearby.iter().any(|entry| &entry.id == id);
+        let decision = match decision {
+            Consolidation::Merge { id, content } if offered(&id) && !content.trim().is_empty() => {
+                Consolidation::Merge {
+                    id,
+                    content: content.trim().to_string(),
+                }
+            }
+            Consolidation::Supersede { ids } if !ids.is_empty() && ids.iter().all(offered) => {
+                Consolidation::Supersede { ids }
+            }
+            Consolidation::Keep => Consolidation::Keep,
+            other => {
+                warn!(decision = ?other, "ignoring consolidation decision about unknown facts");
+                Consolidation::Keep
+            }
+        };
+
+        debug!(nearby = nearby.len(), decision = ?decision, "consolidated long-term fact");
+        decision
+    }
+
+    /// Long-term facts about a user, newest first. `guild_id` narrows them to
+    /// the facts learned in that guild.
+    pub async fn list_user_facts(
+        &self,
+        user_id: &str,
+        guild_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        self.long_term.list_by_user(user_id, guild_id, limit).await
+    }
+
+    pub async fn search_user_facts(
+        &self,
+        user_id: &str,
+        guild_id: Option<&str>,
+        query: &str,
+        top_k: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        match guild_id {
+            Some(guild_id) => {
+                self.long_term
+                    .search_by_user_in_guild(user_id, guild_id, query, top_k)
+                    .await
+            }
+            None => self.long_term.search_by_user(user_id, query, top_k).await,
+        }
     }
 
-    pub fn clear_short_term(&self, session_key: &SessionKey) {
-        self.short_term_memory.clear(session_key);
+    pub async fn search_guild_facts(
+        &self,
+        guild_id: &str,
+        query: &str,
+        top_k: usize,
+    ) -> Result<Vec<MemoryEntry>> {
+        self.long_term.search_by_guild(guild_id, query, top_k).await
+    }
+
+    pub async fn get_fact(&self, id: &str) -> Result<Option<MemoryEntry>> {
+        self.long_term.get(id).await
+    }
+
+    pub async fn forget_fact(&self, id: &str) -> Result<()> {
+        self.long_term.delete(id).await
+    }
+
+    /// Deletes the long-term facts about a user and returns how many there were.
+    pub async fn forget_user_facts(&self, user_id: &str, guild_id: Option<&str>) -> Result<u64> {
+        self.long_term.delete_by_user(user_id, guild_id).await
+    }
+
+    /// Collects what every memory tier holds about a user, for data access requests.
+    pub async fn export_user_data(&self, user_id: &str) -> Result<UserDataExport> {
+        let conversations = &self.stores.conversations;
+        let mut short_term = Vec::new();
+        let mut sessions = Vec::new();
+
+        for session_key in conversations.list_session_keys().await? {
+            let entries = self
+                .short_term_memory
+                .get_user_messages(&session_key, user_id)
+                .await;
+            if !entries.is_empty() {
+                short_term.push(ShortTermExport {
+                    session_key: session_key.clone(),
+                    entries,
+                });
+            }
+
+            if let Some(session) = conversations.load_session(&session_key).await? {
+                let turns: Vec<_> = session
+                    .turns
+                    .into_iter()
+                    .filter(|turn| turn.user_id.as_deref() == Some(user_id))
+                    .collect();
+                if !turns.is_empty() {
+                    sessions.push(SessionExport {
+                        session_key,
+                        turns,
+                        shared_summary: session.summary,
+                    });
+                }
+            }
+        }
+
+        let mid_term = self.mid_term.list_by_user(user_id).await?;
+        let long_term = self
+            .long_term
+            .list_by_user(user_id, None, usize::MAX)
+            .await?;
+        let pending_extractions = self.stores.extraction_queue.list_user_jobs(user_id).await?;
+        let usage = match user_id.parse() {
+            Ok(id) => self.stores.usage.list_user_usage(id).await?,
+            Err(_) => Vec::new(),
+        };
+
+        info!(
+            user_id = user_id,
+            short_term_sessions = short_term.len(),
+            sessions = sessions.len(),
+            mid_term = mid_term.len(),
+            long_term = long_term.len(),
+            pending_extractions = pending_extractions.len(),
+            usage = usage.len(),
+            "exported user data"
+        );
+
+        Ok(UserDataExport {
+            user_id: user_id.to_string(),
+            exported_at: Utc::now().to_rfc3339(),
+            short_term,
+            sessions,
+            mid_term: mid_term.into_iter().map(Into::into).collect(),
+            long_term: long_term.into_iter().map(Into::into).collect(),
+            pending_extractions,
+            usage,
+        })
+    }
+
+    /// Removes what every memory tier holds about a user, for erasure requests.
+    ///
+    /// Mid-term summaries of conversations the user took part in are deleted
+    /// as a whole, and so is the running summary of a session whose turns by
+    /// the user are removed. Sessions cached by a running `SessionManager` are
+    /// not touched, so erase while the bot is stopped.
+    pub async fn erase_user_data(&self, user_id: &str) -> Result<UserDataErasure> {
+        let mut erased = UserDataErasure {
+            // First, so that no new facts are extracted from the user's conversations.
+            pending_extractions: self
+                .stores
+                .extraction_queue
+                .delete_user_jobs(user_id)
+                .await?,
+            ..UserDataErasure::default()
+        };
+
+        let conversations = &self.stores.conversations;
+        for session_key in conversations.list_session_keys().await? {
+            erased.short_term_entries += self
+                .short_term_memory
+                .erase_user(&session_key, user_id)
+                .await?;
+
+            let Some(mut session) = conversations.load_session(&session_key).await? else {
+                continue;
+            };
+            let before = session.turns.len();
+            session
+                .turns
+                .retain(|turn| turn.user_id.as_deref() != Some(user_id));
+            let removed = before - session.turns.len();
+            if removed == 0 {
+                continue;
+            }
+
+            erased.session_turns += removed;
+            if session.turns.is_empty() {
+                conversations.delete_session(&session_key).await?;
+            } else {
+                session.summary = None;
+                conversations.save_session(&session_key, &session).await?;
+            }
+        }
+
+        erased.mid_term_summaries = self.mid_term.delete_by_user(user_id).await?;
+        erased.long_term_facts = self.long_term.delete_by_user(user_id, None).await?;
+        if let Ok(id) = user_id.parse() {
+            erased.usage_records = self.stores.usage.delete_user_usage(id).await?;
+        }
+
+        info!(user_id = user_id, erased = ?erased, "erased user data");
+        Ok(erased)
+    }
+
+    pub async fn get_short_term_messages(&self, session_key: &SessionKey) -> Vec<ShortTermEntry> {
+        self.short_term_memory.get_messages(session_key).await
+    }
+
+    pub async fn clear_short_term(&self, session_key: &SessionKey) {
+        self.short_term_memory.clear(session_key).await;
         debug!(session = %session_key.channel_id, "cleared short-term memory");
     }
 
-    /// Start a background cleanup job for midterm memory retention.
-    /// This runs periodically and deletes entries older than retention_days.
+    /// Starts a background job that, daily, deletes mid-term summaries past their retention and evicts
+    /// long-term facts per `memory.long_term_retention`.
     pub fn start_cleanup_job(&self) {
         let mid_term = self.mid_term.clone();
         let retention_days = self.mid_term.retention_days();
+        let long_term = self.long_term.clone();
+        let long_term_retention = self.long_term_retention.clone();
+        let ranking = self.ranking.clone();
 
         tokio::spawn(async move {
             let mut interval = interval(Duration::from_secs(24 * 60 * 60)); // Run daily
@@ -258,10 +727,14 @@ impl MemoryStore {
                         warn!(error = %e, "failed to run mid-term cleanup job");
                     }
                 }
+
+                if let Err(e) = long_term.evict(&long_term_retention, &ranking).await {
+                    warn!(error = %e, "failed to evict long-term facts");
+                }
             }
         });
 
-        info!("started mid-term cleanup job (runs daily)");
+        info!("started memory cleanup job (runs daily)");
     }
 
     pub async fn promote_to_mid_term_with_messages(
@@ -273,7 +746,7 @@ impl MemoryStore {
         self.mid_term
             .store_summary(session_key, messages, summary)
             .await?;
-        self.short_term_memory.clear(session_key);
+        self.short_term_memory.clear(session_key).await;
         Ok(())
     }
 }
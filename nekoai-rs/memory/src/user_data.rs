@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use nekoai_domain::agent::session::SessionKey;
+use nekoai_infra::usage::UsageRecord;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    persistence::{ExtractionJob, StoredTurn},
+    short_term::ShortTermEntry,
+    store::MemoryEntry,
+};
+
+/// Everything stored about one user, as returned by `MemoryStore::export_user_data`.
+///
+/// Only data that records the user is covered: short-term entries and session
+/// turns stored before user IDs were recorded, and mid-term summaries without
+/// `user_ids`, cannot be attributed to anyone.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserDataExport {
+    pub user_id: String,
+    /// RFC 3339.
+    pub exported_at: String,
+    pub short_term: Vec<ShortTermExport>,
+    pub sessions: Vec<SessionExport>,
+    pub mid_term: Vec<MemoryExport>,
+    pub long_term: Vec<MemoryExport>,
+    /// Conversations still waiting for long-term extraction.
+    pub pending_extractions: Vec<ExtractionJob>,
+    /// Token usage billed to the user, per day, model, purpose and session.
+    pub usage: Vec<UsageRecord>,
+}
+
+/// The short-term entries of the user's turns in one session.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShortTermExport {
+    pub session_key: SessionKey,
+    pub entries: Vec<ShortTermEntry>,
+}
+
+/// The user's turns in one session's history.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionExport {
+    pub session_key: SessionKey,
+    pub turns: Vec<StoredTurn>,
+    /// Running summary of the session's compacted turns. It covers the turns of
+    /// every participant, not only the user's, and is included as context for
+    /// the user's earlier turns.
+    pub shared_summary: Option<String>,
+}
+
+/// A mid-term summary or long-term fact with its payload.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryExport {
+    pub id: String,
+    pub content: String,
+    /// RFC 3339.
+    pub created_at: String,
+    pub metadata: HashMap<String, Value>,
+}
+
+impl From<MemoryEntry> for MemoryExport {
+    fn from(entry: MemoryEntry) -> Self {
+        Self {
+            id: entry.id,
+            content: entry.content,
+            created_at: entry.created_at.to_rfc3339(),
+            metadata: entry.metadata,
+        }
+    }
+}
+
+/// What `MemoryStore::erase_user_data` removed.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct UserDataErasure {
+    pub short_term_entries: usize,
+    pub session_turns: usize,
+    pub mid_term_summaries: u64,
+    pub long_term_facts: u64,
+    pub pending_extractions: u64,
+    pub usage_records: u64,
+}
+
+impl UserDataErasure {
+    pub fn is_empty(&self) -> bool {
+        self.short_term_entries == 0
+            && self.session_turns == 0
+            && self.mid_term_summaries == 0
+            && self.long_term_facts == 0
+            && self.pending_extractions == 0
+            && self.usage_records == 0
+    }
+}
@@ -4,20 +4,64 @@ use async_trait::async_trait;
 use tokio::sync::RwLock;
 
 use super::{
-    FilterCondition, SearchFilter, SearchRequest, SearchResult, UpsertRequest, VectorDbClient,
+    FilterCondition, KeywordSearchRequest, ScrollPage, ScrollRequest, SearchFilter, SearchRequest,
+    SearchResult, SparseVector, UpsertRequest, VectorDbClient,
 };
 
 pub struct InMemoryVectorDb {
-    collections: Arc<RwLock<HashMap<String, Vec<Point>>>>,
+    collections: Arc<RwLock<HashMap<String, Collection>>>,
+}
+
+#[derive(Default)]
+struct Collection {
+    points: Vec<Point>,
+    /// Inverted index of keyword weights: term -> point ID -> weight.
+    keywords: HashMap<u32, HashMap<String, f32>>,
 }
 
 struct Point {
     id: String,
     vector: Vec<f32>,
     norm: f32,
+    /// Terms of the point in `Collection::keywords`.
+    terms: Vec<u32>,
     payload: HashMap<String, serde_json::Value>,
 }
 
+impl Collection {
+    fn index(&mut self, id: &str, keywords: &SparseVector) {
+        for (term, weight) in keywords.iter() {
+            self.keywords
+                .entry(term)
+                .or_default()
+                .insert(id.to_string(), weight);
+        }
+    }
+
+    fn unindex(&mut self, point: &Point) {
+        for term in &point.terms {
+            if let Some(postings) = self.keywords.get_mut(term) {
+                postings.remove(&point.id);
+                if postings.is_empty() {
+                    self.keywords.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Removes the points `remove` accepts and returns how many.
+    fn remove_points(&mut self, mut remove: impl FnMut(&Point) -> bool) -> usize {
+        let (removed, kept) = std::mem::take(&mut self.points)
+            .into_iter()
+            .partition::<Vec<_>, _>(|point| remove(point));
+        self.points = kept;
+        for point in &removed {
+            self.unindex(point);
+        }
+        removed.len()
+    }
+}
+
 // impl Point {
 //     fn new(id: String, vector: Vec<f32>, payload: HashMap<String, serde_json::Value>) -> Self {
 //         let norm = vector_norm(&vector);
@@ -48,35 +92,50 @@ impl Default for InMemoryVectorDb {
 impl VectorDbClient for InMemoryVectorDb {
     async fn upsert(&self, req: UpsertRequest<'_>) -> anyhow::Result<()> {
         let mut collections = self.collections.write().await;
-        let points = collections.entry(req.collection.to_string()).or_default();
+        let collection = collections.entry(req.collection.to_string()).or_default();
 
         let UpsertRequest {
             id,
             vector,
+            keywords,
             payload,
             ..
         } = req;
         let norm = vector_norm(&vector);
+        let keywords = keywords.unwrap_or_default();
 
-        if let Some(existing) = points.iter_mut().find(|p| p.id == id) {
-            existing.vector = vector;
-            existing.norm = norm;
-            existing.payload = payload;
-        } else {
-            points.push(Point {
-                id: id.to_string(),
-                vector,
-                norm,
-                payload,
-            });
-        }
+        collection.remove_points(|p| p.id == id);
+        collection.index(id, &keywords);
+        collection.points.push(Point {
+            id: id.to_string(),
+            vector,
+            norm,
+            terms: keywords.indices,
+            payload,
+        });
+
+        Ok(())
+    }
 
+    async fn set_payload(
+        &self,
+        collection: &str,
+        id: &str,
+        payload: HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        let mut collections = self.collections.write().await;
+        if let Some(point) = collections
+            .get_mut(collection)
+            .and_then(|collection| collection.points.iter_mut().find(|p| p.id == id))
+        {
+            point.payload.extend(payload);
+        }
         Ok(())
     }
 
     async fn search(&self, req: SearchRequest<'_>) -> anyhow::Result<Vec<SearchResult>> {
         let collections = self.collections.read().await;
-        let Some(points) = collections.get(req.collection) else {
+        let Some(collection) = collections.get(req.collection) else {
             return Ok(Vec::new());
         };
 
@@ -86,7 +145,8 @@ impl VectorDbClient for InMemoryVectorDb {
         }
 
         let filter_ref = req.filter.as_ref();
-        let mut results: Vec<SearchResult> = points
+        let mut results: Vec<SearchResult> = collection
+            .points
             .iter()
             .filter(|p| filter_ref.is_none_or(|filter| matches_filter(&p.payload, filter)))
             .map(|p| {
@@ -110,10 +170,111 @@ impl VectorDbClient for InMemoryVectorDb {
         Ok(results)
     }
 
+    async fn keyword_search(
+        &self,
+        req: KeywordSearchRequest<'_>,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        let collections = self.collections.read().await;
+        let Some(collection) = collections.get(req.collection) else {
+            return Ok(Vec::new());
+        };
+
+        // IDF as Qdrant computes it, over the points that have keywords.
+        let indexed = collection
+            .points
+            .iter()
+            .filter(|p| !p.terms.is_empty())
+            .count() as f32;
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for (term, query_weight) in req.query.iter() {
+            let Some(postings) = collection.keywords.get(&term) else {
+                continue;
+            };
+            let matching = postings.len() as f32;
+            let idf = ((indexed - matching + 0.5) / (matching + 0.5) + 1.0).ln();
+            for (id, weight) in postings {
+                *scores.entry(id.as_str()).or_default() += idf * weight * query_weight;
+            }
+        }
+        if scores.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let filter_ref = req.filter.as_ref();
+        let mut results: Vec<SearchResult> = collection
+            .points
+            .iter()
+            .filter_map(|p| {
+                let score = *scores.get(p.id.as_str())?;
+                filter_ref
+                    .is_none_or(|filter| matches_filter(&p.payload, filter))
+                    .then(|| SearchResult {
+                        id: p.id.clone(),
+                        score,
+                        payload: p.payload.clone(),
+                    })
+            })
+            .collect();
+
+        results.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(req.top_k);
+
+        Ok(results)
+    }
+
+    async fn scroll(&self, req: ScrollRequest<'_>) -> anyhow::Result<ScrollPage> {
+        let collections = self.collections.read().await;
+        let Some(collection) = collections.get(req.collection) else {
+            return Ok(ScrollPage::default());
+        };
+
+        // Offsets are positions in the collection.
+        let start = req
+            .offset
+            .as_deref()
+            .and_then(|offset| offset.parse::<usize>().ok())
+            .unwrap_or(0);
+        let filter_ref = req.filter.as_ref();
+        let mut matching = collection
+            .points
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, p)| filter_ref.is_none_or(|filter| matches_filter(&p.payload, filter)));
+
+        let page: Vec<SearchResult> = matching
+            .by_ref()
+            .take(req.limit)
+            .map(|(_, p)| SearchResult {
+                id: p.id.clone(),
+                score: 0.0,
+                payload: p.payload.clone(),
+            })
+            .collect();
+        let next_offset = matching.next().map(|(index, _)| index.to_string());
+
+        Ok(ScrollPage {
+            points: page,
+            next_offset,
+        })
+    }
+
+    async fn get(&self, collection: &str, id: &str) -> anyhow::Result<Option<SearchResult>> {
+        let collections = self.collections.read().await;
+        Ok(collections
+            .get(collection)
+            .and_then(|collection| collection.points.iter().find(|p| p.id == id))
+            .map(|p| SearchResult {
+                id: p.id.clone(),
+                score: 0.0,
+                payload: p.payload.clone(),
+            }))
+    }
+
     async fn delete(&self, collection: &str, id: &str) -> anyhow::Result<()> {
         let mut collections = self.collections.write().await;
-        if let Some(points) = collections.get_mut(collection) {
-            points.retain(|p| p.id != id);
+        if let Some(collection) = collections.get_mut(collection) {
+            collection.remove_points(|p| p.id == id);
         }
         Ok(())
     }
@@ -125,15 +286,13 @@ impl VectorDbClient for InMemoryVectorDb {
     ) -> anyhow::Result<u64> {
         let mut collections = self.collections.write().await;
 
-        let Some(points) = collections.get_mut(collection) else {
+        let Some(collection) = collections.get_mut(collection) else {
             return Ok(0);
         };
 
-        let before = points.len();
-        points.retain(|p| !matches_filter(&p.payload, &filter));
-        let deleted = (before - points.len()) as u64;
+        let deleted = collection.remove_points(|p| matches_filter(&p.payload, &filter));
 
-        Ok(deleted)
+        Ok(deleted as u64)
     }
 
     async fn ensure_collection(&self, name: &str, _dim: usize) -> anyhow::Result<()> {
@@ -149,7 +308,12 @@ fn matches_filter(payload: &HashMap<String, serde_json::Value>, filter: &SearchF
         .iter()
         .all(|condition| matches_condition(payload, condition));
 
-    if !must_ok {
+    if !must_ok
+        || filter
+            .must_not
+            .iter()
+            .any(|condition| matches_condition(payload, condition))
+    {
         return false;
     }
 
@@ -5,10 +5,29 @@ use async_trait::async_trait;
 pub mod inmemory;
 pub mod qdrant;
 
+/// Points read per `scroll` call by [`scroll_all`].
+const SCROLL_PAGE_SIZE: usize = 256;
+
 #[async_trait]
 pub trait VectorDbClient: Send + Sync {
     async fn upsert(&self, req: UpsertRequest<'_>) -> anyhow::Result<()>;
     async fn search(&self, req: SearchRequest<'_>) -> anyhow::Result<Vec<SearchResult>>;
+    /// Scores points by BM25 over the keywords they were upserted with. Points
+    /// without keywords never match.
+    async fn keyword_search(
+        &self,
+        req: KeywordSearchRequest<'_>,
+    ) -> anyhow::Result<Vec<SearchResult>>;
+    /// Pages through the points matching a filter, in no particular order.
+    async fn scroll(&self, req: ScrollRequest<'_>) -> anyhow::Result<ScrollPage>;
+    async fn get(&self, collection: &str, id: &str) -> anyhow::Result<Option<SearchResult>>;
+    /// Adds or overwrites the given payload keys of a point, keeping the others.
+    async fn set_payload(
+        &self,
+        collection: &str,
+        id: &str,
+        payload: HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()>;
     async fn delete(&self, collection: &str, id: &str) -> anyhow::Result<()>;
     async fn delete_by_filter(&self, collection: &str, filter: SearchFilter)
     -> anyhow::Result<u64>;
@@ -20,6 +39,8 @@ pub struct UpsertRequest<'a> {
     pub collection: &'a str,
     pub id: &'a str,
     pub vector: Vec<f32>,
+    /// Term weights for `keyword_search`, from `keyword::document_vector`.
+    pub keywords: Option<SparseVector>,
     pub payload: HashMap<String, serde_json::Value>,
 }
 
@@ -31,10 +52,63 @@ pub struct SearchRequest<'a> {
     pub top_k: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct KeywordSearchRequest<'a> {
+    pub collection: &'a str,
+    /// From `keyword::query_vector`.
+    pub query: SparseVector,
+    pub filter: Option<SearchFilter>,
+    pub top_k: usize,
+}
+
+/// Weights of hashed terms; `indices` are distinct.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseVector {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.indices
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+}
+
+impl FromIterator<(u32, f32)> for SparseVector {
+    fn from_iter<I: IntoIterator<Item = (u32, f32)>>(iter: I) -> Self {
+        let (indices, values) = iter.into_iter().unzip();
+        Self { indices, values }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScrollRequest<'a> {
+    pub collection: &'a str,
+    pub filter: Option<SearchFilter>,
+    pub limit: usize,
+    /// `next_offset` of the previous page.
+    pub offset: Option<String>,
+}
+
+/// Points of a scroll have a score of `0.0`.
+#[derive(Debug, Clone, Default)]
+pub struct ScrollPage {
+    pub points: Vec<SearchResult>,
+    pub next_offset: Option<String>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SearchFilter {
     pub must: Vec<FilterCondition>,
     pub should: Vec<FilterCondition>,
+    pub must_not: Vec<FilterCondition>,
 }
 
 #[derive(Debug, Clone)]
@@ -56,3 +130,33 @@ pub struct SearchResult {
     pub score: f32,
     pub payload: HashMap<String, serde_json::Value>,
 }
+
+/// Scrolls until `limit` points matching `filter` were read or none are left.
+pub(crate) async fn scroll_all(
+    db: &dyn VectorDbClient,
+    collection: &str,
+    filter: SearchFilter,
+    limit: usize,
+) -> anyhow::Result<Vec<SearchResult>> {
+    let mut points = Vec::new();
+    let mut offset = None;
+
+    loop {
+        let page = db
+            .scroll(ScrollRequest {
+                collection,
+                filter: Some(filter.clone()),
+                limit: SCROLL_PAGE_SIZE,
+                offset,
+            })
+            .await?;
+        points.extend(page.points);
+
+        match page.next_offset {
+            Some(next) if points.len() < limit => offset = Some(next),
+            _ => break,
+        }
+    }
+
+    Ok(points)
+}
@@ -1,24 +1,32 @@
 use std::{collections::HashMap, time::Duration};
 
 use async_trait::async_trait;
+use dashmap::DashSet;
 use nekoai_domain::agent::session::{SessionKey, SessionKind};
 use serde_json::json;
 use tokio_retry::{
     Retry,
     strategy::{ExponentialBackoff, jitter},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use super::{
-    FilterCondition, SearchFilter, SearchRequest, SearchResult, UpsertRequest, VectorDbClient,
+    FilterCondition, KeywordSearchRequest, ScrollPage, ScrollRequest, SearchFilter, SearchRequest,
+    SearchResult, UpsertRequest, VectorDbClient,
 };
 
+/// Sparse vector holding the keywords of a point, next to its unnamed dense vector.
+const KEYWORDS_VECTOR: &str = "keywords";
+
 pub struct QdrantClient {
     #[allow(dead_code)]
     url: String,
     #[allow(dead_code)]
     api_key: Option<String>,
     client: qdrant_client::Qdrant,
+    /// Collections the keywords vector could not be added to. Keywords are
+    /// neither stored in nor searched there.
+    without_keywords: DashSet<String>,
 }
 
 impl QdrantClient {
@@ -38,8 +46,46 @@ impl QdrantClient {
             url,
             api_key,
             client,
+            without_keywords: DashSet::new(),
         })
     }
+
+    /// Adds the keywords vector to a collection created before keyword search.
+    /// Points stored earlier have no keywords until they are upserted again.
+    async fn ensure_keywords_vector(&self, name: &str) {
+        let has_keywords = match self.client.collection_info(name).await {
+            Ok(response) => response
+                .result
+                .and_then(|info| info.config)
+                .and_then(|config| config.params)
+                .and_then(|params| params.sparse_vectors_config)
+                .is_some_and(|sparse| sparse.map.contains_key(KEYWORDS_VECTOR)),
+            Err(e) => {
+                warn!(collection = name, error = %e, "failed to read collection info");
+                false
+            }
+        };
+        if has_keywords {
+            return;
+        }
+
+        info!(collection = name, "adding keywords vector to collection");
+        let request = qdrant_client::qdrant::CreateVectorNameRequestBuilder::new(
+            name,
+            KEYWORDS_VECTOR,
+            qdrant_client::qdrant::SparseVectorCreationConfigBuilder::default()
+                .modifier(qdrant_client::qdrant::Modifier::Idf),
+        )
+        .wait(true);
+        if let Err(e) = self.client.create_vector_name(request).await {
+            warn!(
+                collection = name,
+                error = %e,
+                "failed to add keywords vector, keyword search is disabled for this collection"
+            );
+            self.without_keywords.insert(name.to_string());
+        }
+    }
 }
 
 fn qdrant_retry_strategy() -> impl Iterator<Item = Duration> {
@@ -54,14 +100,32 @@ impl VectorDbClient for QdrantClient {
     async fn upsert(&self, req: UpsertRequest<'_>) -> anyhow::Result<()> {
         let collection = req.collection.to_string();
         let point_id = req.id.to_string();
-        let vector = qdrant_client::qdrant::Vector::from(req.vector);
+        let dense = qdrant_client::qdrant::Vector::from(req.vector);
+        let vectors: qdrant_client::qdrant::Vectors = match req.keywords {
+            Some(keywords)
+                if !keywords.is_empty() && !self.without_keywords.contains(req.collection) =>
+            {
+                // The unnamed dense vector is named "".
+                qdrant_client::qdrant::NamedVectors::default()
+                    .add_vector("", dense)
+                    .add_vector(
+                        KEYWORDS_VECTOR,
+                        qdrant_client::qdrant::Vector::new_sparse(
+                            keywords.indices,
+                            keywords.values,
+                        ),
+                    )
+                    .into()
+            }
+            _ => dense.into(),
+        };
 
         let payload: qdrant_client::Payload = req.payload.into();
         let payload_map: HashMap<String, qdrant_client::qdrant::Value> = payload.into();
 
         let points = vec![qdrant_client::qdrant::PointStruct {
             id: Some(qdrant_client::qdrant::PointId::from(point_id.clone())),
-            vectors: Some(vector.into()),
+            vectors: Some(vectors),
             payload: payload_map,
         }];
 
@@ -111,23 +175,156 @@ impl VectorDbClient for QdrantClient {
         })
         .await?;
 
-        let search_results = results
+        Ok(results
             .result
             .into_iter()
-            .map(|point| {
-                let score = point.score;
-                let id = point.id.and_then(point_id_to_string).unwrap_or_default();
-                let payload: HashMap<String, serde_json::Value> = point
-                    .payload
-                    .into_iter()
-                    .map(|(k, v)| (k, serde_json::Value::from(v)))
-                    .collect();
+            .map(scored_point_to_result)
+            .collect())
+    }
 
-                SearchResult { id, score, payload }
-            })
-            .collect();
+    async fn keyword_search(
+        &self,
+        req: KeywordSearchRequest<'_>,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        if req.query.is_empty() || self.without_keywords.contains(req.collection) {
+            return Ok(Vec::new());
+        }
+
+        let filter = req.filter.as_ref().map(build_filter);
+        let collection_name = req.collection.to_string();
+        let query = req.query;
+        let top_k = req.top_k as u64;
+
+        let client = self.client.clone();
+
+        let results = Retry::spawn(qdrant_retry_strategy(), || {
+            let client = client.clone();
+            let col = collection_name.clone();
+            let query = query.clone();
+            let filter = filter.clone();
+            async move {
+                let mut builder = qdrant_client::qdrant::QueryPointsBuilder::new(col)
+                    .query(qdrant_client::qdrant::VectorInput::new_sparse(
+                        query.indices,
+                        query.values,
+                    ))
+                    .using(KEYWORDS_VECTOR)
+                    .limit(top_k)
+                    .with_payload(true);
+                if let Some(f) = filter {
+                    builder = builder.filter(f);
+                }
+                client.query(builder).await.map_err(|e| anyhow::anyhow!(e))
+            }
+        })
+        .await?;
+
+        Ok(results
+            .result
+            .into_iter()
+            .map(scored_point_to_result)
+            .collect())
+    }
+
+    async fn scroll(&self, req: ScrollRequest<'_>) -> anyhow::Result<ScrollPage> {
+        let filter = req.filter.as_ref().map(build_filter);
+        let collection_name = req.collection.to_string();
+        let offset = req.offset.as_deref().map(point_id_from_str);
+        let limit = req.limit as u32;
+
+        let client = self.client.clone();
+
+        let response = Retry::spawn(qdrant_retry_strategy(), || {
+            let client = client.clone();
+            let col = collection_name.clone();
+            let filter = filter.clone();
+            let offset = offset.clone();
+            async move {
+                let mut builder = qdrant_client::qdrant::ScrollPointsBuilder::new(col)
+                    .limit(limit)
+                    .with_payload(true);
+                if let Some(f) = filter {
+                    builder = builder.filter(f);
+                }
+                if let Some(offset) = offset {
+                    builder = builder.offset(offset);
+                }
+                client.scroll(builder).await.map_err(|e| anyhow::anyhow!(e))
+            }
+        })
+        .await?;
+
+        Ok(ScrollPage {
+            points: response
+                .result
+                .into_iter()
+                .map(|point| retrieved_point_to_result(point.id, point.payload))
+                .collect(),
+            next_offset: response.next_page_offset.and_then(point_id_to_string),
+        })
+    }
+
+    async fn get(&self, collection: &str, id: &str) -> anyhow::Result<Option<SearchResult>> {
+        let client = self.client.clone();
+        let col = collection.to_string();
+        let point_id = point_id_from_str(id);
+
+        let response = Retry::spawn(qdrant_retry_strategy(), || {
+            let client = client.clone();
+            let col = col.clone();
+            let pid = point_id.clone();
+            async move {
+                client
+                    .get_points(
+                        qdrant_client::qdrant::GetPointsBuilder::new(col, vec![pid])
+                            .with_payload(true),
+                    )
+                    .await
+                    .map_err(|e| anyhow::anyhow!(e))
+            }
+        })
+        .await?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .next()
+            .map(|point| retrieved_point_to_result(point.id, point.payload)))
+    }
+
+    async fn set_payload(
+        &self,
+        collection: &str,
+        id: &str,
+        payload: HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        let client = self.client.clone();
+        let col = collection.to_string();
+        let point_id = point_id_from_str(id);
+        let payload: qdrant_client::Payload = payload.into();
 
-        Ok(search_results)
+        Retry::spawn(qdrant_retry_strategy(), || {
+            let client = client.clone();
+            let col = col.clone();
+            let pid = point_id.clone();
+            let payload = payload.clone();
+            async move {
+                client
+                    .set_payload(
+                        qdrant_client::qdrant::SetPayloadPointsBuilder::new(col, payload)
+                            .points_selector(qdrant_client::qdrant::PointsIdsList {
+                                ids: vec![pid],
+                            })
+                            .wait(true),
+                    )
+                    .await?;
+                Ok::<_, anyhow::Error>(())
+            }
+        })
+        .await?;
+
+        debug!(collection = collection, id = %id, "updated point payload");
+        Ok(())
     }
 
     async fn delete(&self, collection: &str, id: &str) -> anyhow::Result<()> {
@@ -230,6 +427,13 @@ impl VectorDbClient for QdrantClient {
                 let client = client.clone();
                 let name = collection_name.clone();
                 async move {
+                    let mut sparse_vectors =
+                        qdrant_client::qdrant::SparseVectorsConfigBuilder::default();
+                    sparse_vectors.add_named_vector_params(
+                        KEYWORDS_VECTOR,
+                        qdrant_client::qdrant::SparseVectorParamsBuilder::default()
+                            .modifier(qdrant_client::qdrant::Modifier::Idf),
+                    );
                     client
                         .create_collection(
                             qdrant_client::qdrant::CreateCollectionBuilder::new(name)
@@ -237,13 +441,16 @@ impl VectorDbClient for QdrantClient {
                                     size: dim as u64,
                                     distance: qdrant_client::qdrant::Distance::Cosine.into(),
                                     ..Default::default()
-                                }),
+                                })
+                                .sparse_vectors_config(sparse_vectors),
                         )
                         .await?;
                     Ok::<_, anyhow::Error>(())
                 }
             })
             .await?;
+        } else {
+            self.ensure_keywords_vector(name).await;
         }
 
         Ok(())
@@ -254,6 +461,7 @@ fn build_filter(filter: &SearchFilter) -> qdrant_client::qdrant::Filter {
     qdrant_client::qdrant::Filter {
         must: filter.must.iter().map(build_condition).collect(),
         should: filter.should.iter().map(build_condition).collect(),
+        must_not: filter.must_not.iter().map(build_condition).collect(),
         ..Default::default()
     }
 }
@@ -314,6 +522,32 @@ fn build_condition(condition: &FilterCondition) -> qdrant_client::qdrant::Condit
     }
 }
 
+fn scored_point_to_result(point: qdrant_client::qdrant::ScoredPoint) -> SearchResult {
+    SearchResult {
+        id: point.id.and_then(point_id_to_string).unwrap_or_default(),
+        score: point.score,
+        payload: point
+            .payload
+            .into_iter()
+            .map(|(k, v)| (k, serde_json::Value::from(v)))
+            .collect(),
+    }
+}
+
+fn retrieved_point_to_result(
+    id: Option<qdrant_client::qdrant::PointId>,
+    payload: HashMap<String, qdrant_client::qdrant::Value>,
+) -> SearchResult {
+    SearchResult {
+        id: id.and_then(point_id_to_string).unwrap_or_default(),
+        score: 0.0,
+        payload: payload
+            .into_iter()
+            .map(|(k, v)| (k, serde_json::Value::from(v)))
+            .collect(),
+    }
+}
+
 fn point_id_to_string(point_id: qdrant_client::qdrant::PointId) -> Option<String> {
     match point_id.point_id_options {
         Some(qdrant_client::qdrant::point_id::PointIdOptions::Num(value)) => {
@@ -348,6 +582,7 @@ pub(crate) fn session_scope_filter(session_key: &SessionKey) -> SearchFilter {
             },
         ],
         should: vec![],
+        must_not: vec![],
     }
 }
 
@@ -0,0 +1,74 @@
+//! Checks that fused recall scores stay on one scale whether or not the
+//! keyword search found anything.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use nekoai_memory::{ranking::reciprocal_rank_fusion, store::MemoryEntry};
+
+const RRF_K: f32 = 60.0;
+
+fn entry(id: &str, score: f32) -> MemoryEntry {
+    MemoryEntry {
+        id: id.to_string(),
+        content: format!("memory {id}"),
+        score,
+        created_at: Utc::now(),
+        importance: 0.5,
+        last_accessed_at: None,
+        access_count: 0,
+        metadata: HashMap::new(),
+    }
+}
+
+fn scores(entries: &[MemoryEntry]) -> Vec<(String, f32)> {
+    entries
+        .iter()
+        .map(|entry| (entry.id.clone(), entry.score))
+        .collect()
+}
+
+#[test]
+fn vector_results_alone_are_fused_like_any_other() {
+    let vector = vec![entry("a", 0.9), entry("b", 0.4)];
+
+    let fused = reciprocal_rank_fusion(vec![vector.clone(), Vec::new()], RRF_K);
+
+    assert_eq!(
+        fused.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+        ["a", "b"]
+    );
+    // The cosine similarity is replaced, not kept.
+    assert!((fused[0].score - 0.5).abs() < 1e-6, "{:?}", scores(&fused));
+    assert!(fused[1].score < fused[0].score);
+}
+
+#[test]
+fn unrelated_keyword_hits_leave_vector_scores_unchanged() {
+    let vector = vec![entry("a", 0.9), entry("b", 0.4)];
+
+    let alone = reciprocal_rank_fusion(vec![vector.clone(), Vec::new()], RRF_K);
+    let with_keywords = reciprocal_rank_fusion(vec![vector, vec![entry("c", 7.0)]], RRF_K);
+
+    for (id, score) in scores(&alone) {
+        let other = with_keywords
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.score);
+        assert_eq!(other, Some(score), "{id}");
+    }
+}
+
+#[test]
+fn top_of_every_list_scores_one() {
+    let fused = reciprocal_rank_fusion(
+        vec![
+            vec![entry("a", 0.9)],
+            vec![entry("a", 3.0), entry("b", 2.0)],
+        ],
+        RRF_K,
+    );
+
+    assert_eq!(fused[0].id, "a");
+    assert!((fused[0].score - 1.0).abs() < 1e-6);
+}
@@ -1,6 +1,9 @@
 use nekoai_config::loader::{
-    ChatPlatform, Config, ConversationModel, Discord, EmbeddingModel, Memory, Parameters, Provider,
-    SecretKey, SummarizerModel, ToolPermissions, VectorDb, WebUiConfig,
+    AgentLoopConfig, AttachmentConfig, ChatPlatform, CircuitBreakerConfig, Config,
+    ConsolidationConfig, ContextConfig, ConversationModel, Discord, EmbeddingModel,
+    LongTermRetentionConfig, Memory, Parameters, PersistenceConfig, Provider, ProviderKind,
+    RankingConfig, RateLimitConfig, RecallConfig, SecretKey, SummarizerModel, ToolPermissions,
+    VectorDb, WebUiConfig,
 };
 use tracing::warn;
 
@@ -18,6 +21,7 @@ pub fn make_config(
 ) -> Config {
     let (provider_base_url, model_name) = resolve_provider(provider, model, base_url);
     let summarizer_model_name = resolve_summarizer(provider, &model_name);
+    let kind = resolve_provider_kind(provider, base_url);
 
     Config {
         chat_platform: ChatPlatform::Discord,
@@ -27,23 +31,28 @@ pub fn make_config(
         },
         provider: Provider {
             conversation_model: ConversationModel {
+                kind,
                 provider_base_url: provider_base_url.clone(),
                 api_key: SecretKey::new(api_key.to_owned()),
                 model_name,
                 parameters: Parameters {
                     max_token: 262144,
                     temperature: 1.0,
-                    top_p: 0.95,
+                    top_p: None,
+                    structured_output: true,
                 },
             },
+            fallback_conversation_models: Vec::new(),
             summarizer_model: SummarizerModel {
+                kind,
                 provider_base_url: provider_base_url.clone(),
                 api_key: SecretKey::new(api_key.to_owned()),
                 model_name: summarizer_model_name,
                 parameters: Parameters {
                     max_token: 262144,
                     temperature: 1.0,
-                    top_p: 0.95,
+                    top_p: None,
+                    structured_output: true,
                 },
             },
             embedding_model: EmbeddingModel {
@@ -52,6 +61,9 @@ pub fn make_config(
                 model_name: "text-embedding-3-small".to_owned(),
                 dimension: 1536,
             },
+            circuit_breaker: CircuitBreakerConfig::default(),
+            pricing: Vec::new(),
+            usage_retention_days: 400,
         },
         memory: Memory {
             vector_db: VectorDb::default(),
@@ -60,6 +72,12 @@ pub fn make_config(
             long_term_top_k: 5,
             mid_term_retention_days: 30,
             long_term_extraction_interval: 10,
+            session_idle_timeout_minutes: 60,
+            persistence: PersistenceConfig::default(),
+            consolidation: ConsolidationConfig::default(),
+            ranking: RankingConfig::default(),
+            long_term_retention: LongTermRetentionConfig::default(),
+            recall: RecallConfig::default(),
         },
         tools: ToolPermissions {
             web_search,
@@ -70,6 +88,11 @@ pub fn make_config(
             read_file_dirs: Default::default(),
         },
         web_ui: WebUiConfig::default(),
+        context: ContextConfig::default(),
+        agent_loop: AgentLoopConfig::default(),
+        rate_limit: RateLimitConfig::default(),
+        attachments: AttachmentConfig::default(),
+        personas: Vec::new(),
     }
 }
 
@@ -101,6 +124,19 @@ fn resolve_provider(provider: &str, model: &str, base_url: &str) -> (String, Str
     (url.to_owned(), model.to_owned())
 }
 
+/// Resolve the API protocol for the provider name.
+/// A custom base URL is assumed to speak the OpenAI-compatible protocol.
+fn resolve_provider_kind(provider: &str, base_url: &str) -> ProviderKind {
+    if !base_url.is_empty() {
+        return ProviderKind::OpenAICompatible;
+    }
+
+    match provider.to_ascii_lowercase().as_str() {
+        "anthropic" => ProviderKind::Anthropic,
+        _ => ProviderKind::OpenAICompatible,
+    }
+}
+
 /// Resolve the default summarizer model name based on the provider name.
 fn resolve_summarizer(provider: &str, conv_model: &str) -> String {
     match provider.to_ascii_lowercase().as_str() {
@@ -54,6 +54,11 @@ fn merge_with_existing(new_config: &Config) -> Result<Config> {
     merge_provider(&mut merged, &existing);
     merge_memory(&mut merged, &existing);
     merge_tools(&mut merged, &existing);
+    merge_context(&mut merged, &existing);
+    merge_agent_loop(&mut merged, &existing);
+    merge_rate_limit(&mut merged, &existing);
+    merge_attachments(&mut merged, &existing);
+    merge_personas(&mut merged, &existing);
 
     warn!("existing config values were preserved where present");
     Ok(merged)
@@ -91,12 +96,21 @@ fn merge_provider(merged: &mut Config, existing: &Config) {
             .conversation_model
             .provider_base_url
             .clone();
+        merged.provider.conversation_model.kind = existing.provider.conversation_model.kind;
     }
     merge_parameters(
         &mut merged.provider.conversation_model.parameters,
         &existing.provider.conversation_model.parameters,
     );
 
+    if merged.provider.fallback_conversation_models.is_empty() {
+        merged.provider.fallback_conversation_models =
+            existing.provider.fallback_conversation_models.clone();
+    }
+    merged.provider.circuit_breaker = existing.provider.circuit_breaker.clone();
+    merged.provider.pricing = existing.provider.pricing.clone();
+    merged.provider.usage_retention_days = existing.provider.usage_retention_days;
+
     // ── Summarizer model ────────────────────────────────────
     if !is_placeholder(existing.provider.summarizer_model.api_key.expose()) {
         merged.provider.summarizer_model.api_key =
@@ -114,6 +128,7 @@ fn merge_provider(merged: &mut Config, existing: &Config) {
     {
         merged.provider.summarizer_model.provider_base_url =
             existing.provider.summarizer_model.provider_base_url.clone();
+        merged.provider.summarizer_model.kind = existing.provider.summarizer_model.kind;
     }
     merge_parameters(
         &mut merged.provider.summarizer_model.parameters,
@@ -153,9 +168,12 @@ fn merge_parameters(
     if (existing.temperature - 1.0).abs() > 0.01 {
         merged_params.temperature = existing.temperature;
     }
-    if (existing.top_p - 0.95).abs() > 0.01 {
+    if existing.top_p.is_some() {
         merged_params.top_p = existing.top_p;
     }
+    if !existing.structured_output {
+        merged_params.structured_output = false;
+    }
 }
 
 /// Keep the existing memory settings if they differ from defaults.
@@ -175,6 +193,14 @@ fn merge_memory(merged: &mut Config, existing: &Config) {
     if existing.memory.long_term_extraction_interval != 10 {
         merged.memory.long_term_extraction_interval = existing.memory.long_term_extraction_interval;
     }
+    if existing.memory.session_idle_timeout_minutes != 60 {
+        merged.memory.session_idle_timeout_minutes = existing.memory.session_idle_timeout_minutes;
+    }
+    merged.memory.persistence = existing.memory.persistence.clone();
+    merged.memory.consolidation = existing.memory.consolidation.clone();
+    merged.memory.ranking = existing.memory.ranking.clone();
+    merged.memory.long_term_retention = existing.memory.long_term_retention.clone();
+    merged.memory.recall = existing.memory.recall.clone();
     // Vector DB
     if !existing.memory.vector_db.url.is_empty()
         && existing.memory.vector_db.url != DEFAULT_QDRANT_URL
@@ -191,6 +217,28 @@ fn merge_tools(merged: &mut Config, existing: &Config) {
     merged.tools = existing.tools.clone();
 }
 
+/// Keep the existing context budget settings (the wizard does not ask for them).
+fn merge_context(merged: &mut Config, existing: &Config) {
+    merged.context = existing.context.clone();
+}
+
+/// Keep the existing agent loop limits (the wizard does not ask for them).
+fn merge_agent_loop(merged: &mut Config, existing: &Config) {
+    merged.agent_loop = existing.agent_loop.clone();
+}
+
+fn merge_rate_limit(merged: &mut Config, existing: &Config) {
+    merged.rate_limit = existing.rate_limit.clone();
+}
+
+fn merge_attachments(merged: &mut Config, existing: &Config) {
+    merged.attachments = existing.attachments.clone();
+}
+
+fn merge_personas(merged: &mut Config, existing: &Config) {
+    merged.personas = existing.personas.clone();
+}
+
 /// Check if a string looks like a placeholder (e.g. "YOUR_..." or empty).
 fn is_placeholder(s: &str) -> bool {
     s.is_empty() || s.starts_with("YOUR_") || s.starts_with("sk-...") || s == "sk-ant-..."
@@ -2,15 +2,18 @@ use anyhow::{Context, Result, bail};
 use colored::Colorize;
 use dialoguer::{Confirm, Input, Password, Select, theme::SimpleTheme};
 use nekoai_config::loader::{
-    ChatPlatform, Config, ConversationModel, DEFAULT_QDRANT_URL, Discord, EmbeddingModel, Memory,
-    Parameters, Provider, SearxngConfig, SecretKey, SummarizerModel, ToolPermissions, VectorDb,
-    WebUiConfig,
+    AgentLoopConfig, AttachmentConfig, ChatPlatform, CircuitBreakerConfig, Config,
+    ConsolidationConfig, ContextConfig, ConversationModel, DEFAULT_QDRANT_URL, Discord,
+    EmbeddingModel, LongTermRetentionConfig, Memory, Parameters, PersistenceConfig, Provider,
+    ProviderKind, RankingConfig, RateLimitConfig, RecallConfig, SearxngConfig, SecretKey,
+    SummarizerModel, ToolPermissions, VectorDb, WebUiConfig,
 };
 
 // ── Provider Presets ──────────────────────────────────────────────────────────
 
 struct ProviderPreset {
     label: &'static str,
+    kind: ProviderKind,
     base_url: &'static str,
     default_model: &'static str,
     default_summarizer: &'static str,
@@ -19,24 +22,28 @@ struct ProviderPreset {
 const PROVIDER_PRESETS: &[ProviderPreset] = &[
     ProviderPreset {
         label: "OpenAI",
+        kind: ProviderKind::OpenAICompatible,
         base_url: "https://api.openai.com/v1",
         default_model: "gpt-4o",
         default_summarizer: "gpt-4o-mini",
     },
     ProviderPreset {
         label: "Anthropic",
+        kind: ProviderKind::Anthropic,
         base_url: "https://api.anthropic.com/v1",
         default_model: "claude-sonnet-4-20250514",
         default_summarizer: "claude-haiku-3-5-20241022",
     },
     ProviderPreset {
         label: "Ollama (Local)",
+        kind: ProviderKind::OpenAICompatible,
         base_url: "http://localhost:11434/v1",
         default_model: "llama3.1",
         default_summarizer: "llama3.1",
     },
     ProviderPreset {
         label: "Custom",
+        kind: ProviderKind::OpenAICompatible,
         base_url: "",
         default_model: "",
         default_summarizer: "",
@@ -217,12 +224,21 @@ fn input_model_params(label: &str, defaults: &Parameters) -> Result<Parameters>
 
     println!();
 
-    let top_p = validated_input(
-        &format!("  Top P (0.0 ~ 1.0; nucleus sampling) ({label})"),
-        Some(defaults.top_p.to_string()),
-        validate_top_p,
-    )?;
-    let top_p: f64 = top_p.parse().unwrap_or(defaults.top_p);
+    let top_p: String = Input::with_theme(&SimpleTheme)
+        .with_prompt(format!(
+            "  Top P (0.0 ~ 1.0; nucleus sampling, leave empty to not send it) ({label})"
+        ))
+        .with_initial_text(defaults.top_p.map(|p| p.to_string()).unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|s: &String| {
+            if s.is_empty() {
+                Ok(())
+            } else {
+                validate_top_p(s)
+            }
+        })
+        .interact_text()?;
+    let top_p: Option<f64> = top_p.parse().ok();
 
     println!();
 
@@ -230,6 +246,7 @@ fn input_model_params(label: &str, defaults: &Parameters) -> Result<Parameters>
         max_token,
         temperature,
         top_p,
+        structured_output: defaults.structured_output,
     })
 }
 
@@ -279,7 +296,7 @@ fn step_discord() -> Result<(String, u64)> {
 
 // ── Step 2: AI Provider ──────────────────────────────────────────────────────
 
-fn step_provider() -> Result<(String, String, String, String)> {
+fn step_provider() -> Result<(String, String, ProviderKind, String, String)> {
     print_header(2, 5, "AI Provider");
     println!("  Select your AI provider and enter the API credentials.");
     print_subheader(
@@ -310,6 +327,7 @@ fn step_provider() -> Result<(String, String, String, String)> {
     Ok((
         preset.default_model.to_string(),
         preset.default_summarizer.to_string(),
+        preset.kind,
         base_url,
         api_key,
     ))
@@ -587,6 +605,12 @@ fn step_advanced() -> Result<AdvancedConfig> {
         long_term_top_k,
         mid_term_retention_days,
         long_term_extraction_interval,
+        session_idle_timeout_minutes: 60,
+        persistence: PersistenceConfig::default(),
+        consolidation: ConsolidationConfig::default(),
+        ranking: RankingConfig::default(),
+        long_term_retention: LongTermRetentionConfig::default(),
+        recall: RecallConfig::default(),
     };
 
     print_footer();
@@ -660,16 +684,23 @@ fn print_summary(
     println!("  {}", "─── Conversation Parameters ───".dimmed());
     println!("  Max tokens      : {}", p.max_token);
     println!("  Temperature     : {:.2}", p.temperature);
-    println!("  Top P           : {:.2}", p.top_p);
+    println!("  Top P           : {}", format_top_p(p.top_p));
 
     let sp = &advanced.summarizer_params;
     println!("  {}", "─── Summarizer Parameters ───".dimmed());
     println!("  Max tokens      : {}", sp.max_token);
     println!("  Temperature     : {:.2}", sp.temperature);
-    println!("  Top P           : {:.2}", sp.top_p);
+    println!("  Top P           : {}", format_top_p(sp.top_p));
     println!();
 }
 
+fn format_top_p(top_p: Option<f64>) -> String {
+    match top_p {
+        Some(top_p) => format!("{top_p:.2}"),
+        None => "(API default)".to_string(),
+    }
+}
+
 // ── Main Wizard Orchestrator ─────────────────────────────────────────────────
 
 /// Run all 5 steps of the setup wizard and return a complete Config.
@@ -678,7 +709,7 @@ pub fn run_wizard() -> Result<Config> {
     let (token, guild_id) = step_discord()?;
 
     // ── Step 2: AI Provider ──────────────────────────────────
-    let (default_model, default_summarizer, base_url, api_key) = step_provider()?;
+    let (default_model, default_summarizer, provider_kind, base_url, api_key) = step_provider()?;
 
     // ── Step 3: Model Selection ──────────────────────────────
     let (model_name, summarizer_model_name, embed_model_name, embed_dimension) =
@@ -722,12 +753,15 @@ pub fn run_wizard() -> Result<Config> {
         },
         provider: Provider {
             conversation_model: ConversationModel {
+                kind: provider_kind,
                 provider_base_url: base_url.clone(),
                 api_key: SecretKey::new(api_key.clone()),
                 model_name,
                 parameters: advanced.params,
             },
+            fallback_conversation_models: Vec::new(),
             summarizer_model: SummarizerModel {
+                kind: provider_kind,
                 provider_base_url: base_url.clone(),
                 api_key: SecretKey::new(api_key.clone()),
                 model_name: summarizer_model_name,
@@ -739,6 +773,9 @@ pub fn run_wizard() -> Result<Config> {
                 model_name: embed_model_name,
                 dimension: embed_dimension,
             },
+            circuit_breaker: CircuitBreakerConfig::default(),
+            pricing: Vec::new(),
+            usage_retention_days: 400,
         },
         memory: advanced.memory,
         tools: ToolPermissions {
@@ -750,6 +787,11 @@ pub fn run_wizard() -> Result<Config> {
             read_file_dirs: Default::default(),
         },
         web_ui: WebUiConfig::default(),
+        context: ContextConfig::default(),
+        agent_loop: AgentLoopConfig::default(),
+        rate_limit: RateLimitConfig::default(),
+        attachments: AttachmentConfig::default(),
+        personas: Vec::new(),
     };
 
     Ok(config)